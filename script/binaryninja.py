@@ -1,8 +1,18 @@
+import ssl
+import hmac
 import json
+import time
+import uuid
+import zlib
 import socket
 import select
+import hashlib
+import getpass
+import secrets
+import threading
 
 from binaryninja import (
+    core_version,
     TypeClass,
     Type,
     BinaryView,
@@ -11,15 +21,219 @@ from binaryninja import (
     BackgroundTaskThread,
     BinaryDataNotification,
     FunctionBuilder,
-    StructureBuilder
+    StructureBuilder,
+    execute_on_main_thread
 )
 
+try:
+    from binaryninjaui import UIContext, UIContextNotification
+except ImportError:
+    # running headless, so there is no cursor to report
+    UIContext = None
+
 #       CONFIG
 # --------------------
 PORT_NUMBER = 12007
+# set to "0.0.0.0" to let binal connect from other machines, ideally along with the settings below
+LISTEN_ADDRESS = "127.0.0.1"
 INIT_SYNC_BATCH = 500
+# set to ("host", port), or the path of a unix socket, to connect to a binal hub instead of
+# waiting for binal to connect
+BINAL_HUB = None
+# SHA-256 fingerprint of the hub's certificate, to connect to it over TLS
+BINAL_HUB_FINGERPRINT = None
+# PEM file with a certificate and its private key, to only accept connections over TLS
+TLS_CERTIFICATE = None
+# shared secret that binal has to prove it knows, and is given proof of, before anything is synced
+BINAL_TOKEN = None
+# who the changes made here are shown as being from, the login name if not set
+BINAL_USER = None
+# only receive the objects matching one of these from binal, for example
+# [{"objects": ["function"], "name": "net_*"}, {"start": 0x401000, "end": 0x402000}]
+BINAL_FILTERS = None
 # --------------------
 
+PROTOCOL_VERSION = 2
+HANDSHAKE_TIMEOUT = 10
+# seconds updates are held back for, so an object analysis keeps touching is only sent once
+COALESCE_WINDOW = 0.05
+# summaries group functions and data by address, and types by a number derived from their id,
+# into buckets of these sizes
+ADDRESS_BUCKET_SIZE = 1 << 16
+TYPE_BUCKET_SIZE = 1 << 56
+
+def make_hello(challenge):
+    raw = bv.file.raw
+
+    hello = {
+        "kind": "hello",
+        "version": PROTOCOL_VERSION,
+        "tool": "binaryninja",
+        "tool_version": str(core_version()),
+        "binary_hash": hashlib.sha256(raw.read(raw.start, raw.length)).hexdigest(),
+        "user": BINAL_USER or getpass.getuser(),
+        "objects": ["type", "function", "data", "comment"],
+        "messages": ["push", "delete", "rename", "pull", "response", "error", "ack", "reject", "summary", "hashes", "cursor", "goto", "lock", "unlock"],
+        "encodings": ["json"],
+        "compression": ["deflate"],
+    }
+
+    if challenge:
+        hello["challenge"] = challenge
+
+    if BINAL_FILTERS:
+        hello["filters"] = BINAL_FILTERS
+
+    return hello
+
+def accept_tls(s: socket.socket):
+    context = ssl.SSLContext(ssl.PROTOCOL_TLS_SERVER)
+    context.load_cert_chain(TLS_CERTIFICATE)
+
+    s.settimeout(HANDSHAKE_TIMEOUT)
+    s = context.wrap_socket(s, server_side=True)
+    s.settimeout(None)
+
+    return s
+
+def connect_tls(s: socket.socket):
+    # the hub's certificate is self-signed, so it is checked against the pinned fingerprint instead
+    context = ssl.SSLContext(ssl.PROTOCOL_TLS_CLIENT)
+    context.check_hostname = False
+    context.verify_mode = ssl.CERT_NONE
+
+    s = context.wrap_socket(s, server_hostname="binal")
+    fingerprint = hashlib.sha256(s.getpeercert(binary_form=True)).hexdigest()
+
+    if fingerprint != BINAL_HUB_FINGERPRINT.replace(":", "").lower():
+        s.close()
+        raise ssl.SSLError(f"hub certificate {fingerprint} is not the pinned one")
+
+    return s
+
+def prove(challenge, other):
+    return hmac.new(BINAL_TOKEN.encode("utf-8"), (challenge + other).encode("utf-8"), hashlib.sha256).hexdigest()
+
+# functions and data are identified by address, types by an id made up by whoever defined them first
+type_ids = {}
+
+def type_id(name):
+    if name not in type_ids:
+        type_ids[name] = f"type:{uuid.uuid4()}"
+
+    return type_ids[name]
+
+def function_id(address):
+    return f"function:{address:#x}"
+
+def data_id(address):
+    return f"data:{address:#x}"
+
+# there is one comment per place, so comments go by the address they are at or the id of what they are attached to
+def address_comment_id(address):
+    return f"comment:{address:#x}"
+
+def function_comment_id(address):
+    return f"comment:{function_id(address)}"
+
+# SHA-256 of the object as JSON with sorted keys and no whitespace, the same as binal computes
+def content_hash(obj):
+    return hashlib.sha256(json.dumps(obj, sort_keys=True, separators=(",", ":"), ensure_ascii=False).encode("utf-8")).hexdigest()
+
+def bucket_start(object_id, obj):
+    target = obj.get("target", {})
+    address = obj.get("location", target.get("address", target.get("location")))
+
+    if address is None:
+        position = int.from_bytes(hashlib.sha256(object_id.encode("utf-8")).digest()[:8], "big")
+        return position - position % TYPE_BUCKET_SIZE
+
+    return address - address % ADDRESS_BUCKET_SIZE
+
+# content hashes of every object, by kind and bucket start
+def hash_index(objects: dict):
+    index = {}
+
+    for object_id, obj in objects.items():
+        kind = object_id.partition(":")[0]
+        index.setdefault((kind, bucket_start(object_id, obj)), {})[object_id] = content_hash(obj)
+
+    return index
+
+def summarize(index: dict):
+    buckets = []
+
+    for (kind, start), hashes in sorted(index.items()):
+        lines = "".join(f"{object_id}:{hashes[object_id]}\n" for object_id in sorted(hashes))
+        buckets.append({ "object": kind, "start": start, "count": len(hashes), "hash": hashlib.sha256(lines.encode("utf-8")).hexdigest() })
+
+    return buckets
+
+def lift_all():
+    objects = {}
+
+    for type_ in bv.types.values():
+        objects.update(lift_type(type_))
+    for func in bv.functions:
+        objects.update(lift_function(func))
+    for var in bv.data_vars.values():
+        objects.update(lift_global(var))
+    for address in bv.address_comments:
+        objects.update(lift_comment(address))
+
+    return objects
+
+def lift_object(object_id):
+    kind, _, key = object_id.partition(":")
+
+    if kind == "function":
+        func = bv.get_function_at(int(key, 16))
+        return lift_function(func) if func else {}
+    if kind == "data":
+        var = bv.get_data_var_at(int(key, 16))
+        return lift_global(var) if var else {}
+    if kind == "type":
+        for name, other in type_ids.items():
+            if other == object_id and name in bv.types:
+                return lift_type(bv.types[name])
+    if kind == "comment" and key.startswith("function:"):
+        func = bv.get_function_at(int(key.partition(":")[2], 16))
+        return lift_comments(func) if func else {}
+    if kind == "comment" and not key.startswith("type:"):
+        return lift_comment(int(key, 16))
+
+    return {}
+
+# the comment on a function as a whole, and the ones on its instructions
+def lift_comments(func):
+    binal_objects = {}
+
+    if func.comment:
+        binal_objects[function_comment_id(func.start)] = {
+            "kind": "comment",
+            "target": { "kind": "function", "location": func.start },
+            "text": func.comment,
+        }
+
+    for address, text in func.comments.items():
+        binal_objects[address_comment_id(address)] = {
+            "kind": "comment",
+            "target": { "kind": "address", "address": address },
+            "text": text,
+        }
+
+    return binal_objects
+
+def lift_comment(address):
+    text = bv.get_comment_at(address)
+
+    for func in bv.get_functions_containing(address):
+        text = func.get_comment_at(address) or text
+
+    if not text:
+        return {}
+
+    return { address_comment_id(address): { "kind": "comment", "target": { "kind": "address", "address": address }, "text": text } }
 
 def lift_function(func):
     binal_objects = {}
@@ -34,12 +248,14 @@ def lift_function(func):
     
     binal_func = {
         "kind": "function",
+        "name": func.name,
         "location": func.start,
         "return_type": func.return_type.get_string(),
         "arguments": arguments,
     }
 
-    binal_objects[func.name] = binal_func
+    binal_objects[function_id(func.start)] = binal_func
+    binal_objects.update(lift_comments(func))
 
     return binal_objects
 
@@ -67,7 +283,7 @@ def lift_type(type_: Type):
 
         parsed_types.add(type_)
 
-        binal_type = {"kind": "type", "size": type_.width, "alignment": type_.alignment}
+        binal_type = {"kind": "type", "name": type_.get_string(), "size": type_.width, "alignment": type_.alignment}
         
         if type_.type_class == TypeClass.PointerTypeClass:
             binal_type["info"] = { "kind": "pointer" }
@@ -122,168 +338,296 @@ def lift_type(type_: Type):
             # any other types shouldn't be sent either
             continue
 
-        binal_types[type_.get_string()] = binal_type
+        binal_types[type_id(type_.get_string())] = binal_type
 
     return binal_types
 
 def lift_global(global_):
     binal_objects = lift_type(global_.type)
 
-    binal_global = { "kind": "global", "location": global_.address, "global_type": global_.type.get_string() }
-    binal_objects[global_.name] = binal_global
+    binal_global = { "kind": "global", "name": global_.name, "location": global_.address, "global_type": global_.type.get_string() }
+    binal_objects[data_id(global_.address)] = binal_global
 
     return binal_objects
 
 def lower_and_add_types(objects: dict):
     lowered_types = {}
+    rejected = {}
+
+    stacks: list[list] = [[(object_id, obj) for object_id, obj in objects.items() if obj["kind"] == "type"], []]
+    lowered_last_pass = 0
 
-    stacks: list[list] = [[(name, obj) for name, obj in objects if obj["kind"] == "type"], []]
     while stacks[0] or stacks[1]:
         if not stacks[0]:
+            # a whole pass without lowering anything means the rest can never be resolved
+            if len(lowered_types) == lowered_last_pass:
+                for object_id, _ in stacks[1]:
+                    rejected[object_id] = "unresolved type dependencies"
+                break
+
+            lowered_last_pass = len(lowered_types)
             stacks[0], stacks[1] = stacks[1], stacks[0]
 
         pair = stacks[0].pop()
         type_ = pair[1]        
-        name = pair[0]
-
-        kind = type_["info"]["kind"]
-        size = type_["size"]
-
-        print(name)
-
-        if kind == "uint" and size == 0:
-            lowered_types[name] = Type.void()
-        elif kind == "uint":
-            lowered_types[name] = Type.int(size, False)
-        elif kind == "int":
-            lowered_types[name] = Type.int(size)
-        elif kind == "float":
-            lowered_types[name] = Type.float(size)
-        elif kind == "bool":
-            lowered_types[name] = Type.bool() 
-        elif kind == "pointer":
-            target_type = lowered_types.get(type_["info"]["to_type"])
+        object_id = pair[0]
+        name = type_["name"]
+
+        # from now on the type goes by the id binal knows it by
+        type_ids[name] = object_id
+
+        try:
+            kind = type_["info"]["kind"]
+            size = type_["size"]
+
+            if kind == "uint" and size == 0:
+                lowered_types[name] = Type.void()
+            elif kind == "uint":
+                lowered_types[name] = Type.int(size, False)
+            elif kind == "int":
+                lowered_types[name] = Type.int(size)
+            elif kind == "float":
+                lowered_types[name] = Type.float(size)
+            elif kind == "bool":
+                lowered_types[name] = Type.bool() 
+            elif kind == "pointer":
+                target_type = lowered_types.get(type_["info"]["to_type"])
             
-            if not target_type:
-                stacks[1].append(type_)
-                continue
+                if not target_type:
+                    stacks[1].append((name, type_))
+                    continue
 
-            binja_type = Type.pointer(bv.arch, type=target_type, width=size)
+                binja_type = Type.pointer(bv.arch, type=target_type, width=size)
         
-            for _ in range(type_["info"]["depth"]):
-                binja_type = Type.pointer(bv.arch, type=binja_type, width=size)
+                for _ in range(type_["info"]["depth"]):
+                    binja_type = Type.pointer(bv.arch, type=binja_type, width=size)
 
-            lowered_types[name] = binja_type
-        elif kind == "function":
-            binja_type = FunctionBuilder.create()
+                lowered_types[name] = binja_type
+            elif kind == "function":
+                binja_type = FunctionBuilder.create()
            
-            # python moment
-            failed = False
-            for parameter in type_["info"]["arg_types"]:
-                param_type = lowered_types.get(parameter)
+                # python moment
+                failed = False
+                for parameter in type_["info"]["arg_types"]:
+                    param_type = lowered_types.get(parameter)
                 
-                if not param_type:
-                    stacks[1].append((name, type_))
-                    failed = True
-                    break
+                    if not param_type:
+                        stacks[1].append((name, type_))
+                        failed = True
+                        break
 
-                binja_type.append(param_type)
+                    binja_type.append(param_type)
 
-            if failed:
-                continue
+                if failed:
+                    continue
 
-            return_type = lowered_types.get(type_["info"]["return_type"])
+                return_type = lowered_types.get(type_["info"]["return_type"])
             
-            if not return_type:
-                stacks[1].append((name, type_))
-                continue
+                if not return_type:
+                    stacks[1].append((name, type_))
+                    continue
 
-            binja_type.return_value = return_type
-            lowered_types[name] = binja_type
-        elif kind == "struct":
-            binja_type = StructureBuilder.create()
+                binja_type.return_value = return_type
+                lowered_types[name] = binja_type
+            elif kind == "struct":
+                binja_type = StructureBuilder.create()
 
-            failed = False
-            for field in type_["info"]["fields"]:
-                field_type = lowered_types.get(field["field_type"])
+                failed = False
+                for field in type_["info"]["fields"]:
+                    field_type = lowered_types.get(field["field_type"])
 
-                if not field_type:
-                    stacks[1].append((name, type_))
+                    if not field_type:
+                        stacks[1].append((name, type_))
                    
-                    # this eliminates circular dependency between two or more structures
-                    bv.define_user_type(name, Type.structure())
-                    lowered_types[name] = Type.named_type_from_registered_type(bv, name)
+                        # this eliminates circular dependency between two or more structures
+                        bv.define_user_type(name, Type.structure())
+                        lowered_types[name] = Type.named_type_from_registered_type(bv, name)
                     
-                    failed = True
-                    break
+                        failed = True
+                        break
 
-                binja_type.add_member_at_offset(field["name"], field_type, field["offset"])
+                    binja_type.add_member_at_offset(field["name"], field_type, field["offset"])
 
-            if failed:
-                continue
+                if failed:
+                    continue
 
-            bv.define_user_type(name, binja_type)
-            lowered_types[name] = Type.named_type_from_registered_type(bv, name)
-        elif kind == "enum":
-            enum_values = [ [ value["name"], value["value"] ] for value in type_["info"]["values"] ]
+                bv.define_user_type(name, binja_type)
+                lowered_types[name] = Type.named_type_from_registered_type(bv, name)
+            elif kind == "enum":
+                enum_values = [ [ value["name"], value["value"] ] for value in type_["info"]["values"] ]
             
-            bv.define_user_type(name, Type.enumeration(members=enum_values, width=size))
-            lowered_types[name] = Type.named_type_from_registered_type(bv, name)
-        elif kind == "array":
-            lowered_types[name] = Type.array(Type.int(4), type_["info"]["count"])
+                bv.define_user_type(name, Type.enumeration(members=enum_values, width=size))
+                lowered_types[name] = Type.named_type_from_registered_type(bv, name)
+            elif kind == "array":
+                lowered_types[name] = Type.array(Type.int(4), type_["info"]["count"])
+        except Exception as e:
+            rejected[object_id] = str(e)
+
+    return rejected
 
 def lower_and_add_functions(objects: dict):
-    for name, obj in objects:
+    rejected = {}
+
+    for object_id, obj in objects.items():
         if obj["kind"] != "function":
             continue
 
-        func = bv.create_user_function(obj["location"])
-        func.name = name
-        
-        function_type = Type.function()
+        try:
+            func = bv.create_user_function(obj["location"])
+            func.name = obj["name"]
+            
+            function_type = Type.function()
+
+            for param in obj["arguments"]:
+                var_type = bv.types[param["arg_type"]]
+                function_type.append(var_type)
 
-        for param in obj["arguments"]:
-            var_type = bv.types[param["arg_type"]]
-            function_type.append(var_type)
+            function_type.return_value = bv.types[obj["return_type"]]
+            func.type = function_type
 
-        function_type.return_value = bv.types[obj["return_type"]]
-        func.type = function_type
+            for i, param in enumerate(obj["arguments"]):
+                func.set_parameter_name(i, param["name"])
+        except Exception as e:
+            rejected[object_id] = str(e)
 
-        for i, param in enumerate(obj["arguments"]):
-            func.set_parameter_name(i, param["name"])
+    return rejected
 
 def lower_and_add_globals(objects):
-    for name, obj in objects:
+    rejected = {}
+
+    for object_id, obj in objects.items():
         if obj["kind"] != "global":
             continue
 
-        bv.create_data_var(obj["location"], bv.types[obj["global_type"]], name)
+        try:
+            bv.create_data_var(obj["location"], bv.types[obj["global_type"]], obj["name"])
+        except Exception as e:
+            rejected[object_id] = str(e)
 
-def lower_and_add_objects(objects: dict):
-    lower_and_add_types(objects)
-    lower_and_add_functions(objects)
-    lower_and_add_globals(objects)
+    return rejected
 
-def remove_object(name: str):
-    funcs = bv.get_functions_by_name(name)
-    
-    if funcs:
-        bv.remove_user_function(funcs[0])
-    
-    bv.undefine_user_type(name)
-    
-    for var in bv.data_vars.values():
-        if var.name == name:
-            bv.remove_user_data_var(var.address)
+def lower_and_add_comments(objects):
+    rejected = {}
+
+    for object_id, obj in objects.items():
+        if obj["kind"] != "comment":
+            continue
+
+        target = obj["target"]
+
+        try:
+            if target["kind"] == "address":
+                funcs = bv.get_functions_containing(target["address"])
+
+                if funcs:
+                    funcs[0].set_comment_at(target["address"], obj["text"])
+                else:
+                    bv.set_comment_at(target["address"], obj["text"])
+            elif target["kind"] == "function":
+                func = bv.get_function_at(target["location"])
+
+                if not func:
+                    raise ValueError(f"there is no function at {target['location']:#x}")
+
+                func.comment = obj["text"]
+            else:
+                rejected[object_id] = "Binary Ninja has no comments on types"
+        except Exception as e:
+            rejected[object_id] = str(e)
+
+    return rejected
+
+# returns the objects that could not be added, along with the reason
+def lower_and_add_objects(objects: dict):
+    rejected = lower_and_add_types(objects)
+    rejected.update(lower_and_add_functions(objects))
+    rejected.update(lower_and_add_globals(objects))
+    rejected.update(lower_and_add_comments(objects))
+
+    return rejected
+
+def rename_object(kind: str, old: str, new: str):
+    if kind == "type":
+        if old in type_ids:
+            type_ids[new] = type_ids.pop(old)
+
+        bv.rename_type(old, new)
+    elif kind == "function":
+        funcs = bv.get_functions_by_name(old)
+
+        if funcs:
+            funcs[0].name = new
+    elif kind == "data":
+        for var in bv.data_vars.values():
+            if var.name == old:
+                var.name = new
+
+def remove_object(object_id: str):
+    kind, _, key = object_id.partition(":")
+
+    if kind == "function":
+        func = bv.get_function_at(int(key, 16))
+
+        if func:
+            bv.remove_user_function(func)
+    elif kind == "data":
+        bv.remove_user_data_var(int(key, 16))
+    elif kind == "type":
+        for name, other in list(type_ids.items()):
+            if other == object_id:
+                del type_ids[name]
+                bv.undefine_user_type(name)
+    elif kind == "comment" and key.startswith("function:"):
+        func = bv.get_function_at(int(key.partition(":")[2], 16))
+
+        if func:
+            func.comment = ""
+    elif kind == "comment" and not key.startswith("type:"):
+        address = int(key, 16)
+
+        for func in bv.get_functions_containing(address):
+            func.set_comment_at(address, "")
+
+        bv.set_comment_at(address, "")
 
 class Connection:
     def __init__(self, socket: socket.socket):
         self.socket = socket
         self.buffer = b""
+        self.compressor = None
+        self.decompressor = None
 
     def send(self, data):
-        binary_data = json.dumps(data) + "\n"
-        self.socket.sendall(binary_data.encode("utf-8"))
+        binary_data = (json.dumps(data) + "\n").encode("utf-8")
+
+        if self.compressor:
+            binary_data = self.compressor.compress(binary_data) + self.compressor.flush(zlib.Z_SYNC_FLUSH)
+
+        self.socket.sendall(binary_data)
+
+    def recv_line(self):
+        self.socket.settimeout(HANDSHAKE_TIMEOUT)
+
+        try:
+            while b"\n" not in self.buffer:
+                data = self.socket.recv(1024)
+
+                if not data:
+                    raise ConnectionResetError
+
+                self.buffer += data
+        finally:
+            self.socket.settimeout(None)
+
+        data, _, self.buffer = self.buffer.partition(b"\n")
+        return json.loads(data.decode("utf-8"))
+
+    def enable_deflate(self):
+        self.compressor = zlib.compressobj(wbits=-15)
+        self.decompressor = zlib.decompressobj(wbits=-15)
+
+        # anything that arrived right behind the hello is already compressed
+        self.buffer = self.decompressor.decompress(self.buffer)
 
     def recv(self):
         data = self.socket.recv(1024)
@@ -291,6 +635,13 @@ class Connection:
         if not data:
             raise ConnectionResetError
 
+        # TLS hands out one record at a time, and select does not see what is left of it
+        while isinstance(self.socket, ssl.SSLSocket) and self.socket.pending():
+            data += self.socket.recv(self.socket.pending())
+
+        if self.decompressor:
+            data = self.decompressor.decompress(data)
+
         self.buffer += data
 
         if b"\n" in self.buffer:
@@ -308,41 +659,145 @@ class Connection:
 
 
 class DecompilerHandler(BinaryDataNotification):
-    def __init__(self, connection: Connection):
+    def __init__(self, connection: Connection, supports_rename: bool, supports_cursor: bool):
         super().__init__()
 
         self.connection = connection
+        self.supports_rename = supports_rename
+        self.supports_cursor = supports_cursor
+
+        # names by address, to tell a rename from a new object
+        self.names = {("function", func.start): func.name for func in bv.functions}
+        self.names.update({("data", var.address): var.name for var in bv.data_vars.values()})
+
+        # every object pushed over this connection along with the revision it was pushed at,
+        # so binal can pull whatever changed since a revision it already has
+        self.revision = 0
+        self.changes = {}
+
+        # content hashes from the last summary binal asked for
+        self.hashes = {}
+
+        # ids of the comments in each function, by its address
+        self.comments = {
+            func.start: set(lift_comments(func))
+            for func in bv.functions
+        }
+
+        # notifications come in on analysis threads, while the network thread flushes the pushes
+        self.lock = threading.Lock()
+        self.outgoing = {}
+        self.flush_at = 0
+
+    def push(self, binal_objects):
+        with self.lock:
+            self.revision += 1
+
+            for object_id, obj in binal_objects.items():
+                self.changes[object_id] = (self.revision, obj)
+
+            if not self.outgoing:
+                self.flush_at = time.monotonic() + COALESCE_WINDOW
+
+            self.outgoing.update(binal_objects)
+
+    # sends the held back pushes as one, once they have waited long enough or right away if forced
+    def flush(self, force=False):
+        with self.lock:
+            self.flush_locked(force)
+
+    def flush_locked(self, force):
+        if not self.outgoing or (not force and time.monotonic() < self.flush_at):
+            return
+
+        objects, self.outgoing = self.outgoing, {}
+        self.connection.send({ "kind": "push", "objects": objects })
+
+    # anything else goes out right after the pushes before it, so binal sees them in order
+    def send(self, message):
+        with self.lock:
+            self.flush_locked(True)
+            self.connection.send(message)
+
+    def changed_since(self, revision):
+        with self.lock:
+            return { object_id: obj for object_id, (changed, obj) in self.changes.items() if changed > revision }
+
+    # the push that follows carries the new name under the same id, but a rename tells binal's
+    # other peers what happened
+    def track_name(self, kind, address, name):
+        old = self.names.get((kind, address))
+        self.names[(kind, address)] = name
+
+        if old is None or old == name:
+            return
+
+        if self.supports_rename:
+            self.send({ "kind": "rename", "object": kind, "old": old, "new": name })
 
     def function_added(self, view: BinaryView, func: Function) -> None:
-        binal_objects = lift_function(func)
-        self.connection.send({ "kind": "push", "objects": binal_objects})
+        self.names[("function", func.start)] = func.name
+        self.push(lift_function(func))
 
     def function_updated(self, view: BinaryView, func: Function) -> None:
+        self.track_name("function", func.start, func.name)
+
         binal_objects = lift_function(func)
-        self.connection.send({ "kind": "push", "objects": binal_objects})
+        self.track_comments(func.start, binal_objects)
+        self.push(binal_objects)
 
     def function_removed(self, view: BinaryView, func: Function) -> None:
-        self.connection.send({ "kind": "delete", "name": func.name })
+        self.names.pop(("function", func.start), None)
+        self.changes.pop(function_id(func.start), None)
+        self.send({ "kind": "delete", "object": function_id(func.start) })
+        self.track_comments(func.start, {})
+
+    # comments are only pushed along with their function, so one that is gone has to be noticed
+    # by comparing with what was pushed last time
+    def track_comments(self, address, binal_objects):
+        comments = { object_id for object_id, obj in binal_objects.items() if obj["kind"] == "comment" }
+        old = self.comments.get(address, set())
+        self.comments[address] = comments
+
+        for object_id in old - comments:
+            self.changes.pop(object_id, None)
+            self.send({ "kind": "delete", "object": object_id })
 
     def type_defined(
         self, view: BinaryView, name: QualifiedName, type: Type
     ) -> None:
-        binal_types = lift_type(type)
-        self.connection.send({ "kind": "push", "objects": binal_types})
+        self.push(lift_type(type))
 
     def type_undefined(self, view: BinaryView, name: QualifiedName, type: Type) -> None:
-        self.connection.send({ "kind": "delete", "name": type.get_string() })
+        object_id = type_id(type.get_string())
+
+        self.changes.pop(object_id, None)
+        self.send({ "kind": "delete", "object": object_id })
 
     def data_var_updated(self, view, var):
-        binal_objects = lift_global(var)
-        self.connection.send({ "kind": "push", "objects": binal_objects})
+        self.track_name("data", var.address, var.name)
+        self.push(lift_global(var))
     
     def data_var_added(self, view, var):
-        binal_objects = lift_global(var)
-        self.connection.send({ "kind": "push", "objects": binal_objects})
+        self.names[("data", var.address)] = var.name
+        self.push(lift_global(var))
 
     def data_var_removed(self, view, var):
-        self.connection.send({ "kind": "delete", "name": var.name })
+        self.names.pop(("data", var.address), None)
+        self.changes.pop(data_id(var.address), None)
+        self.send({ "kind": "delete", "object": data_id(var.address) })
+
+if UIContext:
+    # reports where the user navigates to in this binary, so the tools following along can go there too
+    class CursorHandler(UIContextNotification):
+        def __init__(self, handler):
+            super().__init__()
+
+            self.handler = handler
+
+        def OnAddressChange(self, context, frame, view, location):
+            if view and view.getData() == bv:
+                self.handler.report_cursor(location.getOffset())
 
 class NetworkHandler(BackgroundTaskThread):
     def __init__(self, socket: socket.socket):
@@ -350,69 +805,257 @@ class NetworkHandler(BackgroundTaskThread):
 
         self.connections = [socket]
         self.notifications = {}
+        self.cursor = CursorHandler(self) if UIContext else None
+
+        # who holds the objects binal told us are locked, by object id
+        self.locks = {}
 
     def sync_objects(self, connection: Connection, object_iter: Iterable):
         objects = {}
 
-        for index, (name, obj) in enumerate(object_iter):
-            objects[name] = obj
+        for index, (object_id, obj) in enumerate(object_iter):
+            objects[object_id] = obj
             if index != 0 and index % INIT_SYNC_BATCH == 0:
                 connection.send({"kind": "push", "objects": objects})
                 objects.clear()
 
         connection.send({"kind": "push", "objects": objects})
 
-    def init_connection(self, connection: Connection):
-        self.sync_objects(connection, ((name, obj) for d in map(lift_type, bv.types.values()) for name, obj in d.items()))
-        self.sync_objects(connection, ((name, obj) for d in map(lift_function, bv.functions) for name, obj in d.items()))
-        self.sync_objects(connection, ((name, obj) for d in map(lift_global, bv.data_vars.values()) for name, obj in d.items()))
+    def init_connection(self, connection: Connection, hub=False):
+        challenge = secrets.token_hex(32) if BINAL_TOKEN else None
+
+        try:
+            connection.send(make_hello(challenge))
+
+            # binal sends its hello right away, and it decides how everything after it is sent
+            hello = connection.recv_line()
+        except (OSError, ValueError) as e:
+            print(f"Did not get a hello from binal: {e}")
+            connection.close()
+            return
+
+        if hello.get("kind") != "hello" or hello["version"] != PROTOCOL_VERSION:
+            print(f"binal speaks protocol version {hello.get('version')}, closing connection")
+            connection.close()
+            return
+
+        if not self.authenticate(connection, challenge, hello.get("challenge")):
+            connection.close()
+            return
+
+        if "deflate" in hello.get("compression", []):
+            connection.enable_deflate()
+
+        # the objects are spread over several pushes, so binal should only apply them once they all arrived
+        transactions = "begin" in hello["messages"]
+
+        # binal that can compare summaries pulls only the objects it does not already have, but a hub never pulls
+        if "summary" not in hello["messages"] or hub:
+            if transactions:
+                connection.send({ "kind": "begin", "id": 0 })
+
+            self.sync_objects(connection, ((name, obj) for d in map(lift_type, bv.types.values()) for name, obj in d.items()))
+            self.sync_objects(connection, ((name, obj) for d in map(lift_function, bv.functions) for name, obj in d.items()))
+            self.sync_objects(connection, ((name, obj) for d in map(lift_global, bv.data_vars.values()) for name, obj in d.items()))
+
+            if transactions:
+                connection.send({ "kind": "commit", "id": 0 })
         
-        notify = DecompilerHandler(connection)
+        notify = DecompilerHandler(connection, "rename" in hello["messages"], "cursor" in hello["messages"])
 
         self.notifications[connection] = notify
         bv.register_notification(notify)
 
         self.connections.append(connection)
 
-    def handle_message(self, message):
+    # Both sides answer the other's challenge with an HMAC of the two, keyed with the token
+    def authenticate(self, connection: Connection, challenge, other):
+        if not BINAL_TOKEN:
+            if other:
+                print("binal requires a token, set BINAL_TOKEN")
+                return False
+
+            return True
+
+        if not other or other == challenge:
+            print("binal is not set up with a token, closing connection")
+            return False
+
+        try:
+            connection.send({ "kind": "auth", "proof": prove(other, challenge) })
+            reply = connection.recv_line()
+        except (OSError, ValueError) as e:
+            print(f"binal did not answer the token challenge: {e}")
+            return False
+
+        if reply.get("kind") != "auth" or not hmac.compare_digest(str(reply.get("proof")), prove(challenge, other)):
+            print("binal does not know the token, closing connection")
+            return False
+
+        return True
+
+    def handle_message(self, connection: Connection, message):
         kind = message["kind"]
         
         if kind == "push":
-            lower_and_add_objects(message["objects"])
+            rejected = lower_and_add_objects(message["objects"])
+
+            if "id" in message:
+                if rejected:
+                    connection.send({ "kind": "reject", "id": message["id"], "rejected": rejected })
+
+                applied = [object_id for object_id in message["objects"] if object_id not in rejected]
+                connection.send({ "kind": "ack", "id": message["id"], "applied": applied })
         if kind == "delete":
-            remove_object(message["name"])
+            remove_object(message["object"])
+        if kind == "rename":
+            # so the rename is not reported back as one made here
+            for notify in self.notifications.values():
+                for key, name in notify.names.items():
+                    if key[0] == message["object"] and name == message["old"]:
+                        notify.names[key] = message["new"]
+
+            rename_object(message["object"], message["old"], message["new"])
+        if kind == "pull":
+            self.handle_pull(connection, message)
+        if kind == "goto":
+            address = message["address"]
+            execute_on_main_thread(lambda: bv.navigate(bv.view, address))
+        if kind == "error" and "id" not in message:
+            print(f"binal refused a change: {message['message']}")
+        if kind == "lock":
+            self.locks[message["object"]] = message.get("holder")
+            print(f"{message['object']} is locked by {message.get('holder')}")
+        if kind == "unlock":
+            if self.locks.pop(message["object"], None):
+                print(f"{message['object']} is unlocked")
+
+    # call these from the console, as in handler.lock(function_id(here)), to keep the others in a
+    # binal hub from changing an object while you work on it
+    def lock(self, object_id):
+        for notify in list(self.notifications.values()):
+            notify.send({ "kind": "lock", "object": object_id })
+
+    def unlock(self, object_id):
+        for notify in list(self.notifications.values()):
+            notify.send({ "kind": "unlock", "object": object_id })
+
+    # called on the UI thread, while the connections are handled on this one
+    def report_cursor(self, address):
+        for notify in list(self.notifications.values()):
+            if not notify.supports_cursor:
+                continue
+
+            try:
+                notify.send({ "kind": "cursor", "address": address })
+            except OSError:
+                # the connection is closed once select notices
+                pass
+
+    def handle_pull(self, connection: Connection, message):
+        query = message["query"]
+        notify = self.notifications[connection]
+
+        if query["kind"] == "type":
+            type_ = bv.types.get(query["name"])
+            objects = lift_type(type_) if type_ else {}
+        elif query["kind"] == "functions":
+            objects = {}
+
+            for func in bv.functions:
+                if query["start"] <= func.start < query["end"]:
+                    objects.update(lift_function(func))
+        elif query["kind"] == "changed":
+            objects = notify.changed_since(query["since"])
+        elif query["kind"] == "summary":
+            # kept for the hashes queries that follow, so they describe the same objects
+            notify.hashes = hash_index(lift_all())
+            notify.send({ "kind": "summary", "id": message["id"], "buckets": summarize(notify.hashes) })
+            return
+        elif query["kind"] == "hashes":
+            hashes = notify.hashes.get((query["object"], query["start"]), {})
+            notify.send({ "kind": "hashes", "id": message["id"], "hashes": hashes })
+            return
+        elif query["kind"] == "objects":
+            objects = {}
+
+            for object_id in query["ids"]:
+                objects.update(lift_object(object_id))
+        else:
+            objects = {}
+
+        if not objects and query["kind"] != "changed":
+            notify.send({ "kind": "error", "id": message["id"], "message": "no matching objects" })
+            return
+
+        # binal relies on the pushes made before the pull arriving before the response
+        notify.send({ "kind": "response", "id": message["id"], "revision": notify.revision, "objects": objects })
 
     def close(self, connection: Connection):
         if connection != self.connections[0]:
-            bv.unregister_notification(self.notifications[connection])
+            bv.unregister_notification(self.notifications.pop(connection))
 
         connection.close()
         self.connections.remove(connection)
 
     def run(self):
+        if BINAL_HUB is not None:
+            if isinstance(BINAL_HUB, str):
+                s = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+                s.connect(BINAL_HUB)
+            else:
+                s = socket.create_connection(BINAL_HUB)
+
+            if BINAL_HUB_FINGERPRINT:
+                s = connect_tls(s)
+
+            self.init_connection(Connection(s), hub=True)
+
+        if self.cursor:
+            UIContext.registerNotification(self.cursor)
+
         while not self.cancelled:
-            read, _, error = select.select(self.connections, [], [], 0)
+            read, _, error = select.select(self.connections, [], [], COALESCE_WINDOW)
+
+            for notify in list(self.notifications.values()):
+                try:
+                    notify.flush()
+                except OSError:
+                    # the connection is closed below, once select notices
+                    pass
 
             for connection in read:
                 if connection == self.connections[0]:
                     s, _ = connection.accept()
-                    
+
+                    if TLS_CERTIFICATE:
+                        try:
+                            s = accept_tls(s)
+                        except OSError as e:
+                            print(f"TLS handshake with binal failed: {e}")
+                            s.close()
+                            continue
+
                     self.init_connection(Connection(s))
                 else:
                     try:
                         data = connection.recv()
                     except ConnectionResetError:
-                        self.connections.remove(connection)
+                        # binal reconnects on its own and gets everything pushed again
+                        self.close(connection)
                         continue
 
                     if not data:
                         continue
 
-                    self.handle_message(data)
+                    self.handle_message(connection, data)
 
             for connection in error:
                 self.close(connection)
 
+        if self.cursor:
+            UIContext.unregisterNotification(self.cursor)
+
         for connection in self.connections:
             if connection != self.connections[0]:
                 bv.unregister_notification(self.notifications[connection])
@@ -420,7 +1063,7 @@ class NetworkHandler(BackgroundTaskThread):
             connection.close()
 
 s = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
-s.bind(("127.0.0.1", PORT_NUMBER))
+s.bind((LISTEN_ADDRESS, PORT_NUMBER))
 s.listen(1)
 
 handler = NetworkHandler(s)
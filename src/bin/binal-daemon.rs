@@ -0,0 +1,309 @@
+// Runs a single project without the GUI, for example on a shared analysis server
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use binal::{
+    net::{Client, Role, Server},
+    project::{Project, ProjectKind},
+    record::{Recorder, Replay},
+    tls::{Identity, Tls},
+    transport::{self, Memory, Transport},
+};
+use serde::Deserialize;
+
+const NETWORK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const USAGE: &str = "usage: binal-daemon [--config FILE] [--project FILE] \
+                     [--connect ADDR [--fingerprint SHA256] | --listen ADDR [--certificate FILE]] \
+                     [--token TOKEN] [--read-only-token TOKEN] [--save-interval SECS] \
+                     [--record FILE] [--replay FILE]";
+
+// Read from the JSON file passed with --config, then overridden by the other flags
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    // file the project is loaded from and saved to
+    project: Option<PathBuf>,
+    // plugin to connect to, in any form `transport::parse` accepts
+    connect: Option<String>,
+    // address to accept plugin connections on, relaying between them
+    listen: Option<String>,
+    // seconds to wait between saves while there are unsaved changes
+    save_interval: u64,
+    // SHA-256 of the certificate the plugin presents, to connect to it over TLS
+    fingerprint: Option<String>,
+    // PEM file with the certificate and key to listen over TLS with, generated if missing
+    certificate: Option<PathBuf>,
+    // shared with every peer, which have to prove they know it before syncing anything
+    token: Option<String>,
+    // more tokens to listen with, along with the role of the peers using each one. Peers with
+    // `token` are contributors
+    tokens: HashMap<String, Role>,
+    // file every message exchanged is recorded to
+    record: Option<PathBuf>,
+    // recording to play back in place of connecting or listening, exiting once it is done
+    replay: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            project: None,
+            connect: None,
+            listen: None,
+            save_interval: 10,
+            fingerprint: None,
+            certificate: None,
+            token: None,
+            tokens: HashMap::new(),
+            record: None,
+            replay: None,
+        }
+    }
+}
+
+impl Config {
+    fn from_args() -> Result<Self, String> {
+        let mut args = std::env::args().skip(1);
+        let mut flags = Vec::new();
+
+        while let Some(flag) = args.next() {
+            if flag == "-h" || flag == "--help" {
+                return Err(String::from(USAGE));
+            }
+
+            let Some(value) = args.next() else {
+                return Err(format!("{} expects a value\n{}", flag, USAGE));
+            };
+
+            flags.push((flag, value));
+        }
+
+        let mut config = match flags.iter().find(|(flag, _)| flag == "--config") {
+            Some((_, path)) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read config {}: {}", path, e))?;
+
+                serde_json::from_str(&text)
+                    .map_err(|e| format!("Could not parse config {}: {}", path, e))?
+            }
+            None => Self::default(),
+        };
+
+        for (flag, value) in flags {
+            match flag.as_str() {
+                "--config" => {}
+                "--project" => config.project = Some(PathBuf::from(value)),
+                "--connect" => config.connect = Some(value),
+                "--listen" => config.listen = Some(value),
+                "--fingerprint" => config.fingerprint = Some(value),
+                "--certificate" => config.certificate = Some(PathBuf::from(value)),
+                "--token" => config.token = Some(value),
+                "--read-only-token" => {
+                    config.tokens.insert(value, Role::ReadOnly);
+                }
+                "--record" => config.record = Some(PathBuf::from(value)),
+                "--replay" => config.replay = Some(PathBuf::from(value)),
+                "--save-interval" => {
+                    config.save_interval = u64::from_str(&value)
+                        .map_err(|e| format!("Invalid save interval {}: {}", value, e))?
+                }
+                _ => return Err(format!("Unknown flag {}\n{}", flag, USAGE)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_address(text: &str) -> Result<Box<dyn Transport>, String> {
+    transport::parse(text).map_err(|e| format!("Invalid address {}: {}", text, e))
+}
+
+// Plays a recording back into a project of the kind it was recorded with, over a transport that
+// never leaves the process
+fn replay(
+    path: &Path,
+    recorder: Option<Arc<Recorder>>,
+) -> Result<(ProjectKind, String, JoinHandle<()>), String> {
+    let replay = Replay::open(path)
+        .map_err(|e| format!("Could not open recording {}: {}", path.display(), e))?;
+
+    let transport = Memory(String::from("replay"));
+    let name = format!("Replay {}", path.display());
+
+    log::info!("Replaying {} messages", replay.entries.len());
+
+    if replay.is_hub() {
+        let server = Server::listen(&transport, HashMap::new(), recorder)
+            .map_err(|e| format!("Could not listen for the replay: {}", e))?;
+        let player = replay.connect(Box::new(transport), true);
+
+        Ok((ProjectKind::Hub(server), name, player))
+    } else {
+        let player = replay
+            .serve(&transport, true)
+            .map_err(|e| format!("Could not start the replay: {}", e))?;
+        let client = Client::connect(Box::new(transport), None, recorder)
+            .map_err(|e| format!("Could not connect to the replay: {}", e))?;
+
+        Ok((ProjectKind::Remote(client), name, player))
+    }
+}
+
+fn run(config: Config) -> Result<(), String> {
+    let recorder = match &config.record {
+        Some(path) => {
+            let recorder = Recorder::create(path)
+                .map_err(|e| format!("Could not record to {}: {}", path.display(), e))?;
+
+            log::info!("Recording to {}", path.display());
+            Some(recorder)
+        }
+        None => None,
+    };
+
+    let mut player = None;
+
+    let (kind, name) = match (config.replay, config.connect, config.listen) {
+        (Some(path), None, None) => {
+            let (kind, name, replaying) = replay(&path, recorder)?;
+            player = Some(replaying);
+
+            (kind, name)
+        }
+        (Some(_), _, _) => {
+            return Err(String::from(
+                "Pass only one of --replay, --connect and --listen",
+            ))
+        }
+        (None, Some(address), None) => {
+            let mut transport = parse_address(&address)?;
+
+            if let Some(fingerprint) = &config.fingerprint {
+                transport = Box::new(Tls::connecting(transport, fingerprint)?);
+            }
+
+            let name = transport.to_string();
+
+            let client = Client::connect(transport, config.token, recorder)
+                .map_err(|e| format!("Could not connect to {}: {}", name, e))?;
+
+            log::info!(
+                "Connected to {} {}",
+                client.peer().tool,
+                client.peer().tool_version
+            );
+            (ProjectKind::Remote(client), name)
+        }
+        (None, None, Some(address)) => {
+            let mut transport = parse_address(&address)?;
+
+            if let Some(path) = &config.certificate {
+                let identity = Identity::load_or_generate(path)?;
+                log::info!("Certificate fingerprint {}", identity.fingerprint());
+
+                transport = Box::new(Tls::listening(transport, &identity)?);
+            }
+
+            let mut tokens = config.tokens;
+
+            if let Some(token) = config.token {
+                tokens.insert(token, Role::Contributor);
+            }
+
+            let server = Server::listen(transport.as_ref(), tokens, recorder)
+                .map_err(|e| format!("Could not listen on {}: {}", transport, e))?;
+
+            log::info!("Listening on {}", server.local_addr);
+            (ProjectKind::Hub(server), format!("Hub {}", transport))
+        }
+        (None, Some(_), Some(_)) => {
+            return Err(String::from("Pass only one of --connect and --listen"))
+        }
+        (None, None, None) => {
+            return Err(format!("Pass --connect, --listen or --replay\n{}", USAGE))
+        }
+    };
+
+    let mut project =
+        Project::create(kind, name).map_err(|e| format!("Could not create project: {}", e))?;
+
+    match config.project {
+        Some(path) => {
+            project
+                .persist(path.clone())
+                .map_err(|e| format!("Could not open project {}: {}", path.display(), e))?;
+
+            log::info!("Saving to {}", path.display());
+        }
+        None => log::warn!("No project file given, changes will not be saved"),
+    }
+
+    let running = Arc::new(AtomicBool::new(true));
+
+    {
+        let running = running.clone();
+
+        ctrlc::set_handler(move || running.store(false, Ordering::Relaxed))
+            .map_err(|e| format!("Could not install the interrupt handler: {}", e))?;
+    }
+
+    let save_interval = Duration::from_secs(config.save_interval);
+    let mut last_save = Instant::now();
+    let mut errors = VecDeque::new();
+
+    while running.load(Ordering::Relaxed) {
+        // a replay is over once everything in it was sent, and handled below
+        let replayed = player.as_ref().is_some_and(JoinHandle::is_finished);
+
+        project.handle_network_updates(&mut errors);
+
+        if project.has_unsaved_changes() && last_save.elapsed() >= save_interval {
+            project.save(&mut errors);
+            last_save = Instant::now();
+        }
+
+        for error in errors.drain(..) {
+            log::error!("{}", error);
+        }
+
+        if replayed {
+            break;
+        }
+
+        std::thread::sleep(NETWORK_POLL_INTERVAL);
+    }
+
+    log::info!("Shutting down");
+
+    if project.has_unsaved_changes() {
+        project.save(&mut errors);
+    }
+
+    for error in errors.drain(..) {
+        log::error!("{}", error);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let result = Config::from_args().and_then(run);
+
+    if let Err(e) = result {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,149 @@
+// Stands in for a decompiler plugin, for trying out binal without one
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use binal::{
+    mock::{MockPeer, Program},
+    tls::{Identity, Tls},
+    transport::{self, Transport},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// where the Binary Ninja plugin listens by default
+const DEFAULT_ADDRESS: &str = "127.0.0.1:12007";
+
+const USAGE: &str = "usage: binal-mock [--program FILE] \
+                     [--listen ADDR [--certificate FILE] | --connect ADDR [--fingerprint SHA256]] \
+                     [--token TOKEN]";
+
+#[derive(Default)]
+struct Config {
+    // JSON object of the program's objects by id, loaded at start and saved back on exit
+    program: Option<PathBuf>,
+    listen: Option<String>,
+    // hub to connect to instead of waiting for binal
+    connect: Option<String>,
+    certificate: Option<PathBuf>,
+    fingerprint: Option<String>,
+    token: Option<String>,
+}
+
+impl Config {
+    fn from_args() -> Result<Self, String> {
+        let mut config = Self::default();
+        let mut args = std::env::args().skip(1);
+
+        while let Some(flag) = args.next() {
+            if flag == "-h" || flag == "--help" {
+                return Err(String::from(USAGE));
+            }
+
+            let Some(value) = args.next() else {
+                return Err(format!("{} expects a value\n{}", flag, USAGE));
+            };
+
+            match flag.as_str() {
+                "--program" => config.program = Some(PathBuf::from(value)),
+                "--listen" => config.listen = Some(value),
+                "--connect" => config.connect = Some(value),
+                "--certificate" => config.certificate = Some(PathBuf::from(value)),
+                "--fingerprint" => config.fingerprint = Some(value),
+                "--token" => config.token = Some(value),
+                _ => return Err(format!("Unknown flag {}\n{}", flag, USAGE)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_address(text: &str) -> Result<Box<dyn Transport>, String> {
+    transport::parse(text).map_err(|e| format!("Invalid address {}: {}", text, e))
+}
+
+fn run(config: Config) -> Result<(), String> {
+    let program = match &config.program {
+        Some(path) if path.exists() => {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| format!("Could not read program {}: {}", path.display(), e))?;
+
+            serde_json::from_str(&text)
+                .map_err(|e| format!("Could not parse program {}: {}", path.display(), e))?
+        }
+        _ => Program::default(),
+    };
+
+    log::info!("Loaded {} objects", program.objects.len());
+
+    let mock = match (config.listen, config.connect) {
+        (Some(_), Some(_)) => return Err(String::from("Pass only one of --listen and --connect")),
+        (None, Some(address)) => {
+            let mut transport = parse_address(&address)?;
+
+            if let Some(fingerprint) = &config.fingerprint {
+                transport = Box::new(Tls::connecting(transport, fingerprint)?);
+            }
+
+            MockPeer::connect(transport.as_ref(), program, config.token)
+                .map_err(|e| format!("Could not connect to {}: {}", transport, e))?
+        }
+        (listen, None) => {
+            let mut transport = parse_address(listen.as_deref().unwrap_or(DEFAULT_ADDRESS))?;
+
+            if let Some(path) = &config.certificate {
+                let identity = Identity::load_or_generate(path)?;
+                log::info!("Certificate fingerprint {}", identity.fingerprint());
+
+                transport = Box::new(Tls::listening(transport, &identity)?);
+            }
+
+            let mock = MockPeer::listen(transport.as_ref(), program, config.token)
+                .map_err(|e| format!("Could not listen on {}: {}", transport, e))?;
+
+            log::info!("Listening on {}", transport);
+            mock
+        }
+    };
+
+    let running = Arc::new(AtomicBool::new(true));
+
+    {
+        let running = running.clone();
+
+        ctrlc::set_handler(move || running.store(false, Ordering::Relaxed))
+            .map_err(|e| format!("Could not install the interrupt handler: {}", e))?;
+    }
+
+    while running.load(Ordering::Relaxed) {
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    log::info!("Shutting down");
+
+    if let Some(path) = &config.program {
+        let text = serde_json::to_string_pretty(&mock.program()).map_err(|e| e.to_string())?;
+
+        std::fs::write(path, text)
+            .map_err(|e| format!("Could not save program {}: {}", path.display(), e))?;
+    }
+
+    Ok(())
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let result = Config::from_args().and_then(run);
+
+    if let Err(e) = result {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }
+}
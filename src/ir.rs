@@ -1,19 +1,26 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt::Display,
-    fs::{File, OpenOptions},
+    fs::File,
     io::{Read, Write},
+    ops::{Index, IndexMut},
     path::Path,
 };
 
 use serde::{Deserialize, Serialize};
 
-use crate::net::{self, Object};
+use crate::net::{self, Object, ObjectId};
 
 #[derive(Debug)]
 pub enum DatabaseError {
     Io(std::io::Error),
     Serde(serde_json::Error),
+    Referenced(String, Vec<String>),
+    Exists(String),
+    UnknownKind(String),
+    // the name of the object, and who holds the lock on it
+    Locked(String, String),
+    Missing(String),
 }
 
 impl From<std::io::Error> for DatabaseError {
@@ -28,36 +35,43 @@ impl From<serde_json::Error> for DatabaseError {
     }
 }
 
-impl<'a> Display for DatabaseError {
+impl Display for DatabaseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             DatabaseError::Io(e) => e.fmt(f),
             DatabaseError::Serde(e) => e.fmt(f),
+            DatabaseError::Referenced(name, by) => {
+                write!(f, "{} is still referenced by {}", name, by.join(", "))
+            }
+            DatabaseError::Exists(name) => write!(f, "{} already exists", name),
+            DatabaseError::UnknownKind(kind) => write!(f, "unknown object kind {}", kind),
+            DatabaseError::Locked(name, who) => write!(f, "{} is locked by {}", name, who),
+            DatabaseError::Missing(object_id) => write!(f, "there is no object {}", object_id),
         }
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct StructMember {
+#[derive(PartialEq, Serialize, Deserialize)]
+pub struct StructMember {
     name: String,
     r#type: TypeRef,
     offset: usize,
 }
 
-#[derive(Serialize, Deserialize)]
-struct EnumValue {
+#[derive(PartialEq, Serialize, Deserialize)]
+pub struct EnumValue {
     name: String,
     value: usize,
 }
 
-#[derive(Serialize, Deserialize)]
-struct UnionMember {
+#[derive(PartialEq, Serialize, Deserialize)]
+pub struct UnionMember {
     name: String,
     r#type: TypeRef,
 }
 
-#[derive(Serialize, Deserialize)]
-enum TypeRef {
+#[derive(PartialEq, Serialize, Deserialize)]
+pub enum TypeRef {
     Int(u16),
     Uint(u16),
     Float(u16),
@@ -65,8 +79,17 @@ enum TypeRef {
     Pointer(u8, usize),
 }
 
-#[derive(Serialize, Deserialize)]
-enum TypeInfo {
+impl TypeRef {
+    fn refers_to(&self, id: usize) -> bool {
+        match self {
+            TypeRef::Value(index) | TypeRef::Pointer(_, index) => *index == id,
+            _ => false,
+        }
+    }
+}
+
+#[derive(PartialEq, Serialize, Deserialize)]
+pub enum TypeInfo {
     Struct(Vec<StructMember>),
     Enum(Vec<EnumValue>),
     Union(Vec<UnionMember>),
@@ -75,8 +98,26 @@ enum TypeInfo {
     Array(TypeRef, usize),
 }
 
-#[derive(Serialize, Deserialize)]
-struct Type {
+impl TypeInfo {
+    fn refers_to(&self, id: usize) -> bool {
+        match self {
+            TypeInfo::Struct(members) => members.iter().any(|m| m.r#type.refers_to(id)),
+            TypeInfo::Enum(_) => false,
+            TypeInfo::Union(members) => members.iter().any(|m| m.r#type.refers_to(id)),
+            TypeInfo::TypeDef(type_ref) => type_ref.refers_to(id),
+            TypeInfo::Function(arg_types, return_type) => {
+                arg_types.iter().any(|t| t.refers_to(id)) || return_type.refers_to(id)
+            }
+            TypeInfo::Array(type_ref, _) => type_ref.refers_to(id),
+        }
+    }
+}
+
+#[derive(PartialEq, Serialize, Deserialize)]
+pub struct Type {
+    // missing from files saved before objects had ids, until `Database::index` makes one up
+    #[serde(default)]
+    pub object_id: ObjectId,
     pub name: String,
     size: usize,
     alignment: usize,
@@ -86,6 +127,7 @@ struct Type {
 impl Default for Type {
     fn default() -> Self {
         Type {
+            object_id: ObjectId::new(),
             name: String::new(),
             size: 0,
             alignment: 0,
@@ -94,16 +136,19 @@ impl Default for Type {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[allow(dead_code)]
+#[derive(PartialEq, Serialize, Deserialize)]
 #[repr(u8)]
-enum Instruction {
+pub enum Instruction {
     Const24,
     Const16,
     Const8,
 }
 
-#[derive(Serialize, Deserialize)]
-struct Function {
+#[derive(PartialEq, Serialize, Deserialize)]
+pub struct Function {
+    #[serde(default)]
+    pub object_id: ObjectId,
     pub name: String,
     code: Vec<Instruction>,
 
@@ -117,6 +162,7 @@ struct Function {
 impl Default for Function {
     fn default() -> Self {
         Function {
+            object_id: ObjectId::new(),
             name: String::new(),
             code: Vec::new(),
 
@@ -129,8 +175,10 @@ impl Default for Function {
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct Data {
+#[derive(PartialEq, Serialize, Deserialize)]
+pub struct Data {
+    #[serde(default)]
+    pub object_id: ObjectId,
     pub name: String,
     location: usize,
     r#type: TypeRef,
@@ -139,6 +187,7 @@ struct Data {
 impl Default for Data {
     fn default() -> Self {
         Data {
+            object_id: ObjectId::new(),
             name: String::new(),
             location: 0,
             r#type: TypeRef::Uint(0),
@@ -146,8 +195,33 @@ impl Default for Data {
     }
 }
 
+#[derive(PartialEq, Serialize, Deserialize)]
+pub enum CommentTarget {
+    Address(usize),
+    Function(usize),
+    // the type by id, like in `TypeRef`, and optionally one of its fields
+    Type(usize, Option<String>),
+}
+
+#[derive(PartialEq, Serialize, Deserialize)]
+pub struct Comment {
+    pub object_id: ObjectId,
+    target: CommentTarget,
+    pub text: String,
+}
+
+impl Default for Comment {
+    fn default() -> Self {
+        Comment {
+            object_id: ObjectId::new(),
+            target: CommentTarget::Address(0),
+            text: String::new(),
+        }
+    }
+}
+
 #[derive(Default, Serialize, Deserialize)]
-struct IdVec<T> {
+pub struct IdVec<T> {
     array: Vec<T>,
     reverse_lookup: Vec<usize>,
 
@@ -191,9 +265,60 @@ impl<T> IdVec<T> {
         self.array.iter_mut()
     }
 
+    // Like `iter_mut`, along with the id of every item
+    pub fn iter_ids_mut(&mut self) -> impl Iterator<Item = (usize, &mut T)> + '_ {
+        self.reverse_lookup
+            .iter()
+            .copied()
+            .zip(self.array.iter_mut())
+    }
+
     pub fn len(&self) -> usize {
         self.array.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.array.is_empty()
+    }
+}
+
+impl<T> Index<usize> for IdVec<T> {
+    type Output = T;
+
+    fn index(&self, id: usize) -> &Self::Output {
+        &self.array[self.lookup[id]]
+    }
+}
+
+impl<T> IndexMut<usize> for IdVec<T> {
+    fn index_mut(&mut self, id: usize) -> &mut Self::Output {
+        &mut self.array[self.lookup[id]]
+    }
+}
+
+// Who holds a lock, see `Database::lock`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Holder {
+    // whoever is using binal itself
+    Local,
+    // a peer of the hub
+    Peer(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Lock {
+    pub holder: Holder,
+    // what to show as holding it, as in `Hello::identity`
+    pub who: String,
+}
+
+// What happened to the objects given to `Database::push_net`, by the ids they were pushed under
+#[derive(Default)]
+pub struct PushResult {
+    // added, or different from what was there before
+    pub changed: Vec<ObjectId>,
+    // left out, along with the reason
+    pub rejected: HashMap<ObjectId, String>,
 }
 
 #[derive(Default, Serialize, Deserialize)]
@@ -201,31 +326,982 @@ pub struct Database {
     pub functions: IdVec<Function>,
     pub types: IdVec<Type>,
     pub data: IdVec<Data>,
+    // missing from files saved before there were comments
+    #[serde(default)]
+    pub comments: IdVec<Comment>,
+
+    // ids of types that turned out to be the same as one we already had, see `push_net`
+    #[serde(default)]
+    aliases: HashMap<ObjectId, ObjectId>,
+
+    // rebuilt by `index` whenever the database is opened
+    #[serde(skip)]
+    function_lookup: HashMap<ObjectId, usize>,
+    #[serde(skip)]
+    type_lookup: HashMap<ObjectId, usize>,
+    #[serde(skip)]
+    data_lookup: HashMap<ObjectId, usize>,
+    #[serde(skip)]
+    comment_lookup: HashMap<ObjectId, usize>,
+    // type references are still by name, so types need to be found that way too
+    #[serde(skip)]
+    type_names: HashMap<String, usize>,
+
+    // only last as long as the peers holding them are connected, so they are never saved
+    #[serde(skip)]
+    locks: HashMap<ObjectId, Lock>,
 }
 
 impl Database {
     pub fn open(path: &Path) -> Result<Self, DatabaseError> {
-        let mut project_file = File::open(&path)?;
+        let mut project_file = File::open(path)?;
         let mut project_data = Vec::<u8>::new();
 
         project_file.read_to_end(&mut project_data)?;
-        let db: Database = serde_json::from_slice(project_data.as_slice())?;
+        let mut db: Database = serde_json::from_slice(project_data.as_slice())?;
+        db.index();
 
         Ok(db)
     }
 
     pub fn save(&self, path: &Path) -> Result<(), DatabaseError> {
-        let mut file;
-
-        if !path.exists() {
-            file = File::create(path)?;
-        } else {
-            file = OpenOptions::new().write(true).open(path)?;
-        }
+        let mut file = File::create(path)?;
 
         let data = serde_json::to_vec(&self)?;
         file.write_all(&data)?;
 
         Ok(())
     }
+
+    // Rebuilds the lookups, giving objects from projects saved before they had ids one
+    fn index(&mut self) {
+        for (index, r#type) in self.types.iter_ids_mut() {
+            if r#type.object_id.is_empty() {
+                r#type.object_id = net::new_type_id();
+            }
+
+            self.type_lookup.insert(r#type.object_id.clone(), index);
+            self.type_names.insert(r#type.name.clone(), index);
+        }
+
+        for (index, function) in self.functions.iter_ids_mut() {
+            if function.object_id.is_empty() {
+                function.object_id = net::function_id(function.location);
+            }
+
+            self.function_lookup
+                .insert(function.object_id.clone(), index);
+        }
+
+        for (index, data) in self.data.iter_ids_mut() {
+            if data.object_id.is_empty() {
+                data.object_id = net::data_id(data.location);
+            }
+
+            self.data_lookup.insert(data.object_id.clone(), index);
+        }
+
+        for (index, comment) in self.comments.iter_ids_mut() {
+            self.comment_lookup.insert(comment.object_id.clone(), index);
+        }
+    }
+
+    // The id an object is kept under, which differs from `id` if that was merged into another
+    pub fn resolve_id(&self, id: &str) -> ObjectId {
+        self.aliases
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    // Name of the object with this id, whatever kind it is
+    pub fn name(&self, object_id: &str) -> Option<&String> {
+        let object_id = self.resolve_id(object_id);
+
+        let function = self.function_lookup.get(&object_id);
+        let data = self.data_lookup.get(&object_id);
+        let r#type = self.type_lookup.get(&object_id);
+        let comment = self.comment_lookup.get(&object_id);
+
+        function
+            .map(|id| &self.functions[*id].name)
+            .or_else(|| data.map(|id| &self.data[*id].name))
+            .or_else(|| r#type.map(|id| &self.types[*id].name))
+            .or_else(|| comment.map(|id| &self.comments[*id].text))
+    }
+
+    // Where a comment is attached, for showing it along with the comment
+    pub fn comment_target(&self, comment: &Comment) -> String {
+        match &comment.target {
+            CommentTarget::Address(address) => format!("{:#x}", address),
+            CommentTarget::Function(location) => {
+                let function = self.function_lookup.get(&net::function_id(*location));
+
+                match function {
+                    Some(id) => self.functions[*id].name.clone(),
+                    None => format!("function at {:#x}", location),
+                }
+            }
+            CommentTarget::Type(id, None) => self.types[*id].name.clone(),
+            CommentTarget::Type(id, Some(field)) => {
+                format!("{}.{}", self.types[*id].name, field)
+            }
+        }
+    }
+
+    pub fn type_id(&self, name: &str) -> Option<&ObjectId> {
+        self.type_names
+            .get(name)
+            .map(|index| &self.types[*index].object_id)
+    }
+
+    fn lift_type_ref(&self, type_ref: &net::TypeRef) -> TypeRef {
+        match type_ref {
+            net::TypeRef::Value { name } => TypeRef::Value(self.type_names[name]),
+            net::TypeRef::Pointer { depth, name } => {
+                TypeRef::Pointer(*depth, self.type_names[name])
+            }
+            net::TypeRef::Uint { size } => TypeRef::Uint(*size),
+            net::TypeRef::Int { size } => TypeRef::Int(*size),
+            net::TypeRef::Float { size } => TypeRef::Float(*size),
+        }
+    }
+
+    fn reserve_object<T: Default>(
+        index_lookup: &mut HashMap<ObjectId, usize>,
+        objects: &mut IdVec<T>,
+        object_id: &ObjectId,
+    ) -> usize {
+        if let Some(index) = index_lookup.get(object_id) {
+            return *index;
+        }
+
+        let index = objects.push(T::default());
+        index_lookup.insert(object_id.clone(), index);
+        index
+    }
+
+    // Adds or updates objects, reporting which ones changed and which were rejected
+    // Like `push_net`, leaving out changes to objects someone other than `holder` has locked
+    pub fn push_net_as(
+        &mut self,
+        mut objects: HashMap<ObjectId, Object>,
+        holder: Holder,
+    ) -> PushResult {
+        let mut locked = HashMap::new();
+
+        for (object_id, obj) in &objects {
+            let mut id = self.resolve_id(object_id);
+
+            // a type pushed under an id we do not know yet replaces the one of the same name
+            if let Object::Type { name, .. } = obj {
+                if !self.type_lookup.contains_key(&id) {
+                    id = self.type_id(name).cloned().unwrap_or(id);
+                }
+            }
+
+            let Err(e) = self.check_lock(&id, holder) else {
+                continue;
+            };
+
+            // tools echo back what they were sent, which is no change and so not a conflict
+            let current = self.object_get_net(&id).remove(&id);
+
+            if current.is_none_or(|current| current.content_hash() != obj.content_hash()) {
+                locked.insert(object_id.clone(), e.to_string());
+            }
+        }
+
+        objects.retain(|object_id, _| !locked.contains_key(object_id));
+
+        let mut result = self.push_net(objects);
+        result.rejected.extend(locked);
+        result
+    }
+
+    pub fn push_net(&mut self, objects: HashMap<ObjectId, Object>) -> PushResult {
+        let mut result = PushResult::default();
+        let rejected = &mut result.rejected;
+
+        // find the id each object is kept under. Two tools that each made up an id for the same
+        // new type would otherwise end up with two types of that name, so a type we have not
+        // seen before but whose name we already know becomes an alias of the one we have
+        let mut canonical = HashMap::new();
+        let mut taken = HashSet::new();
+        let mut type_ids = HashMap::new();
+
+        for (object_id, obj) in &objects {
+            let mut id = self.resolve_id(object_id);
+
+            if let Object::Type { name, .. } = obj {
+                if !self.type_lookup.contains_key(&id) {
+                    if let Some(existing) = self.type_id(name) {
+                        id = existing.clone();
+                    }
+                }
+
+                if let Some(index) = self.type_names.get(name) {
+                    if self.types[*index].object_id != id {
+                        rejected.insert(object_id.clone(), format!("{} already exists", name));
+                        continue;
+                    }
+                }
+
+                if let Some(other) = type_ids.insert(name, id.clone()) {
+                    if other != id {
+                        rejected.insert(object_id.clone(), format!("{} was pushed twice", name));
+                        continue;
+                    }
+                }
+            }
+
+            if !taken.insert(id.clone()) {
+                rejected.insert(
+                    object_id.clone(),
+                    format!("{} was pushed twice", obj.name()),
+                );
+                continue;
+            }
+
+            canonical.insert(object_id.clone(), id);
+        }
+
+        // reject objects that refer to types we will not have, repeating until nothing changes
+        // since rejecting a type can leave objects that refer to it dangling as well. Names are
+        // checked as they will be after the push, so a type renamed by it is gone by its old name
+        loop {
+            let mut changed = false;
+            let mut pushed: HashSet<&String> = HashSet::new();
+            let mut renamed: HashSet<&String> = HashSet::new();
+
+            for (object_id, obj) in &objects {
+                let Object::Type { name, .. } = obj else {
+                    continue;
+                };
+
+                if rejected.contains_key(object_id) {
+                    continue;
+                }
+
+                pushed.insert(name);
+
+                if let Some(index) = self.type_lookup.get(&canonical[object_id]) {
+                    if &self.types[*index].name != name {
+                        renamed.insert(&self.types[*index].name);
+                    }
+                }
+            }
+
+            for (object_id, obj) in &objects {
+                if rejected.contains_key(object_id) {
+                    continue;
+                }
+
+                let missing = obj.dependencies().into_iter().find(|dependency| {
+                    !pushed.contains(dependency)
+                        && (renamed.contains(dependency)
+                            || !self.type_names.contains_key(*dependency))
+                });
+
+                if let Some(missing) = missing {
+                    rejected.insert(object_id.clone(), format!("unknown type {}", missing));
+                    changed = true;
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        // we need to create stubs for each object to support circular dependencies
+        let mut objects: Vec<_> = objects
+            .into_iter()
+            .filter(|(object_id, _)| !result.rejected.contains_key(object_id))
+            .map(|(object_id, obj)| {
+                let id = canonical.remove(&object_id).unwrap();
+                (object_id, id, obj)
+            })
+            .collect();
+
+        for (object_id, id, obj) in &objects {
+            match obj {
+                Object::Type { name, .. } => {
+                    let index = Self::reserve_object(&mut self.type_lookup, &mut self.types, id);
+
+                    // a type can be renamed by pushing it under its new name
+                    let old = &self.types[index].name;
+                    if self.type_names.get(old) == Some(&index) {
+                        self.type_names.remove(old);
+                    }
+
+                    self.type_names.insert(name.clone(), index);
+                }
+                Object::Function { .. } => {
+                    Self::reserve_object(&mut self.function_lookup, &mut self.functions, id);
+                }
+                Object::Data { .. } => {
+                    Self::reserve_object(&mut self.data_lookup, &mut self.data, id);
+                }
+                Object::Comment { .. } => {
+                    Self::reserve_object(&mut self.comment_lookup, &mut self.comments, id);
+                }
+            }
+
+            if object_id != id {
+                self.aliases.insert(object_id.clone(), id.clone());
+            }
+        }
+
+        // now fill out each object
+        for (object_id, id, obj) in objects.drain(..) {
+            match obj {
+                Object::Type {
+                    name,
+                    info,
+                    size,
+                    alignment,
+                } => {
+                    let index = self.type_lookup[&id];
+
+                    let info = match info {
+                        net::TypeInfo::Typedef { r#type } => {
+                            TypeInfo::TypeDef(self.lift_type_ref(&r#type))
+                        }
+                        net::TypeInfo::Function { arg_types, r#type } => TypeInfo::Function(
+                            arg_types.iter().map(|t| self.lift_type_ref(t)).collect(),
+                            self.lift_type_ref(&r#type),
+                        ),
+                        net::TypeInfo::Struct { fields } => TypeInfo::Struct(
+                            fields
+                                .into_iter()
+                                .map(|f| StructMember {
+                                    r#type: self.lift_type_ref(&f.r#type),
+                                    name: f.name,
+                                    offset: f.offset,
+                                })
+                                .collect(),
+                        ),
+                        net::TypeInfo::Enum { values } => TypeInfo::Enum(
+                            values
+                                .into_iter()
+                                .map(|v| EnumValue {
+                                    name: v.name,
+                                    value: v.value,
+                                })
+                                .collect(),
+                        ),
+                        net::TypeInfo::Array { r#type, count } => {
+                            TypeInfo::Array(self.lift_type_ref(&r#type), count)
+                        }
+                        net::TypeInfo::Union { fields } => TypeInfo::Union(
+                            fields
+                                .into_iter()
+                                .map(|f| UnionMember {
+                                    r#type: self.lift_type_ref(&f.r#type),
+                                    name: f.name,
+                                })
+                                .collect(),
+                        ),
+                    };
+
+                    let r#type = Type {
+                        object_id: id,
+                        name,
+                        size,
+                        alignment,
+                        info,
+                    };
+
+                    if self.types[index] != r#type {
+                        self.types[index] = r#type;
+                        result.changed.push(object_id);
+                    }
+                }
+                Object::Function {
+                    name,
+                    arguments,
+                    return_type: r#type,
+                    location,
+                } => {
+                    let index = self.function_lookup[&id];
+
+                    let return_type = self.lift_type_ref(&r#type);
+                    let argument_types = arguments
+                        .iter()
+                        .map(|t| self.lift_type_ref(&t.r#type))
+                        .collect();
+
+                    let function = Function {
+                        object_id: id,
+                        name,
+                        code: std::mem::take(&mut self.functions[index].code),
+                        location,
+                        return_type,
+                        argument_names: arguments.into_iter().map(|t| t.name).collect(),
+                        argument_types,
+                    };
+
+                    if self.functions[index] != function {
+                        result.changed.push(object_id);
+                    }
+
+                    self.functions[index] = function;
+                }
+                Object::Data {
+                    name,
+                    r#type,
+                    location,
+                } => {
+                    let index = self.data_lookup[&id];
+
+                    let r#type = self.lift_type_ref(&r#type);
+
+                    let data = Data {
+                        object_id: id,
+                        name,
+                        location,
+                        r#type,
+                    };
+
+                    if self.data[index] != data {
+                        self.data[index] = data;
+                        result.changed.push(object_id);
+                    }
+                }
+                Object::Comment { target, text } => {
+                    let index = self.comment_lookup[&id];
+
+                    let target = match target {
+                        net::CommentTarget::Address { address } => CommentTarget::Address(address),
+                        net::CommentTarget::Function { location } => {
+                            CommentTarget::Function(location)
+                        }
+                        net::CommentTarget::Type { name, field } => {
+                            CommentTarget::Type(self.type_names[&name], field)
+                        }
+                    };
+
+                    let comment = Comment {
+                        object_id: id,
+                        target,
+                        text,
+                    };
+
+                    if self.comments[index] != comment {
+                        self.comments[index] = comment;
+                        result.changed.push(object_id);
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    // Converts a type reference back into its network form, recording the type it points to
+    // so that the caller can send it along with the referencing object
+    fn lower_type_ref(&self, type_ref: &TypeRef, dependencies: &mut Vec<usize>) -> net::TypeRef {
+        match type_ref {
+            TypeRef::Value(id) => {
+                dependencies.push(*id);
+                net::TypeRef::Value {
+                    name: self.types[*id].name.clone(),
+                }
+            }
+            TypeRef::Pointer(depth, id) => {
+                dependencies.push(*id);
+                net::TypeRef::Pointer {
+                    depth: *depth,
+                    name: self.types[*id].name.clone(),
+                }
+            }
+            TypeRef::Uint(size) => net::TypeRef::Uint { size: *size },
+            TypeRef::Int(size) => net::TypeRef::Int { size: *size },
+            TypeRef::Float(size) => net::TypeRef::Float { size: *size },
+        }
+    }
+
+    fn lower_type(&self, id: usize, dependencies: &mut Vec<usize>) -> Object {
+        let r#type = &self.types[id];
+
+        let info = match &r#type.info {
+            TypeInfo::Struct(members) => net::TypeInfo::Struct {
+                fields: members
+                    .iter()
+                    .map(|m| net::StructField {
+                        name: m.name.clone(),
+                        offset: m.offset,
+                        r#type: self.lower_type_ref(&m.r#type, dependencies),
+                    })
+                    .collect(),
+            },
+            TypeInfo::Enum(values) => net::TypeInfo::Enum {
+                values: values
+                    .iter()
+                    .map(|v| net::EnumValue {
+                        name: v.name.clone(),
+                        value: v.value,
+                    })
+                    .collect(),
+            },
+            TypeInfo::Union(members) => net::TypeInfo::Union {
+                fields: members
+                    .iter()
+                    .map(|m| net::UnionField {
+                        name: m.name.clone(),
+                        r#type: self.lower_type_ref(&m.r#type, dependencies),
+                    })
+                    .collect(),
+            },
+            TypeInfo::TypeDef(type_ref) => net::TypeInfo::Typedef {
+                r#type: self.lower_type_ref(type_ref, dependencies),
+            },
+            TypeInfo::Function(arg_types, return_type) => net::TypeInfo::Function {
+                arg_types: arg_types
+                    .iter()
+                    .map(|t| self.lower_type_ref(t, dependencies))
+                    .collect(),
+                r#type: self.lower_type_ref(return_type, dependencies),
+            },
+            TypeInfo::Array(type_ref, count) => net::TypeInfo::Array {
+                r#type: self.lower_type_ref(type_ref, dependencies),
+                count: *count,
+            },
+        };
+
+        Object::Type {
+            name: r#type.name.clone(),
+            size: r#type.size,
+            alignment: r#type.alignment,
+            info,
+        }
+    }
+
+    fn lower_types(&self, mut to_lower: Vec<usize>, map: &mut HashMap<ObjectId, Object>) {
+        while let Some(id) = to_lower.pop() {
+            let object_id = &self.types[id].object_id;
+
+            if map.contains_key(object_id) {
+                continue;
+            }
+
+            let object = self.lower_type(id, &mut to_lower);
+            map.insert(object_id.clone(), object);
+        }
+    }
+
+    // Get a type and every type it depends on in network form
+    pub fn types_get_net(&self, object_id: &str) -> HashMap<ObjectId, Object> {
+        let mut map = HashMap::new();
+
+        if let Some(id) = self.type_lookup.get(&self.resolve_id(object_id)) {
+            self.lower_types(vec![*id], &mut map);
+        }
+
+        map
+    }
+
+    // Get a function and every type it depends on in network form
+    pub fn functions_get_net(&self, object_id: &str) -> HashMap<ObjectId, Object> {
+        let mut map = HashMap::new();
+
+        let Some(id) = self.function_lookup.get(&self.resolve_id(object_id)) else {
+            return map;
+        };
+
+        let function = &self.functions[*id];
+        let mut dependencies = Vec::new();
+
+        let object = Object::Function {
+            name: function.name.clone(),
+            location: function.location,
+            arguments: function
+                .argument_names
+                .iter()
+                .zip(&function.argument_types)
+                .map(|(name, r#type)| net::Argument {
+                    name: name.clone(),
+                    r#type: self.lower_type_ref(r#type, &mut dependencies),
+                })
+                .collect(),
+            return_type: self.lower_type_ref(&function.return_type, &mut dependencies),
+        };
+
+        self.lower_types(dependencies, &mut map);
+        map.insert(function.object_id.clone(), object);
+
+        map
+    }
+
+    // Get a global and every type it depends on in network form
+    pub fn globals_get_net(&self, object_id: &str) -> HashMap<ObjectId, Object> {
+        let mut map = HashMap::new();
+
+        let Some(id) = self.data_lookup.get(&self.resolve_id(object_id)) else {
+            return map;
+        };
+
+        let data = &self.data[*id];
+        let mut dependencies = Vec::new();
+
+        let object = Object::Data {
+            name: data.name.clone(),
+            location: data.location,
+            r#type: self.lower_type_ref(&data.r#type, &mut dependencies),
+        };
+
+        self.lower_types(dependencies, &mut map);
+        map.insert(data.object_id.clone(), object);
+
+        map
+    }
+
+    // Get a comment and the type it is attached to, if any, in network form
+    pub fn comments_get_net(&self, object_id: &str) -> HashMap<ObjectId, Object> {
+        let mut map = HashMap::new();
+
+        let Some(id) = self.comment_lookup.get(&self.resolve_id(object_id)) else {
+            return map;
+        };
+
+        let comment = &self.comments[*id];
+
+        let target = match &comment.target {
+            CommentTarget::Address(address) => net::CommentTarget::Address { address: *address },
+            CommentTarget::Function(location) => net::CommentTarget::Function {
+                location: *location,
+            },
+            CommentTarget::Type(id, field) => {
+                self.lower_types(vec![*id], &mut map);
+
+                net::CommentTarget::Type {
+                    name: self.types[*id].name.clone(),
+                    field: field.clone(),
+                }
+            }
+        };
+
+        let object = Object::Comment {
+            target,
+            text: comment.text.clone(),
+        };
+
+        map.insert(comment.object_id.clone(), object);
+
+        map
+    }
+
+    // Get an object of any kind and every type it depends on in network form
+    pub fn object_get_net(&self, object_id: &str) -> HashMap<ObjectId, Object> {
+        let id = self.resolve_id(object_id);
+
+        if self.type_lookup.contains_key(&id) {
+            self.types_get_net(&id)
+        } else if self.function_lookup.contains_key(&id) {
+            self.functions_get_net(&id)
+        } else if self.comment_lookup.contains_key(&id) {
+            self.comments_get_net(&id)
+        } else {
+            self.globals_get_net(&id)
+        }
+    }
+
+    // Get every object in network form
+    pub fn get_net(&self) -> HashMap<ObjectId, Object> {
+        let mut map = HashMap::new();
+
+        for r#type in self.types.iter() {
+            map.extend(self.types_get_net(&r#type.object_id));
+        }
+
+        for function in self.functions.iter() {
+            map.extend(self.functions_get_net(&function.object_id));
+        }
+
+        for data in self.data.iter() {
+            map.extend(self.globals_get_net(&data.object_id));
+        }
+
+        for comment in self.comments.iter() {
+            map.extend(self.comments_get_net(&comment.object_id));
+        }
+
+        map
+    }
+
+    // Get every function located in `start..end` and the types they depend on in network form
+    pub fn functions_in_range_get_net(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> HashMap<ObjectId, Object> {
+        let mut map = HashMap::new();
+
+        for function in self.functions.iter() {
+            if (start..end).contains(&function.location) {
+                map.extend(self.functions_get_net(&function.object_id));
+            }
+        }
+
+        map
+    }
+
+    // Names of every other object that holds a reference to the type with this id
+    fn type_referenced_by(&self, id: usize) -> Vec<String> {
+        let object_id = &self.types[id].object_id;
+
+        let types = self
+            .types
+            .iter()
+            .filter(|t| &t.object_id != object_id && t.info.refers_to(id))
+            .map(|t| t.name.clone());
+
+        let functions = self
+            .functions
+            .iter()
+            .filter(|f| {
+                f.return_type.refers_to(id) || f.argument_types.iter().any(|t| t.refers_to(id))
+            })
+            .map(|f| f.name.clone());
+
+        let data = self
+            .data
+            .iter()
+            .filter(|d| d.r#type.refers_to(id))
+            .map(|d| d.name.clone());
+
+        let comments = self
+            .comments
+            .iter()
+            .filter(|c| matches!(c.target, CommentTarget::Type(type_id, _) if type_id == id))
+            .map(|c| format!("the comment on {}", self.comment_target(c)));
+
+        types.chain(functions).chain(data).chain(comments).collect()
+    }
+
+    // Deleting a type that is still in use is refused, since it would leave the
+    // referencing objects pointing at a hole in `types`
+    pub fn delete_type(&mut self, object_id: &str) -> Result<bool, DatabaseError> {
+        let object_id = self.resolve_id(object_id);
+
+        let Some(id) = self.type_lookup.get(&object_id).copied() else {
+            return Ok(false);
+        };
+
+        let name = self.types[id].name.clone();
+        let referenced_by = self.type_referenced_by(id);
+
+        if !referenced_by.is_empty() {
+            return Err(DatabaseError::Referenced(name, referenced_by));
+        }
+
+        self.type_lookup.remove(&object_id);
+        if self.type_names.get(&name) == Some(&id) {
+            self.type_names.remove(&name);
+        }
+        self.types.delete(id);
+
+        Ok(true)
+    }
+
+    pub fn delete_function(&mut self, object_id: &str) -> bool {
+        let Some(id) = self.function_lookup.remove(&self.resolve_id(object_id)) else {
+            return false;
+        };
+
+        self.functions.delete(id);
+        true
+    }
+
+    pub fn delete_data(&mut self, object_id: &str) -> bool {
+        let Some(id) = self.data_lookup.remove(&self.resolve_id(object_id)) else {
+            return false;
+        };
+
+        self.data.delete(id);
+        true
+    }
+
+    pub fn delete_comment(&mut self, object_id: &str) -> bool {
+        let Some(id) = self.comment_lookup.remove(&self.resolve_id(object_id)) else {
+            return false;
+        };
+
+        self.comments.delete(id);
+        true
+    }
+
+    // Functions and data are told apart by address rather than name, so this picks the first
+    // one with the old name
+    fn rename_object<T>(
+        objects: &mut IdVec<T>,
+        fields: fn(&mut T) -> (&ObjectId, &mut String),
+        old: &str,
+        new: &str,
+    ) -> Option<ObjectId> {
+        objects.iter_mut().find_map(|object| {
+            let (object_id, name) = fields(object);
+
+            (name == old).then(|| {
+                *name = new.to_string();
+                object_id.clone()
+            })
+        })
+    }
+
+    // Renames an object of the given kind in place. Its id stays the same, so references to
+    // it stay valid. Returns the id of the renamed object, if there was one
+    pub fn rename(
+        &mut self,
+        kind: &str,
+        old: &str,
+        new: &str,
+    ) -> Result<Option<ObjectId>, DatabaseError> {
+        if old == new {
+            return Ok(None);
+        }
+
+        match kind {
+            "type" => {
+                if self.type_names.contains_key(new) {
+                    return Err(DatabaseError::Exists(new.to_string()));
+                }
+
+                let Some(index) = self.type_names.remove(old) else {
+                    return Ok(None);
+                };
+
+                self.type_names.insert(new.to_string(), index);
+                self.types[index].name = new.to_string();
+
+                Ok(Some(self.types[index].object_id.clone()))
+            }
+            "function" => Ok(Self::rename_object(
+                &mut self.functions,
+                |f| (&f.object_id, &mut f.name),
+                old,
+                new,
+            )),
+            "data" => Ok(Self::rename_object(
+                &mut self.data,
+                |d| (&d.object_id, &mut d.name),
+                old,
+                new,
+            )),
+            _ => Err(DatabaseError::UnknownKind(kind.to_string())),
+        }
+    }
+
+    // Like `rename`, unless someone other than `holder` has the object locked
+    pub fn rename_as(
+        &mut self,
+        kind: &str,
+        old: &str,
+        new: &str,
+        holder: Holder,
+    ) -> Result<Option<ObjectId>, DatabaseError> {
+        if let Some(object_id) = self.find(kind, old) {
+            self.check_lock(&object_id, holder)?;
+        }
+
+        self.rename(kind, old, new)
+    }
+
+    // Delete the object with this id regardless of its kind. Returns whether anything was removed
+    pub fn delete(&mut self, object_id: &str) -> Result<bool, DatabaseError> {
+        let function = self.delete_function(object_id);
+        let data = self.delete_data(object_id);
+        let comment = self.delete_comment(object_id);
+        let r#type = self.delete_type(object_id)?;
+
+        if function || data || comment || r#type {
+            self.locks.remove(&self.resolve_id(object_id));
+        }
+
+        Ok(function || data || comment || r#type)
+    }
+
+    // Claims an object for `holder`, so nobody else can change it until it is unlocked. Returns
+    // whether it was not already held by `holder`
+    pub fn lock(
+        &mut self,
+        object_id: &str,
+        holder: Holder,
+        who: String,
+    ) -> Result<bool, DatabaseError> {
+        let object_id = self.resolve_id(object_id);
+
+        if self.name(&object_id).is_none() {
+            return Err(DatabaseError::Missing(object_id));
+        }
+
+        self.check_lock(&object_id, holder)?;
+
+        let lock = Lock { holder, who };
+        Ok(self.locks.insert(object_id, lock).is_none())
+    }
+
+    // Gives up a lock held by `holder`. Returns whether there was one
+    pub fn unlock(&mut self, object_id: &str, holder: Holder) -> Result<bool, DatabaseError> {
+        let object_id = self.resolve_id(object_id);
+
+        self.check_lock(&object_id, holder)?;
+        Ok(self.locks.remove(&object_id).is_some())
+    }
+
+    // Gives up every lock held by `holder`, returning the ids of the objects that were locked
+    pub fn release(&mut self, holder: Holder) -> Vec<ObjectId> {
+        let released: Vec<ObjectId> = self
+            .locks
+            .iter()
+            .filter(|(_, lock)| lock.holder == holder)
+            .map(|(object_id, _)| object_id.clone())
+            .collect();
+
+        for object_id in &released {
+            self.locks.remove(object_id);
+        }
+
+        released
+    }
+
+    pub fn lock_of(&self, object_id: &str) -> Option<&Lock> {
+        self.locks.get(&self.resolve_id(object_id))
+    }
+
+    pub fn locks(&self) -> impl Iterator<Item = (&ObjectId, &Lock)> + '_ {
+        self.locks.iter()
+    }
+
+    // Fails if someone other than `holder` has the object locked
+    pub fn check_lock(&self, object_id: &str, holder: Holder) -> Result<(), DatabaseError> {
+        match self.lock_of(object_id) {
+            Some(lock) if lock.holder != holder => {
+                let name = self.name(object_id).cloned();
+                let name = name.unwrap_or_else(|| object_id.to_string());
+
+                Err(DatabaseError::Locked(name, lock.who.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The id of the object `rename` would rename
+    pub fn find(&self, kind: &str, name: &str) -> Option<ObjectId> {
+        match kind {
+            "type" => self.type_id(name).cloned(),
+            "function" => self
+                .functions
+                .iter()
+                .find(|f| f.name == name)
+                .map(|f| f.object_id.clone()),
+            "data" => self
+                .data
+                .iter()
+                .find(|d| d.name == name)
+                .map(|d| d.object_id.clone()),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,11 @@
+pub mod ir;
+pub mod mock;
+pub mod net;
+pub mod project;
+pub mod record;
+pub mod schema;
+pub mod tls;
+pub mod transport;
+
+#[cfg(feature = "gui")]
+pub mod ui;
@@ -1,40 +1,30 @@
-mod ir;
-mod net;
-mod project;
-
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
 
 use eframe::egui::{self, CentralPanel, Context, TopBottomPanel, ViewportBuilder, Window};
 
-use net::Object;
-use project::{OpenProjectMenu, Project};
+use binal::{
+    net::{Object, ObjectId},
+    project::Project,
+    ui::OpenProjectMenu,
+};
+
+const NETWORK_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
+#[derive(Default)]
 struct App {
     projects: Vec<Project>,
     current: usize,
 
     errors: VecDeque<String>,
-    clipboard: HashMap<String, Object>,
+    clipboard: HashMap<ObjectId, Object>,
 
     should_open: bool,
     open_project: OpenProjectMenu,
 }
 
-impl Default for App {
-    fn default() -> Self {
-        Self {
-            projects: Vec::new(),
-            current: 0,
-
-            errors: VecDeque::new(),
-            clipboard: HashMap::new(),
-
-            should_open: false,
-            open_project: OpenProjectMenu::default(),
-        }
-    }
-}
-
 impl eframe::App for App {
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
         TopBottomPanel::top("top_panel").show(ctx, |ui| {
@@ -103,9 +93,25 @@ impl eframe::App for App {
         }
 
         for project in &mut self.projects {
-            project.handle_network_updates()
+            project.handle_network_updates(&mut self.errors)
         }
 
+        // pass cursor moves on to the other projects, which only act on them while following
+        for i in 0..self.projects.len() {
+            let Some(address) = self.projects[i].take_moved() else {
+                continue;
+            };
+
+            for (j, project) in self.projects.iter_mut().enumerate() {
+                if j != i {
+                    project.goto(address);
+                }
+            }
+        }
+
+        // keep polling the network even while there is no input
+        ctx.request_repaint_after(NETWORK_POLL_INTERVAL);
+
         CentralPanel::default().show(ctx, |ui| {
             self.projects[self.current].render(ui, &mut self.errors, &mut self.clipboard)
         });
@@ -115,6 +121,12 @@ impl eframe::App for App {
 fn main() -> eframe::Result {
     env_logger::init();
 
+    // `binal schema` prints the JSON Schema of the protocol for plugin authors instead of opening
+    if std::env::args().nth(1).as_deref() == Some("schema") {
+        println!("{:#}", binal::schema::schema());
+        return Ok(());
+    }
+
     let app = App::default();
 
     let native_options = eframe::NativeOptions {
@@ -0,0 +1,698 @@
+// A stand-in for a decompiler plugin, speaking the plugin side of the protocol the way
+// `script/binaryninja.py` does over a made up program, so binal's syncing can be exercised
+// end-to-end without any decompiler around
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    net::{
+        self, CommentTarget, Filter, HashIndex, Hello, Message, Object, ObjectId, Query,
+        PROTOCOL_VERSION,
+    },
+    transport::{Stream, Transport},
+};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+// the initial push is split into pushes of this many objects, as the plugin does
+const INIT_SYNC_BATCH: usize = 500;
+
+// The made up program, which is nothing but the objects the plugin would lift out of a real one
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Program {
+    pub objects: HashMap<ObjectId, Object>,
+}
+
+impl Program {
+    pub fn find(&self, kind: &str, name: &str) -> Option<ObjectId> {
+        self.objects
+            .iter()
+            .find(|(_, object)| object.kind() == kind && object.name() == name)
+            .map(|(object_id, _)| object_id.clone())
+    }
+
+    // The object along with every type it refers to, the way the plugin pushes them
+    fn lift(&self, object_id: &str) -> HashMap<ObjectId, Object> {
+        let mut lifted = HashMap::new();
+        let mut to_lift = vec![object_id.to_string()];
+
+        while let Some(object_id) = to_lift.pop() {
+            if lifted.contains_key(&object_id) {
+                continue;
+            }
+
+            let Some(object) = self.objects.get(&object_id) else {
+                continue;
+            };
+
+            for name in object.dependencies() {
+                to_lift.extend(self.find("type", name));
+            }
+
+            lifted.insert(object_id, object.clone());
+        }
+
+        lifted
+    }
+
+    // Adds what binal pushed, returning whatever could not be along with why
+    fn lower(&mut self, objects: HashMap<ObjectId, Object>) -> HashMap<ObjectId, String> {
+        let mut rejected = HashMap::new();
+
+        for (object_id, object) in objects {
+            // like Binary Ninja, which has nowhere to put them
+            if let Object::Comment {
+                target: CommentTarget::Type { .. },
+                ..
+            } = &object
+            {
+                rejected.insert(object_id, String::from("the mock has no comments on types"));
+                continue;
+            }
+
+            self.objects.insert(object_id, object);
+        }
+
+        rejected
+    }
+
+    // Renaming a type renames every reference to it as well, as decompilers do
+    fn rename(&mut self, kind: &str, old: &str, new: &str) {
+        let Some(object_id) = self.find(kind, old) else {
+            return;
+        };
+
+        if let Some(
+            Object::Type { name, .. } | Object::Function { name, .. } | Object::Data { name, .. },
+        ) = self.objects.get_mut(&object_id)
+        {
+            *name = new.to_string();
+        }
+
+        if kind != "type" {
+            return;
+        }
+
+        for object in self.objects.values_mut() {
+            for name in object.dependencies_mut() {
+                if name == old {
+                    *name = new.to_string();
+                }
+            }
+        }
+    }
+}
+
+// One connection to binal, as the plugin keeps a `DecompilerHandler` for each
+struct Session {
+    id: usize,
+    stream: Box<dyn Stream>,
+    // what binal announced, which decides what it is sent
+    binal: Hello,
+    // whether this connected to a hub, which never pulls, rather than binal connecting here
+    hub: bool,
+
+    // every object pushed over this connection along with the revision it was pushed at, so
+    // binal can pull whatever changed since a revision it already has
+    revision: u64,
+    changes: HashMap<ObjectId, (u64, Object)>,
+    // content hashes from the last summary binal asked for
+    hashes: HashIndex,
+}
+
+impl Session {
+    fn supports(&self, kind: &str) -> bool {
+        self.binal.messages.iter().any(|m| m == kind)
+    }
+
+    fn send(&mut self, message: &Message) {
+        if let Err(e) = write_line(&mut self.stream, message) {
+            log::error!("Mock could not send {} message: {}", message.kind(), e);
+        }
+    }
+
+    fn push(&mut self, objects: HashMap<ObjectId, Object>) {
+        self.revision += 1;
+
+        for (object_id, object) in &objects {
+            self.changes
+                .insert(object_id.clone(), (self.revision, object.clone()));
+        }
+
+        self.send(&Message::Push {
+            id: None,
+            objects,
+            author: None,
+        });
+    }
+
+    // Everything the program has, in transactions if binal supports them, unless binal compares
+    // summaries instead
+    fn initial_push(&mut self, program: &Program) {
+        if self.supports("summary") && !self.hub {
+            return;
+        }
+
+        let transactions = self.supports("begin");
+
+        if transactions {
+            self.send(&Message::Begin { id: 0 });
+        }
+
+        // types first, so whatever refers to them can be resolved as it arrives
+        for kind in ["type", "function", "data", "comment"] {
+            let objects: Vec<(&ObjectId, &Object)> = program
+                .objects
+                .iter()
+                .filter(|(_, object)| object.kind() == kind)
+                .collect();
+
+            for batch in objects.chunks(INIT_SYNC_BATCH) {
+                let objects = batch
+                    .iter()
+                    .map(|(object_id, object)| ((*object_id).clone(), (*object).clone()))
+                    .collect();
+
+                self.send(&Message::Push {
+                    id: None,
+                    objects,
+                    author: None,
+                });
+            }
+        }
+
+        if transactions {
+            self.send(&Message::Commit { id: 0 });
+        }
+    }
+
+    fn answer(&mut self, program: &Program, id: u64, query: Query) {
+        // nothing having changed is an answer too
+        let changed = matches!(query, Query::Changed { .. });
+
+        let objects: HashMap<ObjectId, Object> = match query {
+            Query::Type { name } => program
+                .find("type", &name)
+                .map(|object_id| program.lift(&object_id))
+                .unwrap_or_default(),
+            Query::Functions { start, end } => program
+                .objects
+                .iter()
+                .filter(|(_, object)| match object {
+                    Object::Function { location, .. } => (start..end).contains(location),
+                    _ => false,
+                })
+                .flat_map(|(object_id, _)| program.lift(object_id))
+                .collect(),
+            Query::Changed { since } => self
+                .changes
+                .iter()
+                .filter(|(_, (revision, _))| *revision > since)
+                .map(|(object_id, (_, object))| (object_id.clone(), object.clone()))
+                .collect(),
+            Query::Summary => {
+                // kept for the hashes queries that follow, so they describe the same objects
+                self.hashes = HashIndex::new(&program.objects);
+
+                let buckets = self.hashes.summary();
+                return self.send(&Message::Summary { id, buckets });
+            }
+            Query::Hashes { object, start } => {
+                let hashes = self.hashes.bucket(&object, start).cloned();
+                let hashes = hashes.unwrap_or_default().into_iter().collect();
+
+                return self.send(&Message::Hashes { id, hashes });
+            }
+            Query::Objects { ids } => ids
+                .iter()
+                .flat_map(|object_id| program.lift(object_id))
+                .collect(),
+        };
+
+        if objects.is_empty() && !changed {
+            return self.send(&Message::Error {
+                id: Some(id),
+                message: String::from("no matching objects"),
+            });
+        }
+
+        // binal relies on the pushes made before the pull arriving before the response
+        let revision = self.revision;
+        self.send(&Message::Response {
+            id,
+            revision,
+            objects,
+        });
+    }
+}
+
+#[derive(Default)]
+struct State {
+    program: Program,
+    sessions: Vec<Session>,
+    next_session: usize,
+    // who holds the objects binal said are locked, by object id
+    locks: HashMap<ObjectId, Option<String>>,
+    // every message binal sent, oldest first
+    received: Vec<Message>,
+}
+
+// Waits for binal to connect, or connects to a binal hub, and keeps the program in sync with it.
+// Changes to the program are made through the methods below, which send the same messages the
+// plugin sends when Binary Ninja reports them
+pub struct MockPeer {
+    state: Arc<Mutex<State>>,
+    running: Arc<AtomicBool>,
+}
+
+impl MockPeer {
+    // Accepts any number of connections from binal, as the plugin does
+    pub fn listen(
+        transport: &dyn Transport,
+        program: Program,
+        token: Option<String>,
+    ) -> io::Result<Self> {
+        let listener = transport.listen()?;
+        let peer = Self::new(program);
+
+        let state = peer.state.clone();
+        let running = peer.running.clone();
+
+        std::thread::spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok(Some((stream, addr))) => {
+                        log::info!("Mock accepted connection from {}", addr);
+
+                        let state = state.clone();
+                        let token = token.clone();
+
+                        std::thread::spawn(move || {
+                            if let Err(e) = serve(&state, stream, token.as_deref()) {
+                                log::error!("Mock connection from {} failed: {}", addr, e);
+                            }
+                        });
+                    }
+                    Ok(None) => std::thread::sleep(ACCEPT_POLL_INTERVAL),
+                    Err(e) => {
+                        log::error!("Mock could not accept a connection: {}", e);
+                        std::thread::sleep(ACCEPT_POLL_INTERVAL)
+                    }
+                }
+            }
+        });
+
+        Ok(peer)
+    }
+
+    // Connects to a hub once, returning after the handshake and initial push
+    pub fn connect(
+        transport: &dyn Transport,
+        program: Program,
+        token: Option<String>,
+    ) -> io::Result<Self> {
+        let peer = Self::new(program);
+        let stream = transport.connect()?;
+        let (id, reader) = start(&peer.state, stream, token.as_deref(), true)?;
+
+        let state = peer.state.clone();
+        std::thread::spawn(move || read(&state, id, reader));
+
+        Ok(peer)
+    }
+
+    fn new(program: Program) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                program,
+                ..State::default()
+            })),
+            running: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    // Adds or updates an object, pushing it along with the types it refers to. A function or
+    // data that got a new name is also reported as renamed
+    pub fn define(&self, object_id: ObjectId, object: Object) {
+        let mut state = self.state.lock().unwrap();
+        let State {
+            program, sessions, ..
+        } = &mut *state;
+
+        let renamed = match program.objects.get(&object_id) {
+            Some(old @ (Object::Function { .. } | Object::Data { .. }))
+                if old.name() != object.name() =>
+            {
+                Some(Message::Rename {
+                    kind: object.kind().to_string(),
+                    old: old.name().clone(),
+                    new: object.name().clone(),
+                    author: None,
+                })
+            }
+            _ => None,
+        };
+
+        program.objects.insert(object_id.clone(), object);
+        let objects = program.lift(&object_id);
+
+        for session in sessions {
+            if let Some(rename) = renamed.as_ref().filter(|_| session.supports("rename")) {
+                session.send(rename);
+            }
+
+            session.push(objects.clone());
+        }
+    }
+
+    pub fn remove(&self, object_id: &str) {
+        let mut state = self.state.lock().unwrap();
+
+        state.program.objects.remove(object_id);
+
+        for session in &mut state.sessions {
+            session.changes.remove(object_id);
+            session.send(&Message::Delete {
+                object: object_id.to_string(),
+                author: None,
+            });
+        }
+    }
+
+    pub fn lock(&self, object_id: &str) {
+        self.send(Message::Lock {
+            object: object_id.to_string(),
+            holder: None,
+        })
+    }
+
+    pub fn unlock(&self, object_id: &str) {
+        self.send(Message::Unlock {
+            object: object_id.to_string(),
+            author: None,
+        })
+    }
+
+    pub fn cursor(&self, address: usize) {
+        self.send(Message::Cursor { address })
+    }
+
+    pub fn subscribe(&self, filters: Vec<Filter>) {
+        self.send(Message::Subscribe { filters })
+    }
+
+    // Sends a message to binal over every connection that supports it
+    fn send(&self, message: Message) {
+        let mut state = self.state.lock().unwrap();
+
+        for session in &mut state.sessions {
+            if session.supports(message.kind()) {
+                session.send(&message);
+            }
+        }
+    }
+
+    pub fn program(&self) -> Program {
+        self.state.lock().unwrap().program.clone()
+    }
+
+    pub fn locks(&self) -> HashMap<ObjectId, Option<String>> {
+        self.state.lock().unwrap().locks.clone()
+    }
+
+    pub fn received(&self) -> Vec<Message> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().sessions.len()
+    }
+
+    // Whether the program got to satisfy `condition` before the timeout, for waiting on binal
+    pub fn wait_until(&self, timeout: Duration, condition: impl Fn(&Program) -> bool) -> bool {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if condition(&self.state.lock().unwrap().program) {
+                return true;
+            }
+
+            if Instant::now() >= deadline {
+                return false;
+            }
+
+            std::thread::sleep(ACCEPT_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for MockPeer {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+
+        for session in &self.state.lock().unwrap().sessions {
+            let _ = session.stream.shutdown();
+        }
+    }
+}
+
+type Reader = BufReader<Box<dyn Stream>>;
+
+fn serve(state: &Mutex<State>, stream: Box<dyn Stream>, token: Option<&str>) -> io::Result<()> {
+    let (id, reader) = start(state, stream, token, false)?;
+    read(state, id, reader);
+
+    Ok(())
+}
+
+// Does the handshake and initial push, then starts sending changes over the connection
+fn start(
+    state: &Mutex<State>,
+    mut stream: Box<dyn Stream>,
+    token: Option<&str>,
+    hub: bool,
+) -> io::Result<(usize, Reader)> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let binal = handshake(&mut stream, &mut reader, token)?;
+
+    log::info!("Mock connected to {} {}", binal.tool, binal.tool_version);
+
+    let mut state = state.lock().unwrap();
+    let id = state.next_session;
+    state.next_session += 1;
+
+    let mut session = Session {
+        id,
+        stream,
+        binal,
+        hub,
+        revision: 0,
+        changes: HashMap::new(),
+        hashes: HashIndex::default(),
+    };
+
+    session.initial_push(&state.program);
+    state.sessions.push(session);
+
+    Ok((id, reader))
+}
+
+fn read(state: &Mutex<State>, id: usize, reader: Reader) {
+    for line in reader.lines() {
+        let Ok(line) = line else {
+            break;
+        };
+
+        match serde_json::from_str(&line) {
+            Ok(message) => handle(state, id, message),
+            Err(e) => log::error!("Mock could not decode a message: {}", e),
+        }
+    }
+
+    log::info!("Mock connection {} closed", id);
+
+    let mut state = state.lock().unwrap();
+    state.sessions.retain(|session| session.id != id);
+}
+
+fn handle(state: &Mutex<State>, id: usize, message: Message) {
+    let mut state = state.lock().unwrap();
+    let State {
+        program,
+        sessions,
+        locks,
+        received,
+        ..
+    } = &mut *state;
+
+    received.push(message.clone());
+
+    let Some(session) = sessions.iter_mut().find(|session| session.id == id) else {
+        return;
+    };
+
+    match message {
+        Message::Push { id, objects, .. } => {
+            let applied: Vec<ObjectId> = objects.keys().cloned().collect();
+            let rejected = program.lower(objects);
+
+            let Some(id) = id else {
+                return;
+            };
+
+            let applied = applied
+                .into_iter()
+                .filter(|object_id| !rejected.contains_key(object_id))
+                .collect();
+
+            if !rejected.is_empty() {
+                session.send(&Message::Reject { id, rejected });
+            }
+
+            session.send(&Message::Ack { id, applied });
+        }
+        Message::Delete { object, .. } => {
+            program.objects.remove(&object);
+        }
+        Message::Rename { kind, old, new, .. } => program.rename(&kind, &old, &new),
+        Message::Pull { id, query } => session.answer(program, id, query),
+        Message::Lock { object, holder } => {
+            locks.insert(object, holder);
+        }
+        Message::Unlock { object, .. } => {
+            locks.remove(&object);
+        }
+        Message::Error { id: None, message } => log::warn!("binal refused a change: {}", message),
+        // kept in `received` for whoever drives the mock to look at
+        _ => {}
+    }
+}
+
+// Sends the hello first and reads binal's, then answers its challenge before checking the answer
+// to ours, as the plugin does
+fn handshake(
+    stream: &mut Box<dyn Stream>,
+    reader: &mut Reader,
+    token: Option<&str>,
+) -> io::Result<Hello> {
+    let challenge = token.map(|_| net::random_hex());
+
+    let hello = Hello {
+        tool: String::from("mock"),
+        user: Some(String::from("mock")),
+        messages: [
+            "push",
+            "delete",
+            "rename",
+            "pull",
+            "response",
+            "error",
+            "ack",
+            "reject",
+            "summary",
+            "hashes",
+            "cursor",
+            "goto",
+            "subscribe",
+            "lock",
+            "unlock",
+        ]
+        .map(String::from)
+        .to_vec(),
+        encodings: vec![String::from("json")],
+        compression: Vec::new(),
+        challenge: challenge.clone(),
+        ..Hello::default()
+    };
+
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    write_line(stream, &Message::Hello(Box::new(hello)))?;
+
+    let binal = match read_line(reader)? {
+        Message::Hello(hello) => *hello,
+        message => {
+            return Err(io::Error::other(format!(
+                "expected a hello, got a {} message",
+                message.kind()
+            )))
+        }
+    };
+
+    if binal.version != PROTOCOL_VERSION {
+        return Err(io::Error::other(format!(
+            "binal speaks protocol version {}",
+            binal.version
+        )));
+    }
+
+    match (token.zip(challenge), &binal.challenge) {
+        (Some((token, ours)), Some(theirs)) => {
+            if &ours == theirs {
+                return Err(io::Error::other("binal sent back our own challenge"));
+            }
+
+            write_line(
+                stream,
+                &Message::Auth {
+                    proof: prove(token, theirs, &ours),
+                },
+            )?;
+
+            let proof = match read_line(reader)? {
+                Message::Auth { proof } => proof,
+                _ => return Err(io::Error::other("binal did not answer the token challenge")),
+            };
+
+            let key = hmac::Key::new(hmac::HMAC_SHA256, token.as_bytes());
+            let proof = net::from_hex(&proof).unwrap_or_default();
+
+            if hmac::verify(&key, format!("{}{}", ours, theirs).as_bytes(), &proof).is_err() {
+                return Err(io::Error::other("binal does not know the token"));
+            }
+        }
+        (Some(_), None) => return Err(io::Error::other("binal is not set up with a token")),
+        (None, Some(_)) => return Err(io::Error::other("binal requires a token")),
+        (None, None) => {}
+    }
+
+    stream.set_read_timeout(None)?;
+
+    Ok(binal)
+}
+
+// An HMAC of the two challenges keyed with the token, the first being the one it answers
+fn prove(token: &str, first: &str, second: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, token.as_bytes());
+    let proof = hmac::sign(&key, format!("{}{}", first, second).as_bytes());
+
+    net::to_hex(proof.as_ref())
+}
+
+fn write_line(stream: &mut Box<dyn Stream>, message: &Message) -> io::Result<()> {
+    let mut buffer = serde_json::to_vec(message)?;
+    buffer.push(b'\n');
+
+    stream.write_all(&buffer)?;
+    stream.flush()
+}
+
+fn read_line(reader: &mut Reader) -> io::Result<Message> {
+    let mut line = String::new();
+
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::other("binal closed the connection"));
+    }
+
+    serde_json::from_str(&line).map_err(|e| io::Error::other(e.to_string()))
+}
@@ -1,40 +1,95 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use std::{
-    collections::HashMap,
-    io::{BufRead, BufReader, Write},
-    net::{SocketAddrV4, TcpStream},
-    sync::mpsc::{self, Receiver},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt,
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use ring::{digest, hmac, rand};
+
+use crate::{
+    record::{Direction, Recorder, Tap},
+    schema,
+    transport::{Listener, Stream, Transport},
 };
 
-use crate::ir;
+// Bumped whenever a change to the messages below would break an older peer
+pub const PROTOCOL_VERSION: u32 = 2;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+// Frames bigger than this are refused rather than allocated, as the length is probably garbage
+const MAX_FRAME_SIZE: usize = 1 << 28;
+
+// How often background threads check whether the connection or its owner has gone away
+const LINK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long pushes are held back so that repeated updates of the same object go out as one
+const COALESCE_WINDOW: Duration = Duration::from_millis(50);
+
+// Messages that can queue up between two threads before the sending side has to wait, or
+// gives up if it cannot
+const CHANNEL_CAPACITY: usize = 1024;
+
+// Identifies an object for as long as it exists, whatever it gets renamed to
+pub type ObjectId = String;
+
+// Functions and data go by their address, which every tool agrees on
+pub fn function_id(location: usize) -> ObjectId {
+    format!("function:{:#x}", location)
+}
+
+pub fn data_id(location: usize) -> ObjectId {
+    format!("data:{:#x}", location)
+}
+
+// Types have nothing like that, so whoever creates one first makes up an id for it
+pub fn new_type_id() -> ObjectId {
+    format!("type:{}", uuid::Uuid::new_v4())
+}
+
+// Summaries group objects by address, or types and whatever else has no address by a number
+// derived from their id, into buckets of these sizes
+const ADDRESS_BUCKET_SIZE: u64 = 1 << 16;
+const TYPE_BUCKET_SIZE: u64 = 1 << 56;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct EnumValue {
     pub name: String,
     pub value: usize,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Argument {
     pub name: String,
     pub r#type: TypeRef,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct StructField {
     pub name: String,
     pub offset: usize,
     pub r#type: TypeRef,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct UnionField {
     pub name: String,
     pub r#type: TypeRef,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "kind")]
 #[serde(rename_all(deserialize = "lowercase", serialize = "lowercase"))]
 pub enum TypeInfo {
@@ -60,7 +115,7 @@ pub enum TypeInfo {
     },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "kind")]
 #[serde(rename_all(deserialize = "lowercase", serialize = "lowercase"))]
 pub enum TypeRef {
@@ -71,252 +126,2204 @@ pub enum TypeRef {
     Float { size: u16 },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "kind")]
 #[serde(rename_all(deserialize = "lowercase", serialize = "lowercase"))]
 pub enum Object {
     Type {
+        name: String,
         size: usize,
         alignment: usize,
         info: TypeInfo,
     },
     Function {
+        name: String,
         location: usize,
         arguments: Vec<Argument>,
         return_type: TypeRef,
     },
     Data {
+        name: String,
         location: usize,
         r#type: TypeRef,
     },
+    Comment {
+        target: CommentTarget,
+        text: String,
+    },
+}
+
+// What a comment is attached to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind")]
+#[serde(rename_all(deserialize = "lowercase", serialize = "lowercase"))]
+pub enum CommentTarget {
+    // an instruction or a piece of data
+    Address {
+        address: usize,
+    },
+    // a function as a whole, by the address it starts at
+    Function {
+        location: usize,
+    },
+    // a type, or one of its fields
+    Type {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        field: Option<String>,
+    },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "kind")]
 #[serde(rename_all(deserialize = "lowercase", serialize = "lowercase"))]
 pub enum Message {
-    Push { objects: HashMap<String, Object> },
+    Hello(Box<Hello>),
+    // answers the challenge in the peer's hello, only sent during the handshake
+    Auth {
+        proof: String,
+    },
+    // pushes from here until the matching `Commit` are applied together, or not at all if an
+    // `Abort` or a dropped connection comes first
+    Begin {
+        id: u64,
+    },
+    Commit {
+        id: u64,
+    },
+    Abort {
+        id: u64,
+    },
+    Push {
+        // only set when the sender wants an `Ack` back
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+        objects: HashMap<ObjectId, Object>,
+        // who made the change, when it was not the sender itself, as in `Hello::identity`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        author: Option<String>,
+    },
+    Delete {
+        object: ObjectId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        author: Option<String>,
+    },
+    Rename {
+        // as in `Object::kind`, named `object` on the wire since `kind` is the message's
+        #[serde(rename = "object")]
+        kind: String,
+        old: String,
+        new: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        author: Option<String>,
+    },
+    // where the user of the sending tool has navigated to
+    Cursor {
+        address: usize,
+    },
+    // asks the receiving tool to navigate to an address
+    Goto {
+        address: usize,
+    },
+    // replaces the filters in the sender's `Hello`
+    Subscribe {
+        filters: Vec<Filter>,
+    },
+    // claims an object so only the holder can change it, or tells who holds it
+    Lock {
+        object: ObjectId,
+        // as in `Hello::identity`, only set by binal
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        holder: Option<String>,
+    },
+    Unlock {
+        object: ObjectId,
+        // who released the lock, as in `Hello::identity`, only set by binal
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        author: Option<String>,
+    },
+    Pull {
+        id: u64,
+        query: Query,
+    },
+    Response {
+        id: u64,
+        revision: u64,
+        objects: HashMap<ObjectId, Object>,
+    },
+    // answers a pull, or without an id tells the peer something it sent was refused
+    Error {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+        message: String,
+    },
+    // answers a `Summary` query
+    Summary {
+        id: u64,
+        buckets: Vec<Bucket>,
+    },
+    // answers a `Hashes` query with the content hash of every object it covers
+    Hashes {
+        id: u64,
+        hashes: HashMap<ObjectId, String>,
+    },
+    Ack {
+        id: u64,
+        applied: Vec<ObjectId>,
+    },
+    Reject {
+        id: u64,
+        rejected: HashMap<ObjectId, String>,
+    },
 }
 
-pub struct Client {
-    pub rx: mpsc::Receiver<Message>,
-    pub tx: mpsc::Sender<Message>,
+// What a `Pull` asks the peer for
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind")]
+#[serde(rename_all(deserialize = "lowercase", serialize = "lowercase"))]
+pub enum Query {
+    Type { name: String },
+    Functions { start: usize, end: usize },
+    Changed { since: u64 },
+    Summary,
+    // `object` is the kind of objects to list, from the bucket starting at `start`
+    Hashes { object: String, start: u64 },
+    Objects { ids: Vec<ObjectId> },
 }
 
-impl Client {
-    pub fn connect(socket_addr: SocketAddrV4) -> std::io::Result<Self> {
-        let stream = TcpStream::connect(socket_addr)?;
+// One bucket of a summary, which only differs between two sides if an object in it does
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Bucket {
+    // as in `Object::kind`
+    pub object: String,
+    pub start: u64,
+    pub count: usize,
+    pub hash: String,
+}
 
-        let mut reader = BufReader::new(stream.try_clone()?);
-        let mut buffer = Vec::new();
+// Content hashes of a set of objects, grouped into the buckets summaries are made of
+#[derive(Default)]
+pub struct HashIndex {
+    buckets: BTreeMap<(String, u64), BTreeMap<ObjectId, String>>,
+}
 
-        let (tx_inside, rx_outside) = mpsc::channel();
-        let (tx_outside, rx_inside): (mpsc::Sender<Message>, Receiver<Message>) = mpsc::channel();
+impl HashIndex {
+    pub fn new(objects: &HashMap<ObjectId, Object>) -> Self {
+        let mut index = Self::default();
 
-        std::thread::spawn(move || loop {
-            buffer.clear();
+        for (object_id, object) in objects {
+            let kind = object.kind().to_string();
+            let start = object.bucket_start(object_id);
 
-            let size = match reader.read_until(b'\n', &mut buffer) {
-                Ok(size) => size,
-                Err(e) => {
-                    log::error!("Error reading from stream: {}", e);
-                    return;
+            index
+                .buckets
+                .entry((kind, start))
+                .or_default()
+                .insert(object_id.clone(), object.content_hash());
+        }
+
+        index
+    }
+
+    // One entry for every bucket with something in it
+    pub fn summary(&self) -> Vec<Bucket> {
+        self.buckets
+            .iter()
+            .map(|((object, start), hashes)| {
+                let mut context = digest::Context::new(&digest::SHA256);
+
+                for (object_id, hash) in hashes {
+                    context.update(format!("{}:{}\n", object_id, hash).as_bytes());
                 }
-            };
 
-            if size == 0 {
-                log::error!("Socket disconnected");
-                return;
+                Bucket {
+                    object: object.clone(),
+                    start: *start,
+                    count: hashes.len(),
+                    hash: to_hex(context.finish().as_ref()),
+                }
+            })
+            .collect()
+    }
+
+    pub fn bucket(&self, kind: &str, start: u64) -> Option<&BTreeMap<ObjectId, String>> {
+        self.buckets.get(&(kind.to_string(), start))
+    }
+}
+
+impl TypeRef {
+    // Name of the referenced type, unless it is a builtin
+    pub fn name(&self) -> Option<&String> {
+        match self {
+            TypeRef::Value { name } | TypeRef::Pointer { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl Object {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Object::Type { .. } => "type",
+            Object::Function { .. } => "function",
+            Object::Data { .. } => "data",
+            Object::Comment { .. } => "comment",
+        }
+    }
+
+    // What to show the object as, which for comments is their text
+    pub fn name(&self) -> &String {
+        match self {
+            Object::Type { name, .. }
+            | Object::Function { name, .. }
+            | Object::Data { name, .. } => name,
+            Object::Comment { text, .. } => text,
+        }
+    }
+
+    // SHA-256 of the object as JSON with sorted keys and no whitespace, which any peer can
+    // reproduce to tell whether it has the same version of an object
+    pub fn content_hash(&self) -> String {
+        let json = serde_json::to_value(self).map(|value| value.to_string());
+        let json = json.unwrap_or_default();
+
+        to_hex(digest::digest(&digest::SHA256, json.as_bytes()).as_ref())
+    }
+
+    // Where the object is in the binary, if anywhere
+    pub fn address(&self) -> Option<usize> {
+        match self {
+            Object::Function { location, .. } | Object::Data { location, .. } => Some(*location),
+            Object::Comment { target, .. } => match target {
+                CommentTarget::Address { address } => Some(*address),
+                CommentTarget::Function { location } => Some(*location),
+                CommentTarget::Type { .. } => None,
+            },
+            Object::Type { .. } => None,
+        }
+    }
+
+    // Start of the bucket the object goes in for summaries. Objects are placed by their address,
+    // or if they have none, like types, by the first 8 bytes of the SHA-256 of their id
+    pub fn bucket_start(&self, object_id: &str) -> u64 {
+        match self.address() {
+            Some(address) => (address as u64) - (address as u64) % ADDRESS_BUCKET_SIZE,
+            None => {
+                let hash = digest::digest(&digest::SHA256, object_id.as_bytes());
+                let position = u64::from_be_bytes(hash.as_ref()[..8].try_into().unwrap());
+
+                position - position % TYPE_BUCKET_SIZE
             }
+        }
+    }
 
-            let message = match serde_json::from_slice(&buffer) {
-                Ok(o) => o,
-                Err(e) => {
-                    log::error!("Error while deserializing message from server: {}", e);
-                    continue;
+    // Names of every type this object refers to
+    pub fn dependencies(&self) -> Vec<&String> {
+        let type_refs: Vec<&TypeRef> = match self {
+            Object::Type { info, .. } => match info {
+                TypeInfo::Typedef { r#type } => vec![r#type],
+                TypeInfo::Function { arg_types, r#type } => {
+                    arg_types.iter().chain([r#type]).collect()
                 }
-            };
+                TypeInfo::Struct { fields } => fields.iter().map(|f| &f.r#type).collect(),
+                TypeInfo::Enum { .. } => Vec::new(),
+                TypeInfo::Union { fields } => fields.iter().map(|f| &f.r#type).collect(),
+                TypeInfo::Array { r#type, .. } => vec![r#type],
+            },
+            Object::Function {
+                arguments,
+                return_type,
+                ..
+            } => arguments
+                .iter()
+                .map(|a| &a.r#type)
+                .chain([return_type])
+                .collect(),
+            Object::Data { r#type, .. } => vec![r#type],
+            Object::Comment {
+                target: CommentTarget::Type { name, .. },
+                ..
+            } => return vec![name],
+            Object::Comment { .. } => Vec::new(),
+        };
 
-            tx_inside.send(message).unwrap();
-        });
+        type_refs.into_iter().filter_map(TypeRef::name).collect()
+    }
 
-        let mut stream = stream.try_clone()?;
+    // Same as `dependencies`, for following a type being renamed
+    pub fn dependencies_mut(&mut self) -> Vec<&mut String> {
+        let type_refs: Vec<&mut TypeRef> = match self {
+            Object::Type { info, .. } => match info {
+                TypeInfo::Typedef { r#type } => vec![r#type],
+                TypeInfo::Function { arg_types, r#type } => {
+                    arg_types.iter_mut().chain([r#type]).collect()
+                }
+                TypeInfo::Struct { fields } => fields.iter_mut().map(|f| &mut f.r#type).collect(),
+                TypeInfo::Enum { .. } => Vec::new(),
+                TypeInfo::Union { fields } => fields.iter_mut().map(|f| &mut f.r#type).collect(),
+                TypeInfo::Array { r#type, .. } => vec![r#type],
+            },
+            Object::Function {
+                arguments,
+                return_type,
+                ..
+            } => arguments
+                .iter_mut()
+                .map(|a| &mut a.r#type)
+                .chain([return_type])
+                .collect(),
+            Object::Data { r#type, .. } => vec![r#type],
+            Object::Comment {
+                target: CommentTarget::Type { name, .. },
+                ..
+            } => return vec![name],
+            Object::Comment { .. } => Vec::new(),
+        };
 
-        std::thread::spawn(move || loop {
-            if let Ok(message) = rx_inside.recv() {
-                let mut buffer = match serde_json::to_vec(&message) {
-                    Ok(o) => o,
-                    Err(e) => {
-                        log::error!("Error while serializing message: {}", e);
-                        continue;
-                    }
-                };
+        type_refs
+            .into_iter()
+            .filter_map(|type_ref| match type_ref {
+                TypeRef::Value { name } | TypeRef::Pointer { name, .. } => Some(name),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Message {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::Hello(_) => "hello",
+            Message::Auth { .. } => "auth",
+            Message::Begin { .. } => "begin",
+            Message::Commit { .. } => "commit",
+            Message::Abort { .. } => "abort",
+            Message::Push { .. } => "push",
+            Message::Delete { .. } => "delete",
+            Message::Rename { .. } => "rename",
+            Message::Cursor { .. } => "cursor",
+            Message::Goto { .. } => "goto",
+            Message::Subscribe { .. } => "subscribe",
+            Message::Lock { .. } => "lock",
+            Message::Unlock { .. } => "unlock",
+            Message::Pull { .. } => "pull",
+            Message::Response { .. } => "response",
+            Message::Error { .. } => "error",
+            Message::Summary { .. } => "summary",
+            Message::Hashes { .. } => "hashes",
+            Message::Ack { .. } => "ack",
+            Message::Reject { .. } => "reject",
+        }
+    }
+}
 
-                buffer.push(b'\n');
+// Sent by both sides as the very first message of a connection
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Hello {
+    pub version: u32,
+    pub tool: String,
+    pub tool_version: String,
+    pub binary_hash: Option<String>,
+    // who is using the tool, left out if the sender does not know
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    pub objects: Vec<String>,
+    pub messages: Vec<String>,
+    // encodings the sender can read, older plugins leave this out and only speak JSON
+    #[serde(default)]
+    pub encodings: Vec<String>,
+    // compression the sender can undo, left out when it has none
+    #[serde(default)]
+    pub compression: Vec<String>,
+    // random hex string, sent when the sender requires the peer to prove it knows the token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub challenge: Option<String>,
+    // the sender is only pushed objects matching one of these, or everything if there are none
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<Filter>,
+}
 
-                if let Err(_) = stream.write_all(&buffer) {
-                    log::error!("Socket disconnected");
-                    return;
-                }
+// What a peer of a hub may do, depending on the token it connected with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    // gets sent everything, but cannot change anything
+    ReadOnly,
+    // can change objects and lock them
+    #[default]
+    Contributor,
+    // can also release the locks others hold
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::ReadOnly => write!(f, "read-only"),
+            Role::Contributor => write!(f, "contributor"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+// Narrows down the objects a peer is pushed, every condition that is given has to hold
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Filter {
+    // kinds as in `Object::kind`, any kind if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub objects: Vec<String>,
+    // matched against the whole name, or the text of a comment, where `*` stands for any number
+    // of characters and `?` for exactly one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // addresses in `start..end`, which objects without an address never fall into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<usize>,
+}
+
+impl Filter {
+    pub fn matches(&self, object: &Object) -> bool {
+        if !self.objects.is_empty() && !self.objects.iter().any(|k| k == object.kind()) {
+            return false;
+        }
+
+        if let Some(pattern) = &self.name {
+            if !glob_match(pattern, object.name()) {
+                return false;
             }
-        });
+        }
 
-        Ok(Self {
-            rx: rx_outside,
-            tx: tx_outside,
-        })
+        if self.start.is_none() && self.end.is_none() {
+            return true;
+        }
+
+        let range = self.start.unwrap_or(0)..self.end.unwrap_or(usize::MAX);
+        object
+            .address()
+            .is_some_and(|address| range.contains(&address))
     }
 }
 
-pub fn get_net_from_db(db: &ir::Database, id: usize) -> HashMap<String, Object> {
-    let mut map = HashMap::new();
-    let mut lifted = HashSet::new();
-    let mut to_lift = vec![id];
+// Whether `text` matches `pattern` as a whole, see `Filter::name`
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
 
-    while !to_lift.is_empty() {
-        let index = to_lift.pop().unwrap();
+    let (mut p, mut t) = (0, 0);
+    // where the last `*` was, and how much of the text it has taken so far
+    let mut star = None;
 
-        if lifted.contains(&index) {
-            continue;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+impl Hello {
+    // Who is on the other end, for telling peers apart in the activity feed
+    pub fn identity(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{} ({})", user, self.tool),
+            None => self.tool.clone(),
+        }
+    }
+
+    // Whether the filters let through the object with this id, or something among `objects`
+    // that depends on it, as they would in a push of `objects`
+    pub fn subscribes_to(&self, object_id: &str, objects: &HashMap<ObjectId, Object>) -> bool {
+        self.filters.is_empty() || subscribed(&self.filters, objects).contains(object_id)
+    }
+}
+
+// The user binal runs as, which can be overridden with `BINAL_USER`
+fn local_user() -> Option<String> {
+    ["BINAL_USER", "USER", "USERNAME"]
+        .into_iter()
+        .find_map(|name| std::env::var(name).ok())
+        .filter(|user| !user.is_empty())
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            tool: String::from("binal"),
+            tool_version: String::from(env!("CARGO_PKG_VERSION")),
+            binary_hash: None,
+            user: local_user(),
+            objects: ["type", "function", "data", "comment"]
+                .map(String::from)
+                .to_vec(),
+            messages: [
+                "push",
+                "delete",
+                "rename",
+                "pull",
+                "response",
+                "error",
+                "ack",
+                "reject",
+                "begin",
+                "commit",
+                "abort",
+                "summary",
+                "hashes",
+                "cursor",
+                "goto",
+                "subscribe",
+                "lock",
+                "unlock",
+            ]
+            .map(String::from)
+            .to_vec(),
+            encodings: Encoding::ALL.map(|e| e.to_string()).to_vec(),
+            compression: Compression::ALL.map(|c| c.to_string()).to_vec(),
+            challenge: None,
+            filters: Vec::new(),
+        }
+    }
+}
+
+// How messages are framed on the wire once the hellos, which are always JSON lines, are exchanged
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    // one JSON object per line
+    Json,
+    // MessagePack, each message prefixed with its length as a big endian u32
+    MessagePack,
+}
+
+impl Encoding {
+    // In order of preference, which both sides follow so they end up with the same one
+    const ALL: [Encoding; 2] = [Encoding::MessagePack, Encoding::Json];
+
+    // The most preferred encoding both sides announced, falling back to JSON
+    pub fn negotiate(peer: &Hello) -> Self {
+        let ours = Hello::default();
+
+        Self::ALL
+            .into_iter()
+            .find(|e| {
+                let name = e.to_string();
+                ours.encodings.contains(&name) && peer.encodings.contains(&name)
+            })
+            .unwrap_or(Encoding::Json)
+    }
+
+    fn encode(self, message: &Message) -> Option<Vec<u8>> {
+        let result = match self {
+            Encoding::Json => serde_json::to_vec(message).map_err(|e| e.to_string()),
+            // named, since tagged enums need their fields in a map rather than an array
+            Encoding::MessagePack => rmp_serde::to_vec_named(message).map_err(|e| e.to_string()),
+        };
+
+        let mut buffer = match result {
+            Ok(o) => o,
+            Err(e) => {
+                log::error!("Error while serializing message: {}", e);
+                return None;
+            }
+        };
+
+        match self {
+            Encoding::Json => buffer.push(b'\n'),
+            Encoding::MessagePack => {
+                let Ok(size) = u32::try_from(buffer.len()) else {
+                    log::error!("Message of {} bytes is too big to send", buffer.len());
+                    return None;
+                };
+
+                buffer.splice(0..0, size.to_be_bytes());
+            }
         }
-        lifted.insert(index);
 
-        let r#type = &db.types[index];
+        Some(buffer)
+    }
+
+    fn decode(self, buffer: &[u8]) -> Result<Message, String> {
+        match self {
+            Encoding::Json => serde_json::from_slice(buffer).map_err(|e| e.to_string()),
+            Encoding::MessagePack => rmp_serde::from_slice(buffer).map_err(|e| e.to_string()),
+        }
+    }
 
-        let lifted_type: Object = match &r#type.info {
-            TypeInfo::Struct(struct_members) => todo!(),
-            TypeInfo::Enum(enum_values) => todo!(),
-            TypeInfo::Union(union_members) => todo!(),
-            TypeInfo::TypeDef(type_ref) => todo!(),
-            TypeInfo::Function(type_refs, type_ref) => todo!(),
-            TypeInfo::Array(type_ref, _) => todo!(),
+    // Whatever can be made of a message that did not decode, for recording it
+    fn decode_any(self, buffer: &[u8]) -> serde_json::Value {
+        let value = match self {
+            Encoding::Json => serde_json::from_slice(buffer).ok(),
+            Encoding::MessagePack => rmp_serde::from_slice(buffer).ok(),
         };
 
-        map.insert(r#type.name.clone(), lifted_type);
+        value.unwrap_or_else(|| serde_json::Value::from(String::from_utf8_lossy(buffer)))
     }
 
-    map
+    // Reads the next message into `buffer`, returning false once the stream has closed
+    fn read_frame(self, reader: &mut Reader, buffer: &mut Vec<u8>) -> std::io::Result<bool> {
+        match self {
+            Encoding::Json => Ok(reader.read_until(b'\n', buffer)? != 0),
+            Encoding::MessagePack => {
+                let mut size = [0; 4];
+
+                match reader.read_exact(&mut size) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+                    Err(e) => return Err(e),
+                }
+
+                let size = u32::from_be_bytes(size) as usize;
+
+                if size > MAX_FRAME_SIZE {
+                    return Err(std::io::Error::other(format!(
+                        "refusing a frame of {} bytes",
+                        size
+                    )));
+                }
+
+                buffer.resize(size, 0);
+                reader.read_exact(buffer)?;
+
+                Ok(true)
+            }
+        }
+    }
 }
-fn lift_type_ref(db: &ir::Database, type_ref: &TypeRef) -> TypeRef {
-    match type_ref {
-        TypeRef::Value { name } => {
-            let index = self.type_lookup[name];
-            TypeRef::Value(index)
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encoding::Json => write!(f, "json"),
+            Encoding::MessagePack => write!(f, "msgpack"),
         }
-        TypeRef::Pointer { depth, name } => {
-            let index = self.type_lookup[name];
-            TypeRef::Pointer(*depth, index)
+    }
+}
+
+// Applied to the whole stream after the hellos, underneath the encoding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Zstd,
+    // raw deflate without zlib or gzip headers
+    Deflate,
+}
+
+impl Compression {
+    // In order of preference, which both sides follow so they end up with the same one
+    const ALL: [Compression; 2] = [Compression::Zstd, Compression::Deflate];
+
+    // The most preferred compression both sides announced, if any
+    pub fn negotiate(peer: &Hello) -> Self {
+        let ours = Hello::default();
+
+        Self::ALL
+            .into_iter()
+            .find(|c| {
+                let name = c.to_string();
+                ours.compression.contains(&name) && peer.compression.contains(&name)
+            })
+            .unwrap_or(Compression::None)
+    }
+
+    fn reader(self, reader: Reader) -> std::io::Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            Compression::None => Box::new(reader),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+            Compression::Deflate => Box::new(Inflate {
+                inner: reader,
+                state: flate2::Decompress::new(false),
+            }),
+        })
+    }
+
+    fn writer(self, writer: impl Write + Send + 'static) -> std::io::Result<Box<dyn Write + Send>> {
+        Ok(match self {
+            Compression::None => Box::new(writer),
+            Compression::Zstd => Box::new(zstd::stream::write::Encoder::new(writer, 0)?),
+            Compression::Deflate => Box::new(flate2::write::DeflateEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            )),
+        })
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Zstd => write!(f, "zstd"),
+            Compression::Deflate => write!(f, "deflate"),
         }
-        TypeRef::Uint { size } => TypeRef::Uint(*size),
-        TypeRef::Int { size } => TypeRef::Int(*size),
-        TypeRef::Float { size } => TypeRef::Float(*size),
     }
 }
 
-fn reserve_object<T: Default>(
-    index_lookup: &mut HashMap<String, usize>,
-    objects: &mut Vec<T>,
-    name: &String,
-) {
-    if index_lookup.get(name).is_none() {
-        objects.push(T::default());
-        index_lookup.insert(name.clone(), objects.len() - 1);
-    }
-}
-
-pub fn push_net(&mut self, objects: HashMap<String, Object>) {
-    // we need to create stubs for each object to support circular dependencies
-    for (name, obj) in &objects {
-        match obj {
-            Object::Type { .. } => {
-                Self::reserve_object(&mut self.type_lookup, &mut self.types, &name)
-            }
-            Object::Function { .. } => {
-                Self::reserve_object(&mut self.function_lookup, &mut self.functions, &name);
-            }
-            Object::Data { .. } => {
-                Self::reserve_object(&mut self.data_lookup, &mut self.data, &name);
-            }
-        }
-    }
-
-    // now fill out each object
-    for (name, obj) in objects {
-        match obj {
-            Object::Type {
-                info,
-                size,
-                alignment,
-            } => {
-                let index = self.type_lookup[&name];
-
-                self.types[index].name = name;
-                self.types[index].size = size;
-                self.types[index].alignment = alignment;
-
-                self.types[index].info = match info {
-                    TypeInfo::Typedef { r#type } => TypeInfo::TypeDef(self.lift_type_ref(&r#type)),
-                    TypeInfo::Function { arg_types, r#type } => TypeInfo::Function(
-                        arg_types.iter().map(|t| self.lift_type_ref(t)).collect(),
-                        self.lift_type_ref(&r#type),
-                    ),
-                    TypeInfo::Struct { fields } => TypeInfo::Struct(
-                        fields
-                            .into_iter()
-                            .map(|f| StructMember {
-                                name: f.name,
-                                offset: f.offset,
-                                r#type: self.lift_type_ref(&f.r#type),
-                            })
-                            .collect(),
-                    ),
-                    TypeInfo::Enum { values } => TypeInfo::Enum(
-                        values
-                            .into_iter()
-                            .map(|v| EnumValue {
-                                name: v.name,
-                                value: v.value,
-                            })
-                            .collect(),
-                    ),
-                    TypeInfo::Array { r#type, count } => {
-                        TypeInfo::Array(self.lift_type_ref(&r#type), count)
-                    }
-                    TypeInfo::Union { fields } => TypeInfo::Union(
-                        fields
-                            .into_iter()
-                            .map(|f| UnionMember {
-                                name: f.name,
-                                r#type: self.lift_type_ref(&f.r#type),
-                            })
-                            .collect(),
-                    ),
-                };
+// Decompresses raw deflate, handing out everything it can before waiting on more input; flate2's
+// own readers wait first, which holds a message back until the next one arrives
+struct Inflate<R> {
+    inner: R,
+    state: flate2::Decompress,
+}
+
+impl<R: BufRead> Read for Inflate<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut first = true;
+
+        loop {
+            let input = if first {
+                &[][..]
+            } else {
+                self.inner.fill_buf()?
+            };
+
+            if !first && input.is_empty() {
+                return Ok(0);
             }
-            Object::Function {
-                arguments,
-                return_type: r#type,
-                location,
-            } => {
-                let index = self.function_lookup[&name];
-
-                self.functions[index].location = location;
-                self.functions[index].name = name;
-                self.functions[index].return_type = self.lift_type_ref(&r#type);
-                self.functions[index].argument_types = arguments
-                    .iter()
-                    .map(|t| self.lift_type_ref(&t.r#type))
-                    .collect();
-                self.functions[index].argument_names =
-                    arguments.iter().map(|t| t.name.clone()).collect();
-            }
-            Object::Data { r#type, location } => {
-                let index = self.data_lookup[&name];
-
-                self.data[index].location = location;
-                self.data[index].name = name;
-                self.data[index].r#type = self.lift_type_ref(&r#type);
+
+            first = false;
+
+            let (total_in, total_out) = (self.state.total_in(), self.state.total_out());
+
+            let status = self
+                .state
+                .decompress(input, buf, flate2::FlushDecompress::None)
+                .map_err(std::io::Error::other)?;
+
+            let consumed = (self.state.total_in() - total_in) as usize;
+            let written = (self.state.total_out() - total_out) as usize;
+
+            self.inner.consume(consumed);
+
+            if written > 0 || buf.is_empty() || status == flate2::Status::StreamEnd {
+                return Ok(written);
             }
         }
     }
 }
+
+// Bytes that went through a connection, as messages and as actually sent over the wire
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Traffic {
+    pub sent: u64,
+    pub sent_wire: u64,
+    pub received: u64,
+    pub received_wire: u64,
+    // object updates that were superseded by a later one before being handed on
+    pub merged: u64,
+    // messages that were thrown away because the other side was not keeping up
+    pub dropped: u64,
+}
+
+impl fmt::Display for Traffic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sent {} ({} on the wire), received {} ({} on the wire)",
+            format_bytes(self.sent),
+            format_bytes(self.sent_wire),
+            format_bytes(self.received),
+            format_bytes(self.received_wire)
+        )?;
+
+        if self.merged > 0 {
+            write!(f, ", merged {} updates", self.merged)?;
+        }
+
+        if self.dropped > 0 {
+            write!(f, ", dropped {} messages", self.dropped)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+// Shared with the threads doing the reading and writing, which keep them up to date
+#[derive(Default)]
+struct Counters {
+    sent: AtomicU64,
+    sent_wire: AtomicU64,
+    received: AtomicU64,
+    received_wire: AtomicU64,
+    merged: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Counters {
+    fn traffic(&self) -> Traffic {
+        Traffic {
+            sent: self.sent.load(Ordering::Relaxed),
+            sent_wire: self.sent_wire.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            received_wire: self.received_wire.load(Ordering::Relaxed),
+            merged: self.merged.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Counts the bytes read or written through it into one of the counters
+struct Counted<T> {
+    inner: T,
+    counters: Arc<Counters>,
+    counter: fn(&Counters) -> &AtomicU64,
+}
+
+impl<T> Counted<T> {
+    fn new(inner: T, counters: &Arc<Counters>, counter: fn(&Counters) -> &AtomicU64) -> Self {
+        Self {
+            inner,
+            counters: counters.clone(),
+            counter,
+        }
+    }
+
+    fn count(&self, bytes: usize) {
+        (self.counter)(&self.counters).fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+impl<T: Read> Read for Counted<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let size = self.inner.read(buf)?;
+        self.count(size);
+
+        Ok(size)
+    }
+}
+
+impl<T: Write> Write for Counted<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let size = self.inner.write(buf)?;
+        self.count(size);
+
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// A connection that made it through the handshake, read and written with whatever encoding
+// and compression were agreed on
+struct Connection {
+    // the underlying stream, kept around to shut it down
+    stream: Box<dyn Stream>,
+    reader: Reader,
+    writer: Writer,
+    peer: Hello,
+    // which of the tokens the peer proved it knows, if any were required
+    token: Option<usize>,
+}
+
+impl Connection {
+    // Connecting sides give the token they have, if any, while listening sides give every token
+    // they accept
+    fn establish(
+        stream: Box<dyn Stream>,
+        counters: &Arc<Counters>,
+        tokens: &[String],
+        listening: bool,
+        tap: Tap,
+    ) -> std::io::Result<Self> {
+        let mut writer = Counted::new(stream.try_clone()?, counters, |c| &c.sent_wire);
+
+        let mut reader: Reader =
+            BufReader::new(Box::new(Counted::new(stream.try_clone()?, counters, |c| {
+                &c.received_wire
+            })));
+
+        let (peer, token) = handshake(
+            stream.as_ref(),
+            &mut writer,
+            &mut reader,
+            tokens,
+            listening,
+            &tap,
+        )?;
+
+        let encoding = Encoding::negotiate(&peer);
+        let compression = Compression::negotiate(&peer);
+
+        // anything the reader buffered past the hello is already compressed, so it goes
+        // underneath the decompression
+        let reader = BufReader::new(Box::new(Counted::new(
+            compression.reader(reader)?,
+            counters,
+            |c| &c.received,
+        )) as Box<dyn Read + Send>);
+
+        let writer = Writer {
+            inner: Box::new(Counted::new(compression.writer(writer)?, counters, |c| {
+                &c.sent
+            })),
+            encoding,
+            tap,
+        };
+
+        Ok(Self {
+            stream,
+            reader,
+            writer,
+            peer,
+            token,
+        })
+    }
+}
+
+struct Writer {
+    inner: Box<dyn Write + Send>,
+    encoding: Encoding,
+    tap: Tap,
+}
+
+impl Writer {
+    // Writes a message and flushes it past any compression, so the peer can read it right away
+    fn write(&mut self, message: &Message) -> std::io::Result<()> {
+        let Some(buffer) = self.encoding.encode(message) else {
+            return Ok(());
+        };
+
+        self.tap.message(Direction::Out, message);
+
+        self.inner.write_all(&buffer)?;
+        self.inner.flush()
+    }
+}
+
+// A `Pull` that is still waiting on its `Response` or `Error`
+pub struct Pending {
+    pub id: u64,
+    pub rx: mpsc::Receiver<Message>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionState {
+    Connected,
+    // the connection dropped, `attempt` is the next try at getting it back
+    Reconnecting { attempt: u32, retry_at: Instant },
+}
+
+// Connection details shared between a `Client` and the thread that keeps it connected
+struct Status {
+    state: ConnectionState,
+    peer: Hello,
+    // bumped every time the connection is re-established
+    generation: u64,
+}
+
+// The reading half of a connection, buffered so whole messages can be picked out of it
+type Reader = BufReader<Box<dyn Read + Send>>;
+
+type Waiting = Arc<Mutex<HashMap<u64, mpsc::Sender<Message>>>>;
+
+pub struct Client {
+    pub rx: mpsc::Receiver<Message>,
+    pub tx: mpsc::SyncSender<Message>,
+
+    next_id: u64,
+    pending: Waiting,
+    status: Arc<Mutex<Status>>,
+    counters: Arc<Counters>,
+
+    // pushes that did not fit in `tx`, merged into one that goes out once there is room
+    held: Mutex<Option<Message>>,
+}
+
+impl Client {
+    // With a token, the peer has to be set up with the same one before anything is exchanged.
+    // With a recorder, every message exchanged is recorded, across reconnects
+    pub fn connect(
+        transport: Box<dyn Transport>,
+        token: Option<String>,
+        recorder: Option<Arc<Recorder>>,
+    ) -> std::io::Result<Self> {
+        let counters = Arc::<Counters>::default();
+        let tap = Tap::new(recorder, None);
+        let connection = Connection::establish(
+            transport.connect()?,
+            &counters,
+            token.as_slice(),
+            false,
+            tap.clone(),
+        )?;
+
+        let (tx_inside, rx_outside) = mpsc::sync_channel(CHANNEL_CAPACITY);
+        let (tx_outside, rx_inside) = mpsc::sync_channel(CHANNEL_CAPACITY);
+
+        let pending = Waiting::default();
+        let status = Arc::new(Mutex::new(Status {
+            state: ConnectionState::Connected,
+            peer: connection.peer.clone(),
+            generation: 0,
+        }));
+
+        let mut link = Link {
+            transport,
+            token,
+            tap,
+            rx: rx_inside,
+            tx: tx_inside,
+            pending: pending.clone(),
+            status: status.clone(),
+            counters: counters.clone(),
+            backlog: VecDeque::new(),
+        };
+
+        std::thread::spawn(move || link.run(connection));
+
+        Ok(Self {
+            rx: rx_outside,
+            tx: tx_outside,
+            next_id: 0,
+            pending,
+            status,
+            counters,
+            held: Mutex::default(),
+        })
+    }
+
+    pub fn peer(&self) -> Hello {
+        self.status.lock().unwrap().peer.clone()
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        Encoding::negotiate(&self.status.lock().unwrap().peer)
+    }
+
+    pub fn compression(&self) -> Compression {
+        Compression::negotiate(&self.status.lock().unwrap().peer)
+    }
+
+    // Totals over every connection made so far
+    pub fn traffic(&self) -> Traffic {
+        self.counters.traffic()
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.status.lock().unwrap().state
+    }
+
+    // Changes every time the connection is re-established after dropping
+    pub fn generation(&self) -> u64 {
+        self.status.lock().unwrap().generation
+    }
+
+    pub fn supports(&self, kind: &str) -> bool {
+        self.status
+            .lock()
+            .unwrap()
+            .peer
+            .messages
+            .iter()
+            .any(|m| m == kind)
+    }
+
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        id
+    }
+
+    // Push objects to the peer, returning the id its `Ack` will carry if it sends one
+    pub fn push(
+        &mut self,
+        objects: HashMap<ObjectId, Object>,
+    ) -> Result<Option<u64>, mpsc::SendError<Message>> {
+        let id = self.supports("ack").then(|| self.next_id());
+
+        self.send(Message::Push {
+            id,
+            objects,
+            author: None,
+        })?;
+
+        Ok(id)
+    }
+
+    // Ask the peer for objects; the reply arrives on the returned `Pending` instead of `rx`
+    pub fn pull(&mut self, query: Query) -> Result<Pending, mpsc::SendError<Message>> {
+        let id = self.next_id();
+
+        let (tx, rx) = mpsc::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+
+        if let Err(e) = self.send(Message::Pull { id, query }) {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+
+        Ok(Pending { id, rx })
+    }
+
+    // Send a message to the peer, leaving out anything it did not announce support for. While
+    // the network thread is behind, pushes that do not want an `Ack` are merged into one that
+    // waits for room, cursor positions are dropped as the next one supersedes them anyway, and
+    // anything else waits for room before returning
+    pub fn send(&self, message: Message) -> Result<(), mpsc::SendError<Message>> {
+        let Some(message) = filter_for(&self.peer(), message) else {
+            return Ok(());
+        };
+
+        let mut held = self.held.lock().unwrap();
+
+        if let Some(pushed) = held.take() {
+            match self.tx.try_send(pushed) {
+                Ok(()) => {}
+                Err(mpsc::TrySendError::Full(pushed)) => *held = Some(pushed),
+                Err(mpsc::TrySendError::Disconnected(pushed)) => {
+                    return Err(mpsc::SendError(pushed))
+                }
+            }
+        }
+
+        // still no room, and nothing gets ahead of what is held back
+        if let Some(pushed) = held.as_mut() {
+            let message = match message {
+                Message::Cursor { .. } => {
+                    self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+                message => merge_push(pushed, message, &self.counters),
+            };
+
+            let Some(message) = message else {
+                return Ok(());
+            };
+
+            if let Some(pushed) = held.take() {
+                self.tx.send(pushed)?;
+            }
+
+            return self.tx.send(message);
+        }
+
+        match self.tx.try_send(message) {
+            Ok(()) => Ok(()),
+            Err(mpsc::TrySendError::Full(message @ Message::Push { id: None, .. })) => {
+                *held = Some(message);
+                Ok(())
+            }
+            Err(mpsc::TrySendError::Full(Message::Cursor { .. })) => {
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(mpsc::TrySendError::Full(message)) => self.tx.send(message),
+            Err(mpsc::TrySendError::Disconnected(message)) => Err(mpsc::SendError(message)),
+        }
+    }
+
+    // Hands the held back push to the network thread if there is room for it now, see `send`
+    pub fn flush(&self) -> Result<(), mpsc::SendError<Message>> {
+        let mut held = self.held.lock().unwrap();
+
+        match held.take().map(|pushed| self.tx.try_send(pushed)) {
+            None | Some(Ok(())) => Ok(()),
+            Some(Err(mpsc::TrySendError::Full(pushed))) => {
+                *held = Some(pushed);
+                Ok(())
+            }
+            Some(Err(mpsc::TrySendError::Disconnected(pushed))) => Err(mpsc::SendError(pushed)),
+        }
+    }
+}
+
+// Owns the socket of a `Client` on a background thread, writing outgoing messages, and
+// reconnecting with backoff whenever the connection drops
+struct Link {
+    transport: Box<dyn Transport>,
+    token: Option<String>,
+    tap: Tap,
+
+    rx: mpsc::Receiver<Message>,
+    tx: mpsc::SyncSender<Message>,
+
+    pending: Waiting,
+    status: Arc<Mutex<Status>>,
+    counters: Arc<Counters>,
+
+    // messages taken off `rx` that are yet to be written, such as one that could not be written
+    // before the connection dropped
+    backlog: VecDeque<Message>,
+}
+
+impl Link {
+    fn run(&mut self, mut connection: Connection) {
+        loop {
+            let stream = connection.stream.try_clone();
+
+            if let Err(e) = self.serve(connection) {
+                log::error!("Connection to {} lost: {}", self.transport, e);
+            }
+
+            if let Ok(stream) = stream {
+                let _ = stream.shutdown();
+            }
+
+            if self.abandoned() {
+                return;
+            }
+
+            // nobody is going to answer pulls sent over the old connection
+            self.pending.lock().unwrap().clear();
+
+            match self.reconnect() {
+                Some(new_connection) => connection = new_connection,
+                None => return,
+            }
+        }
+    }
+
+    // The client was dropped along with its project, so the connection is no longer needed
+    fn abandoned(&self) -> bool {
+        Arc::strong_count(&self.status) == 1
+    }
+
+    fn serve(&mut self, connection: Connection) -> std::io::Result<()> {
+        let Connection {
+            reader,
+            mut writer,
+            peer,
+            ..
+        } = connection;
+        let encoding = writer.encoding;
+        let tap = writer.tap.clone();
+
+        // tells the peer what was wrong with the messages it sent, if it understands errors
+        let (replies, errors) = mpsc::channel::<Message>();
+        let explains = peer.messages.iter().any(|m| m == "error");
+
+        let reader = {
+            let tx = self.tx.clone();
+            let pending = self.pending.clone();
+            let counters = self.counters.clone();
+            let status = self.status.clone();
+
+            std::thread::spawn(move || {
+                // replies are routed after coalescing, so the pushes sent ahead of them are
+                // already queued by the time whoever is waiting sees them
+                let (messages, coalescer) = coalescer(counters, move |message| {
+                    // replies go to whoever is waiting on that id rather than the general queue
+                    let id = match &message {
+                        Message::Response { id, .. }
+                        | Message::Summary { id, .. }
+                        | Message::Hashes { id, .. } => Some(*id),
+                        Message::Error { id, .. } => *id,
+                        _ => None,
+                    };
+
+                    if let Message::Subscribe { filters } = &message {
+                        status.lock().unwrap().peer.filters = filters.clone();
+                    }
+
+                    match id.and_then(|id| pending.lock().unwrap().remove(&id)) {
+                        Some(waiting) => {
+                            let _ = waiting.send(message);
+                            true
+                        }
+                        None => tx.send(message).is_ok(),
+                    }
+                });
+
+                let invalid = |error: String| {
+                    if explains {
+                        let message = format!("invalid message: {}", error);
+                        let _ = replies.send(Message::Error { id: None, message });
+                    }
+                };
+
+                // a transaction is passed on once it is finished, so the project never sees one
+                // the connection dropped in the middle of
+                let mut transaction: Option<Vec<Message>> = None;
+
+                read_messages(reader, encoding, tap, invalid, |message| {
+                    let finished =
+                        matches!(message, Message::Commit { .. } | Message::Abort { .. });
+
+                    match &mut transaction {
+                        Some(held) => held.push(message),
+                        None if matches!(message, Message::Begin { .. }) => {
+                            transaction = Some(vec![message])
+                        }
+                        None => return messages.send(message).is_ok(),
+                    }
+
+                    if !finished {
+                        return true;
+                    }
+
+                    let held = transaction.take().unwrap_or_default();
+                    held.into_iter()
+                        .all(|message| messages.send(message).is_ok())
+                });
+
+                if let Some(held) = transaction {
+                    log::warn!(
+                        "Discarding {} messages of a transaction the connection dropped in",
+                        held.len()
+                    );
+                }
+
+                drop(messages);
+                let _ = coalescer.join();
+            })
+        };
+
+        loop {
+            if reader.is_finished() {
+                return Err(std::io::Error::other("socket disconnected"));
+            }
+
+            let message = match self.backlog.pop_front().or_else(|| errors.try_recv().ok()) {
+                Some(message) => message,
+                None => match self.rx.recv_timeout(LINK_POLL_INTERVAL) {
+                    Ok(message) => self.merge(message),
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+                },
+            };
+
+            if let Err(e) = writer.write(&message) {
+                self.backlog.push_front(message);
+                return Err(e);
+            }
+        }
+    }
+
+    // Pushes that do not want an `Ack` and queued up while the peer was slow to take them are
+    // sent as one, with only the latest version of each object
+    fn merge(&mut self, mut message: Message) -> Message {
+        while let Ok(next) = self.rx.try_recv() {
+            if let Some(next) = merge_push(&mut message, next, &self.counters) {
+                self.backlog.push_back(next);
+                break;
+            }
+        }
+
+        message
+    }
+
+    fn reconnect(&mut self) -> Option<Connection> {
+        let mut backoff = RECONNECT_BACKOFF_MIN;
+
+        for attempt in 1.. {
+            let retry_at = Instant::now() + backoff;
+            self.status.lock().unwrap().state = ConnectionState::Reconnecting { attempt, retry_at };
+
+            while Instant::now() < retry_at {
+                if self.abandoned() {
+                    return None;
+                }
+
+                std::thread::sleep(LINK_POLL_INTERVAL);
+            }
+
+            let connection = self.transport.connect().and_then(|stream| {
+                Connection::establish(
+                    stream,
+                    &self.counters,
+                    self.token.as_slice(),
+                    false,
+                    self.tap.clone(),
+                )
+            });
+
+            match connection {
+                Ok(connection) => {
+                    let mut status = self.status.lock().unwrap();
+
+                    status.state = ConnectionState::Connected;
+                    status.peer = connection.peer.clone();
+                    status.generation += 1;
+
+                    return Some(connection);
+                }
+                Err(e) => log::warn!("Could not reconnect to {}: {}", self.transport, e),
+            }
+
+            backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+        }
+
+        None
+    }
+}
+
+pub enum PeerEvent {
+    Joined { peer: usize },
+    Left { peer: usize },
+    Message { peer: usize, message: Message },
+}
+
+struct Peer {
+    hello: Hello,
+    role: Role,
+    tx: mpsc::SyncSender<Message>,
+    stream: Box<dyn Stream>,
+}
+
+type Peers = Arc<Mutex<HashMap<usize, Peer>>>;
+
+// Accepts connections from any number of plugins, so that binal can sit between them and
+// relay what each one sends to the others
+pub struct Server {
+    pub rx: mpsc::Receiver<PeerEvent>,
+    pub local_addr: String,
+
+    peers: Peers,
+    running: Arc<AtomicBool>,
+    counters: Arc<Counters>,
+}
+
+impl Server {
+    // With tokens, only peers set up with one of them are let in, and get the role it maps to.
+    // Without any, every peer is a contributor. With a recorder, every message exchanged with
+    // any peer is recorded
+    pub fn listen(
+        transport: &dyn Transport,
+        tokens: HashMap<String, Role>,
+        recorder: Option<Arc<Recorder>>,
+    ) -> std::io::Result<Self> {
+        let listener = transport.listen()?;
+        let local_addr = listener.local_addr();
+
+        let (tx, rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
+        let peers = Peers::default();
+        let running = Arc::new(AtomicBool::new(true));
+        let counters = Arc::<Counters>::default();
+
+        {
+            let peers = peers.clone();
+            let running = running.clone();
+            let counters = counters.clone();
+
+            std::thread::spawn(move || {
+                Self::accept(listener, tx, peers, running, counters, tokens, recorder)
+            });
+        }
+
+        Ok(Self {
+            rx,
+            local_addr,
+            peers,
+            running,
+            counters,
+        })
+    }
+
+    fn accept(
+        listener: Box<dyn Listener>,
+        events: mpsc::SyncSender<PeerEvent>,
+        peers: Peers,
+        running: Arc<AtomicBool>,
+        counters: Arc<Counters>,
+        tokens: HashMap<String, Role>,
+        recorder: Option<Arc<Recorder>>,
+    ) {
+        let mut next_peer = 0;
+        let tokens: Arc<Vec<(String, Role)>> = Arc::new(tokens.into_iter().collect());
+
+        // accepting does not block, so the thread notices the server being dropped
+        while running.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok(Some((stream, addr))) => {
+                    log::info!("Accepted connection from {}", addr);
+
+                    let peer = next_peer;
+                    next_peer += 1;
+
+                    let events = events.clone();
+                    let peers = peers.clone();
+                    let counters = counters.clone();
+                    let tokens = tokens.clone();
+                    let tap = Tap::new(recorder.clone(), Some(peer));
+
+                    std::thread::spawn(move || {
+                        if let Err(e) =
+                            Self::serve(peer, stream, events, peers, counters, &tokens, tap)
+                        {
+                            log::error!("Connection from {} failed: {}", addr, e);
+                        }
+                    });
+                }
+                Ok(None) => std::thread::sleep(LINK_POLL_INTERVAL),
+                Err(e) => {
+                    log::error!("Error accepting connection: {}", e);
+                    std::thread::sleep(LINK_POLL_INTERVAL)
+                }
+            }
+        }
+    }
+
+    fn serve(
+        peer: usize,
+        stream: Box<dyn Stream>,
+        events: mpsc::SyncSender<PeerEvent>,
+        peers: Peers,
+        counters: Arc<Counters>,
+        tokens: &[(String, Role)],
+        tap: Tap,
+    ) -> std::io::Result<()> {
+        let secrets: Vec<String> = tokens.iter().map(|(token, _)| token.clone()).collect();
+
+        let Connection {
+            stream,
+            reader,
+            mut writer,
+            peer: hello,
+            token,
+        } = Connection::establish(stream, &counters, &secrets, true, tap)?;
+        let encoding = writer.encoding;
+        let tap = writer.tap.clone();
+
+        let role = token.map_or(Role::default(), |index| tokens[index].1);
+        log::info!("{} joined as {}", hello.identity(), role);
+
+        let (tx, rx) = mpsc::sync_channel::<Message>(CHANNEL_CAPACITY);
+
+        // tells the peer what was wrong with the messages it sent, if it understands errors
+        let replies = hello
+            .messages
+            .iter()
+            .any(|m| m == "error")
+            .then(|| tx.clone());
+
+        peers.lock().unwrap().insert(
+            peer,
+            Peer {
+                hello,
+                role,
+                tx,
+                stream,
+            },
+        );
+
+        let _ = events.send(PeerEvent::Joined { peer });
+
+        // stops once the peer is removed below, which drops its sender
+        std::thread::spawn(move || {
+            while let Ok(message) = rx.recv() {
+                if writer.write(&message).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let (messages, coalescer) = {
+            let events = events.clone();
+            let peers = peers.clone();
+
+            coalescer(counters, move |message| {
+                // takes effect before the hub gets to push anything in response
+                if let Message::Subscribe { filters } = &message {
+                    if let Some(p) = peers.lock().unwrap().get_mut(&peer) {
+                        p.hello.filters = filters.clone();
+                    }
+                }
+
+                events.send(PeerEvent::Message { peer, message }).is_ok()
+            })
+        };
+
+        let invalid = |error: String| {
+            if let Some(replies) = &replies {
+                let message = format!("invalid message: {}", error);
+                let _ = replies.try_send(Message::Error { id: None, message });
+            }
+        };
+
+        read_messages(reader, encoding, tap, invalid, |message| {
+            messages.send(message).is_ok()
+        });
+        drop(replies);
+
+        // everything the peer sent is handed on before it is reported as gone
+        drop(messages);
+        let _ = coalescer.join();
+
+        if let Some(removed) = peers.lock().unwrap().remove(&peer) {
+            let _ = removed.stream.shutdown();
+        }
+
+        let _ = events.send(PeerEvent::Left { peer });
+
+        Ok(())
+    }
+
+    // Totals over every peer that has connected so far
+    pub fn traffic(&self) -> Traffic {
+        self.counters.traffic()
+    }
+
+    // What a connected peer may do
+    pub fn role(&self, peer: usize) -> Option<Role> {
+        self.peers.lock().unwrap().get(&peer).map(|p| p.role)
+    }
+
+    // Every connected peer along with the hello it introduced itself with
+    pub fn peers(&self) -> Vec<(usize, Hello)> {
+        let peers = self.peers.lock().unwrap();
+        let mut list: Vec<_> = peers.iter().map(|(id, p)| (*id, p.hello.clone())).collect();
+
+        list.sort_by_key(|(id, _)| *id);
+        list
+    }
+
+    // Send a message to one peer, leaving out anything it did not announce support for
+    pub fn send(&self, id: usize, message: Message) {
+        let peers = self.peers.lock().unwrap();
+
+        let Some(peer) = peers.get(&id) else {
+            return;
+        };
+
+        if let Some(message) = filter_for(&peer.hello, message) {
+            self.queue(id, peer, message);
+        }
+    }
+
+    // Send a message to every peer other than `except`
+    pub fn broadcast(&self, except: Option<usize>, message: Message) {
+        let peers = self.peers.lock().unwrap();
+
+        for (id, peer) in peers.iter() {
+            if Some(*id) == except {
+                continue;
+            }
+
+            if let Some(message) = filter_for(&peer.hello, message.clone()) {
+                self.queue(*id, peer, message);
+            }
+        }
+    }
+
+    // Like `broadcast`, for messages naming an object rather than carrying it, which are only
+    // sent to the peers subscribed to it. `objects` holds it along with whatever depends on it
+    pub fn broadcast_about(
+        &self,
+        except: Option<usize>,
+        object_id: &str,
+        objects: &HashMap<ObjectId, Object>,
+        message: Message,
+    ) {
+        let peers = self.peers.lock().unwrap();
+
+        for (id, peer) in peers.iter() {
+            if Some(*id) == except || !peer.hello.subscribes_to(object_id, objects) {
+                continue;
+            }
+
+            if let Some(message) = filter_for(&peer.hello, message.clone()) {
+                self.queue(*id, peer, message);
+            }
+        }
+    }
+
+    // Hands a message to the thread writing to a peer. A peer that stopped reading is not worth
+    // holding up every other one for, so it is disconnected instead, and gets everything pushed
+    // again once it reconnects
+    fn queue(&self, id: usize, peer: &Peer, message: Message) {
+        if let Err(mpsc::TrySendError::Full(_)) = peer.tx.try_send(message) {
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+
+            log::warn!("Peer {} is not keeping up, disconnecting it", id);
+            let _ = peer.stream.shutdown();
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+
+        for peer in self.peers.lock().unwrap().values() {
+            let _ = peer.stream.shutdown();
+        }
+    }
+}
+
+// Exchange `Hello` messages before anything else is sent, so that a peer speaking
+// another protocol version is refused up front instead of failing on every message
+fn handshake(
+    stream: &dyn Stream,
+    writer: &mut impl Write,
+    reader: &mut Reader,
+    tokens: &[String],
+    listening: bool,
+    tap: &Tap,
+) -> std::io::Result<(Hello, Option<usize>)> {
+    let challenge = (!tokens.is_empty()).then(random_hex);
+
+    let hello = Hello {
+        challenge: challenge.clone(),
+        ..Hello::default()
+    };
+
+    // also covers a TLS handshake, which happens along with the first write
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    write_line(writer, &Message::Hello(Box::new(hello)), tap)?;
+
+    let peer = match read_line(reader, tap)? {
+        Some(Message::Hello(hello)) => *hello,
+        _ => {
+            return Err(std::io::Error::other(
+                "peer did not send a hello message, the plugin is likely out of date",
+            ))
+        }
+    };
+
+    if peer.version != PROTOCOL_VERSION {
+        return Err(std::io::Error::other(format!(
+            "{} {} speaks protocol version {}, but binal speaks version {}",
+            peer.tool, peer.tool_version, peer.version, PROTOCOL_VERSION
+        )));
+    }
+
+    let token = match (challenge, &peer.challenge) {
+        (Some(ours), Some(_)) => Some(authenticate(
+            writer, reader, tokens, &ours, &peer, listening, tap,
+        )?),
+        (Some(_), None) => {
+            return Err(std::io::Error::other(format!(
+                "{} is not set up with a token",
+                peer.tool
+            )))
+        }
+        (None, Some(_)) => {
+            return Err(std::io::Error::other(format!(
+                "{} requires a token",
+                peer.tool
+            )))
+        }
+        (None, None) => None,
+    };
+
+    stream.set_read_timeout(None)?;
+
+    log::info!(
+        "Connected to {} {} (objects: {:?}, messages: {:?}, encoding: {}, compression: {})",
+        peer.tool,
+        peer.tool_version,
+        peer.objects,
+        peer.messages,
+        Encoding::negotiate(&peer),
+        Compression::negotiate(&peer)
+    );
+
+    Ok((peer, token))
+}
+
+// Both sides prove they know the token by answering each other's challenge, without it ever
+// being sent; the proof covers both challenges so one side's answer can't be played back to it.
+// A listening side may accept several tokens, so it waits for the peer's proof to tell which
+// one it used before answering. Returns the index of that token
+fn authenticate(
+    writer: &mut impl Write,
+    reader: &mut Reader,
+    tokens: &[String],
+    ours: &str,
+    peer: &Hello,
+    listening: bool,
+    tap: &Tap,
+) -> std::io::Result<usize> {
+    let theirs = peer.challenge.as_deref().unwrap_or_default();
+
+    if ours == theirs {
+        return Err(std::io::Error::other("peer sent back our own challenge"));
+    }
+
+    let keys: Vec<hmac::Key> = tokens
+        .iter()
+        .map(|token| hmac::Key::new(hmac::HMAC_SHA256, token.as_bytes()))
+        .collect();
+
+    let answer = |key: &hmac::Key| {
+        let proof = hmac::sign(key, format!("{}{}", theirs, ours).as_bytes());
+
+        Message::Auth {
+            proof: to_hex(proof.as_ref()),
+        }
+    };
+
+    if !listening {
+        write_line(writer, &answer(&keys[0]), tap)?;
+    }
+
+    let proof = match read_line(reader, tap)? {
+        Some(Message::Auth { proof }) => proof,
+        _ => {
+            return Err(std::io::Error::other(format!(
+                "{} did not answer the token challenge",
+                peer.tool
+            )))
+        }
+    };
+
+    let index = from_hex(&proof).and_then(|proof| {
+        keys.iter().position(|key| {
+            hmac::verify(key, format!("{}{}", ours, theirs).as_bytes(), &proof).is_ok()
+        })
+    });
+
+    let Some(index) = index else {
+        return Err(std::io::Error::other(format!(
+            "{} {} does not know the token",
+            peer.tool, peer.tool_version
+        )));
+    };
+
+    if listening {
+        write_line(writer, &answer(&keys[index]), tap)?;
+    }
+
+    Ok(index)
+}
+
+// Handshake messages are always JSON lines, whatever is negotiated for after them
+fn write_line(writer: &mut impl Write, message: &Message, tap: &Tap) -> std::io::Result<()> {
+    tap.message(Direction::Out, message);
+
+    let mut buffer = serde_json::to_vec(message)?;
+    buffer.push(b'\n');
+
+    writer.write_all(&buffer)?;
+    writer.flush()
+}
+
+fn read_line(reader: &mut Reader, tap: &Tap) -> std::io::Result<Option<Message>> {
+    let mut buffer = Vec::new();
+    reader.read_until(b'\n', &mut buffer)?;
+
+    match serde_json::from_slice(&buffer) {
+        Ok(message) => {
+            tap.message(Direction::In, &message);
+            Ok(Some(message))
+        }
+        Err(e) => {
+            let message = Encoding::Json.decode_any(&buffer);
+            let error = explain(&message, e.to_string());
+
+            // nothing at all is the peer hanging up rather than sending something wrong
+            if !buffer.is_empty() {
+                log::error!("Invalid message during the handshake: {}", error);
+            }
+
+            tap.undecodable(message, error);
+            Ok(None)
+        }
+    }
+}
+
+// Why a message could not be decoded, down to the parts of it that do not match the schema
+fn explain(message: &serde_json::Value, error: String) -> String {
+    let problems = schema::validate(message);
+
+    if problems.is_empty() {
+        error
+    } else {
+        problems.join(", ")
+    }
+}
+
+pub(crate) fn random_hex() -> String {
+    let mut bytes = [0; 32];
+
+    // only fails if the operating system has no randomness to give, which is not recoverable
+    rand::SecureRandom::fill(&rand::SystemRandom::new(), &mut bytes)
+        .expect("could not generate a random challenge");
+
+    to_hex(&bytes)
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+// Strip a message down to what the peer announced support for in its hello
+fn filter_for(peer: &Hello, mut message: Message) -> Option<Message> {
+    if !peer.messages.iter().any(|m| m == message.kind()) {
+        log::warn!(
+            "{} does not support {} messages, not sending",
+            peer.tool,
+            message.kind()
+        );
+        return None;
+    }
+
+    if let Message::Push { id, objects, .. } = &mut message {
+        objects.retain(|_, object| {
+            let supported = peer.objects.iter().any(|k| k == object.kind());
+
+            if !supported {
+                log::warn!(
+                    "{} does not support {} objects, not sending {}",
+                    peer.tool,
+                    object.kind(),
+                    object.name()
+                );
+            }
+
+            supported
+        });
+
+        if !peer.filters.is_empty() {
+            retain_subscribed(&peer.filters, objects);
+
+            // nothing left to say outside of a transaction
+            if objects.is_empty() && id.is_none() {
+                return None;
+            }
+        }
+    }
+
+    Some(message)
+}
+
+// Keep the objects matching any of the filters, along with the types in the same push they need
+fn retain_subscribed(filters: &[Filter], objects: &mut HashMap<ObjectId, Object>) {
+    let keep = subscribed(filters, objects);
+    objects.retain(|id, _| keep.contains(id));
+}
+
+// Ids of the objects matching any of the filters, along with the types among `objects` they need
+fn subscribed(filters: &[Filter], objects: &HashMap<ObjectId, Object>) -> HashSet<ObjectId> {
+    let types: HashMap<&String, &ObjectId> = objects
+        .iter()
+        .filter(|(_, object)| matches!(object, Object::Type { .. }))
+        .map(|(id, object)| (object.name(), id))
+        .collect();
+
+    let mut keep: HashSet<ObjectId> = HashSet::new();
+    let mut pending: Vec<&ObjectId> = objects
+        .iter()
+        .filter(|(_, object)| filters.iter().any(|f| f.matches(object)))
+        .map(|(id, _)| id)
+        .collect();
+
+    while let Some(id) = pending.pop() {
+        if !keep.insert(id.clone()) {
+            continue;
+        }
+
+        for name in objects[id].dependencies() {
+            if let Some(type_id) = types.get(name) {
+                pending.push(type_id);
+            }
+        }
+    }
+
+    keep
+}
+
+// Merges `next` into `pushed` if both are pushes by the same author that do not want an `Ack`,
+// keeping the latest version of each object. Hands `next` back otherwise
+fn merge_push(pushed: &mut Message, next: Message, counters: &Counters) -> Option<Message> {
+    let Message::Push {
+        id: None,
+        objects,
+        author,
+    } = pushed
+    else {
+        return Some(next);
+    };
+
+    match next {
+        Message::Push {
+            id: None,
+            objects: more,
+            author: pushed_by,
+        } if pushed_by == *author => {
+            for (object_id, object) in more {
+                if objects.insert(object_id, object).is_some() {
+                    counters.merged.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+
+            None
+        }
+        next => Some(next),
+    }
+}
+
+// Starts a thread that hands the messages sent to it on to `deliver`, see `coalesce`
+fn coalescer(
+    counters: Arc<Counters>,
+    deliver: impl FnMut(Message) -> bool + Send + 'static,
+) -> (mpsc::SyncSender<Message>, JoinHandle<()>) {
+    let (tx, rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
+    let thread = std::thread::spawn(move || coalesce(rx, &counters, deliver));
+
+    (tx, thread)
+}
+
+// Holds back pushes that do not want an `Ack` for up to `COALESCE_WINDOW`, merging them into one
+// that only has the latest version of each object. Pushes by different authors are not merged,
+// and anything else is handed on as is, right after the pushes that came before it. Runs until
+// `rx` closes or `deliver` returns false
+fn coalesce(
+    rx: mpsc::Receiver<Message>,
+    counters: &Counters,
+    mut deliver: impl FnMut(Message) -> bool,
+) {
+    let mut objects = HashMap::new();
+    let mut author = None;
+    let mut deadline = Instant::now();
+
+    let held =
+        |objects: &mut HashMap<ObjectId, Object>, author: &mut Option<String>| Message::Push {
+            id: None,
+            objects: std::mem::take(objects),
+            author: author.take(),
+        };
+
+    loop {
+        let message = if objects.is_empty() {
+            rx.recv().ok()
+        } else {
+            match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(message) => Some(message),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    deliver(held(&mut objects, &mut author));
+                    return;
+                }
+            }
+        };
+
+        match message {
+            Some(Message::Push {
+                id: None,
+                objects: pushed,
+                author: pushed_by,
+            }) => {
+                if !objects.is_empty()
+                    && pushed_by != author
+                    && !deliver(held(&mut objects, &mut author))
+                {
+                    return;
+                }
+
+                if objects.is_empty() {
+                    deadline = Instant::now() + COALESCE_WINDOW;
+                    author = pushed_by;
+                }
+
+                for (object_id, object) in pushed {
+                    if objects.insert(object_id, object).is_some() {
+                        counters.merged.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            Some(message) => {
+                if !objects.is_empty() && !deliver(held(&mut objects, &mut author)) {
+                    return;
+                }
+
+                if !deliver(message) {
+                    return;
+                }
+            }
+            None if objects.is_empty() => return,
+            None => {
+                if !deliver(held(&mut objects, &mut author)) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Read messages until the stream closes or `deliver` returns false
+// Messages that cannot be decoded are skipped, and what is wrong with them is handed to `invalid`
+fn read_messages(
+    mut reader: Reader,
+    encoding: Encoding,
+    tap: Tap,
+    mut invalid: impl FnMut(String),
+    mut deliver: impl FnMut(Message) -> bool,
+) {
+    let mut buffer = Vec::new();
+
+    loop {
+        buffer.clear();
+
+        match encoding.read_frame(&mut reader, &mut buffer) {
+            Ok(true) => {}
+            Ok(false) => {
+                log::error!("Socket disconnected");
+                return;
+            }
+            Err(e) => {
+                log::error!("Error reading from stream: {}", e);
+                return;
+            }
+        }
+
+        let message = match encoding.decode(&buffer) {
+            Ok(o) => o,
+            Err(e) => {
+                let message = encoding.decode_any(&buffer);
+                let error = explain(&message, e);
+
+                log::error!("Invalid message: {}", error);
+                invalid(error.clone());
+                tap.undecodable(message, error);
+                continue;
+            }
+        };
+
+        tap.message(Direction::In, &message);
+
+        if !deliver(message) {
+            return;
+        }
+    }
+}
@@ -1,318 +1,1661 @@
 use std::{
     collections::{HashMap, HashSet, VecDeque},
-    net::{Ipv4Addr, SocketAddrV4},
     path::PathBuf,
-    str::FromStr,
+    sync::mpsc::TryRecvError,
+    time::Instant,
 };
 
-use eframe::egui::{self, Ui};
-use rfd::FileDialog;
-
 use crate::{
-    ir::{Database, DatabaseError},
-    net::{Client, Message, Object},
+    ir::{Database, DatabaseError, Holder, PushResult},
+    net::{
+        Bucket, Client, ConnectionState, HashIndex, Hello, Message, Object, ObjectId, PeerEvent,
+        Pending, Query, Role, Server,
+    },
 };
 
-#[derive(Default)]
-pub struct OpenProjectMenu {
-    ip_text: String,
-    port_text: String,
+// How many entries the activity feed keeps before dropping the oldest
+const ACTIVITY_LIMIT: usize = 500;
+
+pub enum ProjectKind {
+    Remote(Client),
+    Local(PathBuf),
+    // binal holds the authoritative copy and relays changes between every connected plugin
+    Hub(Server),
+}
+
+pub enum Tab {
+    Types,
+    Functions,
+    Globals,
+    Comments,
+}
+
+pub struct Project {
+    pub name: String,
+
+    pub(crate) selected: HashSet<ObjectId>,
+    #[cfg(feature = "gui")]
+    pub(crate) current_tab: Tab,
+
+    pub(crate) kind: ProjectKind,
+    pub(crate) db: Database,
+    // where the database is saved, if anywhere
+    file: Option<PathBuf>,
+    // whether the database changed since it was last saved
+    unsaved: bool,
+
+    #[cfg(feature = "gui")]
+    pub(crate) pull_text: String,
+    pending: Vec<Pull>,
+    // pushes sent to the remote tool that it has not acknowledged yet, by id
+    unacked: HashMap<u64, HashMap<ObjectId, Object>>,
+    // objects the remote tool refused to apply, with its reason
+    pub(crate) rejected: HashMap<ObjectId, (Object, String)>,
+    // last revision of the remote tool we have pulled changes up to
+    pub(crate) revision: u64,
+    // pushes the remote tool sent since its last `Begin`, or the ones from each peer of a hub
+    transaction: Option<Transaction>,
+    peer_transactions: HashMap<usize, Transaction>,
+
+    // connection generation we last synchronized with
+    generation: u64,
+    // ids of the objects the remote tool has confirmed since the connection dropped, used to
+    // find the ones it removed while we were away
+    pub(crate) resync: Option<HashSet<ObjectId>>,
+    // answered once the remote tool has pushed everything it has after reconnecting
+    resync_barrier: Option<Pending>,
+    // used instead of the two above with tools that can compare content hashes
+    hash_sync: Option<HashSync>,
+
+    // address the remote tool, or each peer of a hub, last reported its cursor at
+    pub(crate) cursor: Option<usize>,
+    pub(crate) peer_cursors: HashMap<usize, usize>,
+    // latest cursor move that has not been passed on to other projects yet
+    moved: Option<usize>,
+    // whether the tools of this project navigate along with the others
+    pub(crate) follow: bool,
+
+    // who each peer of a hub is, as in `Hello::identity`
+    pub(crate) peer_identities: HashMap<usize, String>,
+    pub(crate) activity: ActivityLog,
+    #[cfg(feature = "gui")]
+    pub(crate) show_activity: bool,
 }
 
-impl OpenProjectMenu {
-    pub fn render(
+impl Project {
+    pub fn create(kind: ProjectKind, name: String) -> Result<Self, DatabaseError> {
+        let file = match &kind {
+            ProjectKind::Local(path) => Some(path.clone()),
+            ProjectKind::Remote(_) | ProjectKind::Hub(_) => None,
+        };
+
+        let mut project = Self {
+            name,
+            kind,
+            #[cfg(feature = "gui")]
+            current_tab: Tab::Types,
+            selected: HashSet::new(),
+            db: Database::default(),
+            file: None,
+            unsaved: false,
+            #[cfg(feature = "gui")]
+            pull_text: String::new(),
+            pending: Vec::new(),
+            unacked: HashMap::new(),
+            rejected: HashMap::new(),
+            revision: 0,
+            transaction: None,
+            peer_transactions: HashMap::new(),
+            // the remote tool may have removed objects since the project was saved, and only
+            // sends what differs if it supports hash syncing, so the first connection is
+            // synced like a reconnect
+            generation: u64::MAX,
+            resync: None,
+            resync_barrier: None,
+            hash_sync: None,
+            cursor: None,
+            peer_cursors: HashMap::new(),
+            moved: None,
+            follow: false,
+            peer_identities: HashMap::new(),
+            activity: ActivityLog::default(),
+            #[cfg(feature = "gui")]
+            show_activity: false,
+        };
+
+        if let Some(path) = file {
+            project.persist(path)?;
+        }
+
+        Ok(project)
+    }
+
+    // Keeps the project in a file from now on, starting out with whatever the file holds
+    pub fn persist(&mut self, path: PathBuf) -> Result<(), DatabaseError> {
+        if path.exists() {
+            self.db = Database::open(&path)?;
+        }
+
+        self.file = Some(path);
+        Ok(())
+    }
+
+    pub fn save(&mut self, errors: &mut VecDeque<String>) {
+        let Some(path) = &self.file else { return };
+
+        match self.db.save(path) {
+            Ok(()) => self.unsaved = false,
+            Err(e) => errors.push_back(format!("Could not save project: {}", e)),
+        }
+    }
+
+    pub fn has_unsaved_changes(&self) -> bool {
+        self.unsaved
+    }
+
+    // What the project holds, for driving it without the GUI
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    // Who did what to the project, oldest first, for driving it without the GUI
+    pub fn activity(&self) -> impl Iterator<Item = (&str, &str)> {
+        let entries = self.activity.entries.iter();
+        entries.map(|activity| (activity.who.as_str(), activity.what.as_str()))
+    }
+
+    // Get all objects that are selected at the moment in the project listing
+    // (used for copying to clipboard)
+    pub fn get_selected(&self) -> HashMap<ObjectId, Object> {
+        let mut data = HashMap::new();
+
+        for object_id in &self.selected {
+            data.extend(self.db.object_get_net(object_id));
+        }
+
+        data
+    }
+
+    // Adds objects to the project (used for pasting)
+    // This will send messages over the socket if the project is of kind `Remote`
+    pub fn add_objects(
         &mut self,
-        ui: &mut Ui,
-        projects: &mut Vec<Project>,
+        mut data: HashMap<ObjectId, Object>,
         errors: &mut VecDeque<String>,
-        remain_open: &mut bool,
     ) {
-        if ui.button("Close").clicked() {
-            *remain_open = false
+        let result = self.db.push_net_as(data.clone(), Holder::Local);
+        self.unsaved |= !result.changed.is_empty();
+
+        if !result.rejected.is_empty() {
+            errors.push_back(format!(
+                "Could not add objects: {}",
+                describe_rejected(&result.rejected)
+            ));
         }
 
-        ui.add_space(20.0);
+        data.retain(|object_id, _| !result.rejected.contains_key(object_id));
+        self.push_remote(data);
+    }
 
-        //Open project from file
-        let open = ui.button("Open").clicked();
+    // Sends objects to the remote tool and keeps them around until it acknowledges them
+    pub(crate) fn push_remote(&mut self, data: HashMap<ObjectId, Object>) {
+        let client = match &mut self.kind {
+            ProjectKind::Remote(client) => client,
+            ProjectKind::Hub(server) => {
+                return server.broadcast(
+                    None,
+                    Message::Push {
+                        id: None,
+                        objects: data,
+                        author: None,
+                    },
+                )
+            }
+            ProjectKind::Local(_) => return,
+        };
 
-        ui.add_space(5.0);
+        if let Some(confirmed) = &mut self.resync {
+            confirmed.extend(data.keys().cloned());
+        }
 
-        //Create new project
-        let new = ui.button("New").clicked();
+        if let Some(sync) = &mut self.hash_sync {
+            sync.edited
+                .extend(data.keys().map(|id| self.db.resolve_id(id)));
+        }
 
-        if open || new {
-            let file = if new {
-                FileDialog::new().save_file()
-            } else {
-                FileDialog::new().pick_file()
-            };
+        match client.push(data.clone()) {
+            Ok(Some(id)) => {
+                self.unacked.insert(id, data);
+            }
+            Ok(None) => {}
+            Err(e) => log::error!("Cannot send pasted objects to network thread: {}", e),
+        }
+    }
 
-            let Some(file) = file else { return };
+    // Deletes an object by id from the project
+    // This will send a message over the socket if the project is of kind `Remote`
+    pub fn delete_object(&mut self, object_id: &str, errors: &mut VecDeque<String>) {
+        let result = self.db.check_lock(object_id, Holder::Local);
+        let (resolved, objects) = (
+            self.db.resolve_id(object_id),
+            surroundings(&self.db, object_id),
+        );
 
-            let filename = file.file_name().unwrap().to_string_lossy().to_string();
+        match result.and_then(|()| self.db.delete(object_id)) {
+            Ok(deleted) => self.unsaved |= deleted,
+            Err(e) => {
+                errors.push_back(format!("Could not delete object: {}", e));
+                return;
+            }
+        }
 
-            let project = match Project::create(ProjectKind::Local(file), filename) {
-                Ok(project) => project,
-                Err(e) => {
-                    errors.push_back(format!("Could not open project: {}", e));
-                    return;
+        let message = Message::Delete {
+            object: object_id.to_string(),
+            author: None,
+        };
+
+        match &mut self.kind {
+            ProjectKind::Remote(client) => {
+                if let Some(sync) = &mut self.hash_sync {
+                    sync.edited.insert(self.db.resolve_id(object_id));
                 }
-            };
 
-            projects.push(project);
-            *remain_open = false;
+                if let Err(e) = client.send(message) {
+                    log::error!("Cannot send deleted object to network thread: {}", e);
+                }
+            }
+            ProjectKind::Hub(server) => server.broadcast_about(None, &resolved, &objects, message),
+            ProjectKind::Local(_) => {}
+        }
+    }
+
+    // Asks the remote tool for objects, which are added to the project once the response arrives
+    pub fn pull(&mut self, query: Query, errors: &mut VecDeque<String>) {
+        let ProjectKind::Remote(client) = &mut self.kind else {
+            return;
+        };
+
+        if !client.supports("pull") {
+            errors.push_back(format!(
+                "{} does not support pulling objects",
+                client.peer().tool
+            ));
+            return;
         }
 
-        ui.add_space(15.0);
+        match client.pull(query.clone()) {
+            Ok(pending) => self.pending.push(Pull::new(query, pending)),
+            Err(e) => log::error!("Cannot send pull request to network thread: {}", e),
+        }
+    }
 
-        let ip_label = ui.label("IP Address:");
-        ui.add(egui::TextEdit::singleline(&mut self.ip_text).hint_text("127.0.0.1"))
-            .labelled_by(ip_label.id);
+    // Handle incoming network messages
+    pub fn handle_network_updates(&mut self, errors: &mut VecDeque<String>) {
+        if let ProjectKind::Hub(_) = &self.kind {
+            return self.handle_peer_events();
+        }
 
-        let port_label = ui.label("Port:");
-        ui.add(egui::TextEdit::singleline(&mut self.port_text).hint_text("12007"))
-            .labelled_by(port_label.id);
+        self.check_connection();
 
-        if ui.button("Connect").clicked() {
-            let ip = Ipv4Addr::from_str(&self.ip_text).unwrap_or(Ipv4Addr::LOCALHOST);
-            let port = u16::from_str(&self.port_text).unwrap_or(12007);
+        if let ProjectKind::Remote(client) = &self.kind {
+            if let Err(e) = client.flush() {
+                log::error!("Cannot send held back objects to network thread: {}", e);
+            }
+        }
 
-            let client = match Client::connect(SocketAddrV4::new(ip, port)) {
-                Ok(client) => client,
-                Err(e) => {
-                    errors.push_back(format!("Could not connect: {}", e));
-                    return;
+        // checked before draining messages, so every push the tool sent ahead of the
+        // barrier reply has been applied by the time we reconcile
+        let resynced = match &self.resync_barrier {
+            Some(barrier) => match barrier.rx.try_recv() {
+                Ok(_) => true,
+                Err(TryRecvError::Empty) => false,
+                Err(TryRecvError::Disconnected) => {
+                    // dropped again, the next connection starts over
+                    self.resync_barrier = None;
+                    false
                 }
+            },
+            None => false,
+        };
+
+        loop {
+            let ProjectKind::Remote(client) = &mut self.kind else {
+                return;
             };
 
-            let project_name = if self.ip_text.is_empty() {
-                String::from("127.0.0.1")
-            } else {
-                std::mem::take(&mut self.ip_text)
+            let Ok(message) = client.rx.try_recv() else {
+                break;
             };
 
-            let project = match Project::create(ProjectKind::Remote(client), project_name) {
-                Ok(project) => project,
-                Err(e) => {
-                    errors.push_back(format!("Could not create project: {}", e));
-                    return;
+            // pushes that are ready to be applied, either on their own or as a whole transaction
+            let mut finished = None;
+
+            match message {
+                Message::Hello(_) | Message::Auth { .. } => {
+                    log::warn!("Ignoring {} sent after the handshake", message.kind())
+                }
+                Message::Begin { id } => {
+                    if let Some(previous) = self.transaction.replace(Transaction::begin(id)) {
+                        log::warn!(
+                            "{} began transaction {} inside transaction {}, discarding that one",
+                            client.peer().tool,
+                            id,
+                            previous.id.unwrap_or_default()
+                        );
+
+                        for reply in previous.abort() {
+                            if let Err(e) = client.send(reply) {
+                                log::error!("Cannot send acknowledgement to network thread: {}", e);
+                            }
+                        }
+                    }
+                }
+                Message::Commit { id } | Message::Abort { id }
+                    if self.transaction.as_ref().and_then(|t| t.id) != Some(id) =>
+                {
+                    log::warn!("Ignoring {} of unknown transaction {}", message.kind(), id)
+                }
+                Message::Commit { .. } => finished = self.transaction.take(),
+                Message::Abort { id } => {
+                    let transaction = self.transaction.take().unwrap();
+                    log::info!("{} aborted transaction {}", client.peer().tool, id);
+
+                    for reply in transaction.abort() {
+                        if let Err(e) = client.send(reply) {
+                            log::error!("Cannot send acknowledgement to network thread: {}", e);
+                        }
+                    }
+                }
+                Message::Push {
+                    id,
+                    objects,
+                    author,
+                } => {
+                    let transaction = self.transaction.get_or_insert_with(Transaction::default);
+                    transaction.add(id, objects, author);
+
+                    // outside of a transaction, every push is applied on its own
+                    if transaction.id.is_none() {
+                        finished = self.transaction.take();
+                    }
+                }
+                Message::Delete { object, author } => {
+                    let name = self.db.name(&object).cloned().unwrap_or(object.clone());
+                    let resolved = self.db.resolve_id(&object);
+
+                    match self.db.delete(&object) {
+                        Ok(deleted) => {
+                            self.unsaved |= deleted;
+                            Pull::changed(&mut self.pending, [resolved]);
+
+                            if deleted {
+                                let who = author.unwrap_or_else(|| client.peer().identity());
+                                self.activity.record(who, format!("deleted {}", name));
+                            }
+                        }
+                        Err(e) => errors.push_back(format!("Could not apply remote delete: {}", e)),
+                    }
+                }
+                Message::Rename {
+                    kind,
+                    old,
+                    new,
+                    author,
+                } => match self.db.rename(&kind, &old, &new) {
+                    Ok(renamed) => {
+                        if let (Some(object_id), Some(confirmed)) = (&renamed, &mut self.resync) {
+                            confirmed.insert(object_id.clone());
+                        }
+
+                        Pull::changed(&mut self.pending, renamed.iter().cloned());
+
+                        self.unsaved |= renamed.is_some();
+
+                        if renamed.is_some() {
+                            let who = author.unwrap_or_else(|| client.peer().identity());
+                            self.activity
+                                .record(who, format!("renamed {} {} to {}", kind, old, new));
+                        }
+                    }
+                    Err(e) => errors.push_back(format!("Could not apply remote rename: {}", e)),
+                },
+                // a tool echoing back where we sent it is not a move of its own
+                Message::Cursor { address } if self.cursor == Some(address) => {}
+                Message::Cursor { address } => {
+                    self.cursor = Some(address);
+                    self.moved = Some(address);
+                }
+                Message::Goto { .. } => {
+                    log::warn!("Ignoring goto from {}", client.peer().tool)
+                }
+                Message::Lock { .. } | Message::Unlock { .. } => {
+                    log::warn!(
+                        "Ignoring {} from {}, only hubs keep locks",
+                        message.kind(),
+                        client.peer().tool
+                    )
+                }
+                // the filters are already in place, so this resends whatever they now let through
+                Message::Subscribe { filters } => {
+                    log::info!(
+                        "{} subscribed with {} filters",
+                        client.peer().tool,
+                        filters.len()
+                    );
+
+                    let objects = self.db.get_net();
+
+                    if !objects.is_empty() {
+                        let push = Message::Push {
+                            id: None,
+                            objects,
+                            author: None,
+                        };
+
+                        if let Err(e) = client.send(push) {
+                            log::error!("Cannot send push to network thread: {}", e);
+                        }
+                    }
+                }
+                Message::Pull { id, query } => {
+                    if let Err(e) = client.send(Self::pull_reply(&self.db, id, query)) {
+                        log::error!("Cannot send pull response to network thread: {}", e);
+                    }
+                }
+                Message::Reject { id, rejected } => {
+                    let Some(unacked) = self.unacked.get_mut(&id) else {
+                        continue;
+                    };
+
+                    for (object_id, reason) in rejected {
+                        if let Some(object) = unacked.remove(&object_id) {
+                            self.rejected.insert(object_id, (object, reason));
+                        }
+                    }
+                }
+                Message::Ack { id, .. } => {
+                    self.unacked.remove(&id);
                 }
+                Message::Error { id: None, message } => errors.push_back(format!(
+                    "{} refused a change: {}",
+                    client.peer().tool,
+                    message
+                )),
+                Message::Response { id, .. }
+                | Message::Error { id: Some(id), .. }
+                | Message::Summary { id, .. }
+                | Message::Hashes { id, .. } => {
+                    log::warn!("Ignoring reply to unknown request {}", id)
+                }
+            }
+
+            let Some(transaction) = finished else {
+                continue;
             };
 
-            projects.push(project);
-            *remain_open = false
+            let result = transaction.apply(&mut self.db);
+
+            // after pushing, since a type can turn out to be one we know by another id
+            if let Some(confirmed) = &mut self.resync {
+                let pushed = transaction.objects.keys();
+                confirmed.extend(pushed.map(|id| self.db.resolve_id(id)));
+            }
+
+            self.unsaved |= !result.changed.is_empty();
+
+            let changed = result.changed.iter().map(|id| self.db.resolve_id(id));
+            Pull::changed(&mut self.pending, changed);
+
+            if !result.changed.is_empty() {
+                let who = transaction.author.clone();
+                let who = who.unwrap_or_else(|| client.peer().identity());
+                let what = describe_changed(&self.db, &result.changed);
+
+                self.activity.record(who, what);
+            }
+
+            if transaction.acks.is_empty() && !result.rejected.is_empty() {
+                log::warn!(
+                    "Rejected pushed objects: {}",
+                    describe_rejected(&result.rejected)
+                );
+            }
+
+            for reply in transaction.acknowledgements(&result.rejected) {
+                if let Err(e) = client.send(reply) {
+                    log::error!("Cannot send acknowledgement to network thread: {}", e);
+                }
+            }
         }
-    }
-}
 
-pub enum ProjectKind {
-    Remote(Client),
-    Local(PathBuf),
-}
+        for pull in std::mem::take(&mut self.pending) {
+            let Pull {
+                query,
+                pending,
+                changed,
+            } = &pull;
 
-enum Tab {
-    Types,
-    Functions,
-    Globals,
-}
+            match pending.rx.try_recv() {
+                Ok(Message::Response {
+                    revision,
+                    mut objects,
+                    ..
+                }) => {
+                    if let Query::Changed { .. } = query {
+                        self.revision = revision;
+                    }
 
-pub struct Project {
-    pub name: String,
+                    objects
+                        .retain(|object_id, _| !changed.contains(&self.db.resolve_id(object_id)));
 
-    selected: HashSet<String>,
-    current_tab: Tab,
+                    let result = self.db.push_net(objects);
+                    let rejected = result.rejected;
 
-    kind: ProjectKind,
-    db: Database,
-}
+                    self.unsaved |= !result.changed.is_empty();
 
-impl Project {
-    pub fn create(kind: ProjectKind, name: String) -> Result<Self, DatabaseError> {
-        let data = match &kind {
-            ProjectKind::Remote(_) => Database::default(),
-            ProjectKind::Local(path) => {
-                let data = if path.exists() {
-                    Database::open(&path)?
-                } else {
-                    Database::default()
-                };
+                    if !rejected.is_empty() {
+                        errors.push_back(format!(
+                            "Could not add pulled objects: {}",
+                            describe_rejected(&rejected)
+                        ));
+                    }
+                }
+                Ok(Message::Error { message, .. }) => {
+                    errors.push_back(format!("Pull request failed: {}", message))
+                }
+                Ok(Message::Summary { buckets, .. }) => self.compare_summary(buckets, errors),
+                Ok(Message::Hashes { hashes, .. }) => {
+                    if let Query::Hashes { object, start } = query {
+                        self.compare_hashes(object, *start, hashes, errors)
+                    }
+                }
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => self.pending.push(pull),
+                Err(TryRecvError::Disconnected) => errors.push_back(format!(
+                    "Connection closed before pull request {} was answered",
+                    pending.id
+                )),
+            }
+        }
 
-                data
+        if resynced {
+            self.resync_barrier = None;
+
+            if let Some(confirmed) = self.resync.take() {
+                self.reconcile(confirmed, errors);
             }
+        }
+
+        // stale objects go last, once nothing that was pulled still refers to them
+        let syncing = self.pending.iter().any(|pull| {
+            matches!(
+                pull.query,
+                Query::Summary | Query::Hashes { .. } | Query::Objects { .. }
+            )
+        });
+
+        if !syncing {
+            if let Some(mut sync) = self.hash_sync.take() {
+                sync.stale
+                    .retain(|object_id| !sync.edited.contains(object_id));
+                self.remove_stale(sync.stale, errors);
+            }
+        }
+    }
+
+    // Compares the remote tool's summary with ours, asking for the hashes of every object in the
+    // buckets that differ, and marking everything in the buckets it no longer has as stale
+    fn compare_summary(&mut self, buckets: Vec<Bucket>, errors: &mut VecDeque<String>) {
+        let local = HashIndex::new(&self.db.get_net());
+        let summary = local.summary();
+        let mut stale = Vec::new();
+        let synced = self.synced_kinds();
+
+        for bucket in &summary {
+            if !synced.contains(&bucket.object) {
+                continue;
+            }
+
+            let remote = buckets
+                .iter()
+                .any(|b| b.object == bucket.object && b.start == bucket.start);
+
+            if let (false, Some(hashes)) = (remote, local.bucket(&bucket.object, bucket.start)) {
+                stale.extend(hashes.keys().cloned());
+            }
+        }
+
+        let differing: Vec<Bucket> = buckets
+            .into_iter()
+            .filter(|bucket| !summary.contains(bucket))
+            .collect();
+
+        log::info!(
+            "{} buckets differ from the remote tool's, {} objects are gone",
+            differing.len(),
+            stale.len()
+        );
+
+        // started when the summary was asked for, with whatever was edited since
+        let sync = self.hash_sync.get_or_insert_with(HashSync::default);
+        sync.local = local;
+        sync.stale = stale;
+
+        for bucket in differing {
+            let query = Query::Hashes {
+                object: bucket.object,
+                start: bucket.start,
+            };
+
+            self.pull(query, errors);
+        }
+    }
+
+    // Pulls the objects of a bucket whose hashes differ from ours, and marks the ones the remote
+    // tool does not have as stale
+    fn compare_hashes(
+        &mut self,
+        kind: &str,
+        start: u64,
+        hashes: HashMap<ObjectId, String>,
+        errors: &mut VecDeque<String>,
+    ) {
+        let Some(sync) = &mut self.hash_sync else {
+            return;
         };
 
-        Ok(Self {
-            name,
-            kind,
-            current_tab: Tab::Types,
-            selected: HashSet::new(),
-            db: data,
-        })
+        // the remote tool may know a type by an id we merged into another. What was edited here
+        // meanwhile is newer than what it has, so that is not pulled
+        let hashes: HashMap<ObjectId, (ObjectId, String)> = hashes
+            .into_iter()
+            .map(|(object_id, hash)| (self.db.resolve_id(&object_id), (object_id, hash)))
+            .filter(|(resolved, _)| !sync.edited.contains(resolved))
+            .collect();
+
+        let mut ids = Vec::new();
+
+        match sync.local.bucket(kind, start) {
+            Some(local) => {
+                let gone = local.keys().filter(|id| !hashes.contains_key(*id));
+                sync.stale.extend(gone.cloned());
+
+                for (resolved, (object_id, hash)) in hashes {
+                    if local.get(&resolved) != Some(&hash) {
+                        ids.push(object_id);
+                    }
+                }
+            }
+            None => ids.extend(hashes.into_values().map(|(object_id, _)| object_id)),
+        }
+
+        if !ids.is_empty() {
+            self.pull(Query::Objects { ids }, errors);
+        }
     }
 
-    pub fn save(&self, errors: &mut VecDeque<String>) {
-        let result = match &self.kind {
-            ProjectKind::Local(path) => self.db.save(&path),
-            ProjectKind::Remote(_) => Ok(()),
+    // Notices the connection dropping and coming back, and starts resyncing once it does
+    fn check_connection(&mut self) {
+        let ProjectKind::Remote(client) = &mut self.kind else {
+            return;
         };
 
-        let Err(e) = result else { return };
-        errors.push_back(format!("Could not save project: {}", e))
+        let reconnecting = matches!(client.state(), ConnectionState::Reconnecting { .. });
+        let generation = client.generation();
+
+        if reconnecting {
+            self.resync.get_or_insert_with(HashSet::new);
+            return;
+        }
+
+        if generation == self.generation {
+            return;
+        }
+
+        self.generation = generation;
+        self.resync.get_or_insert_with(HashSet::new);
+
+        // pushes sent before the connection dropped will never be acknowledged, and the
+        // tool counts revisions from scratch for every connection
+        self.unacked.clear();
+        self.revision = 0;
+
+        if !client.supports("pull") {
+            log::warn!(
+                "{} cannot be resynced without pull support",
+                client.peer().tool
+            );
+            self.resync = None;
+            return;
+        }
+
+        // the tool holds back its objects for us to pull the ones that differ
+        if client.supports("summary") {
+            self.resync = None;
+            self.hash_sync = Some(HashSync::default());
+
+            match client.pull(Query::Summary) {
+                Ok(pending) => self.pending.push(Pull::new(Query::Summary, pending)),
+                Err(e) => log::error!("Cannot send summary request to network thread: {}", e),
+            }
+
+            return;
+        }
+
+        // the tool pushes everything it has when a connection is made and answers messages in
+        // order, so the reply to this pull only arrives after all of those pushes
+        match client.pull(Query::Changed { since: u64::MAX }) {
+            Ok(pending) => self.resync_barrier = Some(pending),
+            Err(e) => log::error!("Cannot send resync request to network thread: {}", e),
+        }
     }
 
-    // Get all objects that are selected at the moment in the project listing
-    // (used for copying to clipboard)
-    pub fn get_selected(&self) -> HashMap<String, Object> {
-        let mut data = HashMap::new();
+    // Removes the objects the remote tool no longer has after reconnecting, leaving everything
+    // else in place so ids and the selection survive
+    fn reconcile(&mut self, confirmed: HashSet<ObjectId>, errors: &mut VecDeque<String>) {
+        let synced = self.synced_kinds();
+        let comments = synced.iter().any(|kind| kind == "comment");
 
-        for name in &self.selected {
-            let objects = match self.current_tab {
-                Tab::Types => self.db.types_get_net(name),
-                Tab::Functions => self.db.functions_get_net(name),
-                Tab::Globals => self.db.globals_get_net(name),
-            };
+        let stale = self
+            .db
+            .types
+            .iter()
+            .map(|t| &t.object_id)
+            .chain(self.db.functions.iter().map(|f| &f.object_id))
+            .chain(self.db.data.iter().map(|d| &d.object_id))
+            .chain(
+                self.db
+                    .comments
+                    .iter()
+                    .filter(|_| comments)
+                    .map(|c| &c.object_id),
+            )
+            .filter(|object_id| !confirmed.contains(*object_id))
+            .cloned()
+            .collect();
+
+        self.remove_stale(stale, errors);
+    }
 
-            data.extend(objects);
+    // Kinds of objects the remote tool syncs, the others it would never confirm having
+    fn synced_kinds(&self) -> Vec<String> {
+        match &self.kind {
+            ProjectKind::Remote(client) => client.peer().objects,
+            _ => Vec::new(),
         }
+    }
 
-        data
+    // Deletes objects the remote tool no longer has, keeping the ones that are still referenced
+    fn remove_stale(&mut self, mut stale: Vec<ObjectId>, errors: &mut VecDeque<String>) {
+        // a type can only go once nothing refers to it, so keep going while deleting frees some up
+        loop {
+            let count = stale.len();
+            stale.retain(|object_id| match self.db.delete(object_id) {
+                Ok(deleted) => {
+                    self.unsaved |= deleted;
+                    false
+                }
+                Err(_) => true,
+            });
+
+            if stale.len() == count {
+                break;
+            }
+        }
+
+        if !stale.is_empty() {
+            errors.push_back(format!(
+                "Kept objects removed by the remote tool that are still referenced: {}",
+                stale
+                    .iter()
+                    .filter_map(|object_id| self.db.name(object_id))
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
     }
 
-    // Adds objects to the project (used for pasting)
-    // This will send messages over the socket if the project is of kind `Remote`
-    pub fn add_objects(&mut self, data: HashMap<String, Object>) {
-        self.db.push_net(data.clone());
+    // Apply what the plugins connected to the hub send, and pass every change on to the others
+    fn handle_peer_events(&mut self) {
+        let ProjectKind::Hub(server) = &self.kind else {
+            return;
+        };
+
+        while let Ok(event) = server.rx.try_recv() {
+            let (peer, message) = match event {
+                PeerEvent::Joined { peer } => {
+                    let hello = server
+                        .peers()
+                        .into_iter()
+                        .find(|(id, _)| *id == peer)
+                        .map(|(_, hello)| hello);
+                    let identity = match &hello {
+                        Some(hello) => hello.identity(),
+                        None => format!("Peer {}", peer),
+                    };
+
+                    self.activity
+                        .record(identity.clone(), String::from("joined"));
+                    self.peer_identities.insert(peer, identity);
 
-        if let ProjectKind::Remote(client) = &mut self.kind {
-            let result = client.tx.send(Message::Push { objects: data });
+                    // bring the new plugin up to date with everything the others have sent
+                    let objects = self.db.get_net();
 
-            if let Err(e) = result {
-                log::error!("Cannot send pasted objects to network thread: {}", e);
+                    if !objects.is_empty() {
+                        server.send(
+                            peer,
+                            Message::Push {
+                                id: None,
+                                objects,
+                                author: None,
+                            },
+                        );
+                    }
+
+                    let hello = hello.unwrap_or_default();
+
+                    for (object, lock) in self.db.locks() {
+                        if !hello.subscribes_to(object, &surroundings(&self.db, object)) {
+                            continue;
+                        }
+
+                        let (object, holder) = (object.clone(), Some(lock.who.clone()));
+                        server.send(peer, Message::Lock { object, holder });
+                    }
+
+                    continue;
+                }
+                PeerEvent::Left { peer } => {
+                    self.peer_cursors.remove(&peer);
+
+                    let who = identity(&self.peer_identities, peer);
+
+                    for object in self.db.release(Holder::Peer(peer)) {
+                        let objects = surroundings(&self.db, &object);
+                        let message = Message::Unlock {
+                            object: object.clone(),
+                            author: Some(who.clone()),
+                        };
+                        server.broadcast_about(None, &object, &objects, message);
+                    }
+
+                    if let Some(identity) = self.peer_identities.remove(&peer) {
+                        self.activity.record(identity, String::from("left"));
+                    }
+
+                    if let Some(transaction) = self.peer_transactions.remove(&peer) {
+                        log::warn!(
+                            "Discarding {} objects of an unfinished transaction from peer {}",
+                            transaction.objects.len(),
+                            peer
+                        );
+                    }
+
+                    continue;
+                }
+                PeerEvent::Message { peer, message } => (peer, message),
+            };
+
+            let role = server.role(peer).unwrap_or_default();
+
+            let changes = matches!(
+                message,
+                Message::Push { .. }
+                    | Message::Delete { .. }
+                    | Message::Rename { .. }
+                    | Message::Lock { .. }
+                    | Message::Unlock { .. }
+            );
+
+            if changes && role == Role::ReadOnly {
+                Self::refuse(server, &self.db, peer, message);
+                continue;
+            }
+
+            let mut finished = None;
+
+            match message {
+                Message::Hello(_) | Message::Auth { .. } => {
+                    log::warn!("Ignoring {} sent after the handshake", message.kind())
+                }
+                Message::Begin { id } => {
+                    let transaction = Transaction::begin(id);
+
+                    if let Some(previous) = self.peer_transactions.insert(peer, transaction) {
+                        log::warn!(
+                            "Peer {} began transaction {} inside transaction {}, discarding that one",
+                            peer,
+                            id,
+                            previous.id.unwrap_or_default()
+                        );
+
+                        for reply in previous.abort() {
+                            server.send(peer, reply);
+                        }
+                    }
+                }
+                Message::Commit { id } | Message::Abort { id }
+                    if self.peer_transactions.get(&peer).and_then(|t| t.id) != Some(id) =>
+                {
+                    log::warn!(
+                        "Ignoring {} of unknown transaction {} from peer {}",
+                        message.kind(),
+                        id,
+                        peer
+                    )
+                }
+                Message::Commit { .. } => finished = self.peer_transactions.remove(&peer),
+                Message::Abort { id } => {
+                    let transaction = self.peer_transactions.remove(&peer).unwrap();
+                    log::info!("Peer {} aborted transaction {}", peer, id);
+
+                    for reply in transaction.abort() {
+                        server.send(peer, reply);
+                    }
+                }
+                Message::Push {
+                    id,
+                    objects,
+                    author,
+                } => {
+                    let transaction = self.peer_transactions.entry(peer).or_default();
+                    transaction.add(id, objects, author);
+
+                    // outside of a transaction, every push is applied on its own
+                    if transaction.id.is_none() {
+                        finished = self.peer_transactions.remove(&peer);
+                    }
+                }
+                Message::Delete { object, .. } => {
+                    let name = self.db.name(&object).cloned().unwrap_or(object.clone());
+                    let result = self.db.check_lock(&object, Holder::Peer(peer));
+                    let objects = surroundings(&self.db, &object);
+
+                    match result.and_then(|()| self.db.delete(&object)) {
+                        Ok(true) => {
+                            self.unsaved = true;
+
+                            let who = identity(&self.peer_identities, peer);
+                            self.activity
+                                .record(who.clone(), format!("deleted {}", name));
+
+                            let object = self.db.resolve_id(&object);
+                            let message = Message::Delete {
+                                object: object.clone(),
+                                author: Some(who),
+                            };
+                            server.broadcast_about(Some(peer), &object, &objects, message)
+                        }
+                        Ok(false) => {}
+                        Err(e) => {
+                            log::warn!("Could not apply delete from peer {}: {}", peer, e);
+
+                            // the peer removed it anyway, so hand it back since the others still
+                            // use it or someone else holds it
+                            hand_back(server, &self.db, peer, &[object]);
+                        }
+                    }
+                }
+                Message::Rename {
+                    kind,
+                    old,
+                    new,
+                    author: _,
+                } => {
+                    let before = self
+                        .db
+                        .find(&kind, &old)
+                        .map(|id| surroundings(&self.db, &id));
+
+                    match self.db.rename_as(&kind, &old, &new, Holder::Peer(peer)) {
+                        Ok(Some(object_id)) => {
+                            self.unsaved = true;
+
+                            let who = identity(&self.peer_identities, peer);
+                            self.activity.record(
+                                who.clone(),
+                                format!("renamed {} {} to {}", kind, old, new),
+                            );
+
+                            let objects = self.db.object_get_net(&object_id);
+                            let before = before.unwrap_or_default();
+                            let after = surroundings(&self.db, &object_id);
+
+                            for (other, hello) in server.peers() {
+                                if other == peer {
+                                    continue;
+                                }
+
+                                let had = hello.subscribes_to(&object_id, &before);
+
+                                if !had && !hello.subscribes_to(&object_id, &after) {
+                                    continue;
+                                }
+
+                                let author = Some(who.clone());
+
+                                // peers that only subscribed to the new name never got the object
+                                if had && hello.messages.iter().any(|m| m == "rename") {
+                                    let (kind, old, new) = (kind.clone(), old.clone(), new.clone());
+                                    server.send(
+                                        other,
+                                        Message::Rename {
+                                            kind,
+                                            old,
+                                            new,
+                                            author,
+                                        },
+                                    );
+                                } else {
+                                    // the id stays the same, so pushing it under its new name is enough
+                                    // for plugins without rename support
+                                    let objects = objects.clone();
+                                    server.send(
+                                        other,
+                                        Message::Push {
+                                            id: None,
+                                            objects,
+                                            author,
+                                        },
+                                    );
+                                }
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            log::warn!("Could not apply rename from peer {}: {}", peer, e);
+
+                            let message = format!("could not rename {} to {}: {}", old, new, e);
+                            server.send(peer, Message::Error { id: None, message });
+
+                            // the peer renamed its copy anyway, so hand it back under the old name
+                            if let Some(object_id) = self.db.find(&kind, &old) {
+                                hand_back(server, &self.db, peer, &[object_id]);
+                            }
+                        }
+                    }
+                }
+                Message::Cursor { address } if self.peer_cursors.get(&peer) == Some(&address) => {}
+                Message::Cursor { address } => {
+                    self.peer_cursors.insert(peer, address);
+                    self.moved = Some(address);
+
+                    if self.follow {
+                        Self::goto_peers(server, &mut self.peer_cursors, Some(peer), address);
+                    }
+                }
+                Message::Lock { object, .. } => {
+                    let object = self.db.resolve_id(&object);
+                    let who = identity(&self.peer_identities, peer);
+
+                    match self.db.lock(&object, Holder::Peer(peer), who.clone()) {
+                        Ok(locked) => {
+                            if locked {
+                                let name = self.db.name(&object).cloned().unwrap_or_default();
+                                self.activity
+                                    .record(who.clone(), format!("locked {}", name));
+                            }
+
+                            // the peer itself included, which tells it the lock is its own
+                            let objects = surroundings(&self.db, &object);
+                            let message = Message::Lock {
+                                object: object.clone(),
+                                holder: Some(who),
+                            };
+                            server.broadcast_about(None, &object, &objects, message);
+                        }
+                        Err(e) => {
+                            log::warn!("Could not lock {} for peer {}: {}", object, peer, e);
+
+                            // tell the peer who does hold it, if anyone
+                            let reply = match self.db.lock_of(&object) {
+                                Some(lock) => Message::Lock {
+                                    object,
+                                    holder: Some(lock.who.clone()),
+                                },
+                                None => Message::Unlock {
+                                    object,
+                                    author: None,
+                                },
+                            };
+
+                            server.send(peer, reply);
+                        }
+                    }
+                }
+                Message::Unlock { object, .. } => {
+                    let object = self.db.resolve_id(&object);
+
+                    // admins can release anyone's lock
+                    let holder = match self.db.lock_of(&object) {
+                        Some(lock) if role == Role::Admin => lock.holder,
+                        _ => Holder::Peer(peer),
+                    };
+
+                    match self.db.unlock(&object, holder) {
+                        Ok(true) => {
+                            let who = identity(&self.peer_identities, peer);
+                            let name = self.db.name(&object).cloned().unwrap_or_default();
+                            self.activity
+                                .record(who.clone(), format!("unlocked {}", name));
+
+                            let objects = surroundings(&self.db, &object);
+                            let message = Message::Unlock {
+                                object: object.clone(),
+                                author: Some(who),
+                            };
+                            server.broadcast_about(None, &object, &objects, message);
+                        }
+                        Ok(false) => {}
+                        Err(e) => {
+                            log::warn!("Could not unlock {} for peer {}: {}", object, peer, e)
+                        }
+                    }
+                }
+                // the server already swapped in the filters, so this resends whatever they let through
+                Message::Subscribe { filters } => {
+                    log::info!("Peer {} subscribed with {} filters", peer, filters.len());
+
+                    let objects = self.db.get_net();
+
+                    if !objects.is_empty() {
+                        server.send(
+                            peer,
+                            Message::Push {
+                                id: None,
+                                objects,
+                                author: None,
+                            },
+                        );
+                    }
+                }
+                Message::Pull { id, query } => {
+                    server.send(peer, Self::pull_reply(&self.db, id, query))
+                }
+                message => log::warn!("Ignoring {} message from peer {}", message.kind(), peer),
+            }
+
+            let Some(transaction) = finished else {
+                continue;
+            };
+
+            let result = transaction.apply_as(&mut self.db, Holder::Peer(peer));
+
+            self.unsaved |= !result.changed.is_empty();
+
+            // the peer already changed its copy of what someone else holds, so hand that back
+            let locked: Vec<ObjectId> = result
+                .rejected
+                .keys()
+                .filter(|object_id| self.db.check_lock(object_id, Holder::Peer(peer)).is_err())
+                .cloned()
+                .collect();
+
+            hand_back(server, &self.db, peer, &locked);
+
+            // whoever the peer says made the change, it is passed on as made by the peer it
+            // authenticated as
+            let who = identity(&self.peer_identities, peer);
+
+            if !result.changed.is_empty() {
+                let what = describe_changed(&self.db, &result.changed);
+                self.activity.record(who.clone(), what);
+            }
+
+            for reply in transaction.acknowledgements(&result.rejected) {
+                server.send(peer, reply);
+            }
+
+            if !result.rejected.is_empty() {
+                log::warn!(
+                    "Rejected objects pushed by peer {}: {}",
+                    peer,
+                    describe_rejected(&result.rejected)
+                );
+            }
+
+            // only pass on what actually changed, so a plugin echoing back an update
+            // does not bounce between the others forever. The others get it under the id
+            // we keep it as, in case the peer knew it by another one
+            let objects: HashMap<ObjectId, Object> = transaction
+                .objects
+                .into_iter()
+                .filter(|(object_id, _)| result.changed.contains(object_id))
+                .map(|(object_id, object)| (self.db.resolve_id(&object_id), object))
+                .collect();
+
+            if !objects.is_empty() {
+                server.broadcast(
+                    Some(peer),
+                    Message::Push {
+                        id: None,
+                        objects,
+                        author: Some(who),
+                    },
+                );
             }
         }
     }
 
-    // Delets an object by name from the project
-    pub fn delete_object(&mut self, name: &str) {
-        if let ProjectKind::Remote(_) = &self.kind {
+    // The latest address a tool of this project moved its cursor to, if it moved since the last
+    // call, for passing on to the other projects
+    pub fn take_moved(&mut self) -> Option<usize> {
+        self.moved.take()
+    }
+
+    // Like the "Follow" toggle, for driving the project without the GUI
+    pub fn set_follow(&mut self, follow: bool) {
+        self.follow = follow;
+    }
+
+    // Sends the tools of this project to an address, if they follow along
+    pub fn goto(&mut self, address: usize) {
+        if !self.follow {
             return;
         }
 
-        match self.current_tab {
-            Tab::Types => self.db.types.delete(name),
-            Tab::Functions => self.db.functions.delete(name),
-            Tab::Globals => self.db.data.delete(name),
-        };
+        match &self.kind {
+            ProjectKind::Remote(client) if client.supports("goto") => {
+                if self.cursor == Some(address) {
+                    return;
+                }
+
+                // so the cursor report that follows is not taken for a move of its own
+                self.cursor = Some(address);
+
+                if let Err(e) = client.send(Message::Goto { address }) {
+                    log::error!("Cannot send goto to network thread: {}", e);
+                }
+            }
+            ProjectKind::Hub(server) => {
+                Self::goto_peers(server, &mut self.peer_cursors, None, address)
+            }
+            _ => {}
+        }
     }
 
-    // Render the project UI and handle input
-    pub fn render(
-        &mut self,
-        ui: &mut Ui,
-        errors: &mut VecDeque<String>,
-        clipboard: &mut HashMap<String, Object>,
+    fn goto_peers(
+        server: &Server,
+        cursors: &mut HashMap<usize, usize>,
+        except: Option<usize>,
+        address: usize,
     ) {
-        if ui.input(|i| i.modifiers.ctrl && i.key_released(egui::Key::S)) {
-            self.save(errors);
-        }
+        for (peer, hello) in server.peers() {
+            let supported = hello.messages.iter().any(|m| m == "goto");
 
-        if ui.input(|i| i.events.iter().any(|e| matches!(e, egui::Event::Paste(_)))) {
-            self.add_objects(clipboard.clone())
+            if Some(peer) == except || !supported || cursors.get(&peer) == Some(&address) {
+                continue;
+            }
+
+            cursors.insert(peer, address);
+            server.send(peer, Message::Goto { address });
         }
+    }
+
+    // Claims the selected objects for whoever uses binal, so the peers of the hub cannot change
+    // them until they are unlocked
+    pub fn lock_selected(&mut self, errors: &mut VecDeque<String>) {
+        let ProjectKind::Hub(server) = &self.kind else {
+            return;
+        };
+
+        let who = Hello::default().identity();
+
+        for object_id in &self.selected {
+            let object = self.db.resolve_id(object_id);
 
-        if ui.input(|i| i.events.iter().any(|e| matches!(e, egui::Event::Copy))) {
-            *clipboard = self.get_selected()
+            match self.db.lock(&object, Holder::Local, who.clone()) {
+                Ok(true) => {
+                    let objects = surroundings(&self.db, &object);
+                    let message = Message::Lock {
+                        object: object.clone(),
+                        holder: Some(who.clone()),
+                    };
+                    server.broadcast_about(None, &object, &objects, message);
+                }
+                Ok(false) => {}
+                Err(e) => errors.push_back(format!("Could not lock object: {}", e)),
+            }
         }
+    }
 
-        if ui.input(|i| i.key_released(egui::Key::Delete)) {
-            let contents = std::mem::take(&mut self.selected);
+    pub fn unlock_selected(&mut self, errors: &mut VecDeque<String>) {
+        let ProjectKind::Hub(server) = &self.kind else {
+            return;
+        };
+
+        let who = Hello::default().identity();
 
-            for name in contents {
-                self.delete_object(&name)
+        for object_id in &self.selected {
+            let object = self.db.resolve_id(object_id);
+
+            match self.db.unlock(&object, Holder::Local) {
+                Ok(true) => {
+                    let objects = surroundings(&self.db, &object);
+                    let message = Message::Unlock {
+                        object: object.clone(),
+                        author: Some(who.clone()),
+                    };
+                    server.broadcast_about(None, &object, &objects, message);
+                }
+                Ok(false) => {}
+                Err(e) => errors.push_back(format!("Could not unlock object: {}", e)),
             }
         }
+    }
+
+    // Answers a change from a read-only peer, which already made it to its own copy, by handing
+    // back what it changed
+    fn refuse(server: &Server, db: &Database, peer: usize, message: Message) {
+        let reason = format!("read-only peers cannot send {} messages", message.kind());
+        log::warn!("Refusing {} from read-only peer {}", message.kind(), peer);
 
-        if ui.input(|i| i.key_released(egui::Key::Escape)) {
-            self.selected.clear()
+        let changed: Vec<ObjectId> = match &message {
+            Message::Push { objects, .. } => objects.keys().cloned().collect(),
+            Message::Delete { object, .. } => vec![object.clone()],
+            Message::Rename { kind, old, .. } => db.find(kind, old).into_iter().collect(),
+            _ => Vec::new(),
+        };
+
+        match message {
+            // the rejects tell the peer what happened, so it gets no error on top
+            Message::Push { id: Some(id), .. } => {
+                let rejected = changed
+                    .iter()
+                    .map(|object_id| (object_id.clone(), reason.clone()))
+                    .collect();
+
+                for reply in acknowledgement(id, Vec::new(), rejected) {
+                    server.send(peer, reply);
+                }
+            }
+            _ => server.send(
+                peer,
+                Message::Error {
+                    id: None,
+                    message: reason,
+                },
+            ),
         }
 
-        ui.horizontal(|ui| {
-            if ui.button("Types").clicked() {
-                self.current_tab = Tab::Types
+        hand_back(server, db, peer, &changed);
+    }
+
+    // Reply to a peer pulling objects from this project
+    fn pull_reply(db: &Database, id: u64, query: Query) -> Message {
+        let objects = match query {
+            Query::Type { name } => Ok(db
+                .type_id(&name)
+                .map(|object_id| db.types_get_net(object_id))
+                .unwrap_or_default()),
+            Query::Functions { start, end } => Ok(db.functions_in_range_get_net(start, end)),
+            Query::Changed { .. } => Err("binal does not keep revisions"),
+            Query::Summary => {
+                let buckets = HashIndex::new(&db.get_net()).summary();
+                return Message::Summary { id, buckets };
             }
+            Query::Hashes { object, start } => {
+                let index = HashIndex::new(&db.get_net());
+                let hashes = index.bucket(&object, start).cloned().unwrap_or_default();
 
-            if ui.button("Functions").clicked() {
-                self.current_tab = Tab::Functions
+                return Message::Hashes {
+                    id,
+                    hashes: hashes.into_iter().collect(),
+                };
             }
+            Query::Objects { ids } => Ok(ids.iter().flat_map(|id| db.object_get_net(id)).collect()),
+        };
 
-            if ui.button("Globals").clicked() {
-                self.current_tab = Tab::Globals
+        match objects {
+            Ok(objects) if !objects.is_empty() => Message::Response {
+                id,
+                revision: 0,
+                objects,
+            },
+            Ok(_) => Message::Error {
+                id: Some(id),
+                message: String::from("no matching objects"),
+            },
+            Err(message) => Message::Error {
+                id: Some(id),
+                message: String::from(message),
+            },
+        }
+    }
+}
+
+// Finds the objects that differ from the remote tool's by comparing the content hashes of whole
+// buckets first, and then of the objects in the buckets that differ, so only those get pulled
+#[derive(Default)]
+struct HashSync {
+    // hashes of our objects from before anything was pulled
+    local: HashIndex,
+    // objects the remote tool no longer has
+    stale: Vec<ObjectId>,
+    // objects pushed or deleted here since the summary was asked for, which the remote tool
+    // may not have seen yet when it described its own, so ours are the ones to keep
+    edited: HashSet<ObjectId>,
+}
+
+// A pull sent to the remote tool that is waiting for its reply
+struct Pull {
+    query: Query,
+    pending: Pending,
+    // objects the tool changed after the pull was sent. The reply has nothing newer of them, as
+    // the tool pushes every change, while changes made after the reply can arrive along with it
+    // and get handled first
+    changed: HashSet<ObjectId>,
+}
+
+impl Pull {
+    fn new(query: Query, pending: Pending) -> Self {
+        Self {
+            query,
+            pending,
+            changed: HashSet::new(),
+        }
+    }
+
+    // Marks objects the tool changed for every pull still waiting, so their replies leave them be
+    fn changed(pulls: &mut [Pull], object_ids: impl IntoIterator<Item = ObjectId>) {
+        for object_id in object_ids {
+            for pull in pulls.iter_mut() {
+                pull.changed.insert(object_id.clone());
             }
-        });
+        }
+    }
+}
 
-        match self.current_tab {
-            Tab::Types => Self::render_main_view(
-                &mut self.selected,
-                ui,
-                self.db.types.len(),
-                self.db.types.iter().map(|t| &t.name),
-            ),
-            Tab::Functions => Self::render_main_view(
-                &mut self.selected,
-                ui,
-                self.db.functions.len(),
-                self.db.functions.iter().map(|f| &f.name()),
-            ),
-            Tab::Globals => Self::render_main_view(
-                &mut self.selected,
-                ui,
-                self.db.data.len(),
-                self.db.data.iter().map(|d| &d.name),
-            ),
-        };
+// Pushes received together, which are applied all at once so a batch of related objects never
+// ends up half applied
+#[derive(Default)]
+struct Transaction {
+    // set when the peer sent a `Begin`, otherwise this is a single push
+    id: Option<u64>,
+    objects: HashMap<ObjectId, Object>,
+    // pushes that asked for an `Ack`, with the ids of the objects each one carried
+    acks: Vec<(u64, Vec<ObjectId>)>,
+    // who the pushes were made by, if the peer relayed them for someone else
+    author: Option<String>,
+}
+
+impl Transaction {
+    fn begin(id: u64) -> Self {
+        Self {
+            id: Some(id),
+            ..Self::default()
+        }
     }
 
-    fn render_main_view<'a, I: Iterator<Item = &'a String>>(
-        selected: &mut HashSet<String>,
-        ui: &mut Ui,
-        len: usize,
-        iter: I,
-    ) {
-        let text_style = ui.text_style_height(&egui::TextStyle::Body);
+    fn add(&mut self, id: Option<u64>, objects: HashMap<ObjectId, Object>, author: Option<String>) {
+        if let Some(id) = id {
+            self.acks.push((id, objects.keys().cloned().collect()));
+        }
 
-        ui.columns(2, |ui| {
-            egui::ScrollArea::vertical().show_rows(&mut ui[0], text_style, len, |ui, row_range| {
-                let names = iter.skip(row_range.start).take(row_range.count());
+        if author.is_some() {
+            self.author = author;
+        }
 
-                for name in names {
-                    let is_selected = selected.contains(name);
-                    let label = ui.selectable_label(is_selected, name);
+        // a later push of the same object replaces the earlier one, as it would have anyway
+        self.objects.extend(objects);
+    }
 
-                    if label.clicked() && is_selected {
-                        selected.remove(name);
-                    } else if label.clicked() && !is_selected {
-                        selected.insert(name.clone());
-                    }
-                }
-            });
+    fn apply(&self, db: &mut Database) -> PushResult {
+        db.push_net(self.objects.clone())
+    }
+
+    // Like `apply`, leaving out objects someone other than `holder` has locked
+    fn apply_as(&self, db: &mut Database, holder: Holder) -> PushResult {
+        db.push_net_as(self.objects.clone(), holder)
+    }
+
+    // Replies to every push that asked for them, once the objects have been applied
+    fn acknowledgements(&self, rejected: &HashMap<ObjectId, String>) -> Vec<Message> {
+        self.acks
+            .iter()
+            .flat_map(|(id, pushed)| {
+                let applied = pushed
+                    .iter()
+                    .filter(|object_id| !rejected.contains_key(*object_id))
+                    .cloned()
+                    .collect();
+
+                let rejected = pushed
+                    .iter()
+                    .filter_map(|object_id| {
+                        Some((object_id.clone(), rejected.get(object_id)?.clone()))
+                    })
+                    .collect();
+
+                acknowledgement(*id, applied, rejected)
+            })
+            .collect()
+    }
+
+    // Rejects everything that was pushed, since none of it is going to be applied
+    fn abort(self) -> Vec<Message> {
+        let rejected = self
+            .objects
+            .keys()
+            .map(|object_id| (object_id.clone(), String::from("transaction aborted")))
+            .collect();
+
+        self.acknowledgements(&rejected)
+    }
+}
+
+// Tells the peer which of the objects it pushed were applied; the ack comes last so it
+// marks the push as finished
+fn acknowledgement(
+    id: u64,
+    applied: Vec<ObjectId>,
+    rejected: HashMap<ObjectId, String>,
+) -> Vec<Message> {
+    let mut replies = Vec::new();
+
+    if !rejected.is_empty() {
+        replies.push(Message::Reject { id, rejected });
+    }
+
+    replies.push(Message::Ack { id, applied });
+    replies
+}
+
+// Who a peer of a hub is to the activity log and the other peers, as it authenticated itself
+fn identity(identities: &HashMap<usize, String>, peer: usize) -> String {
+    identities
+        .get(&peer)
+        .cloned()
+        .unwrap_or_else(|| format!("Peer {}", peer))
+}
+
+// An object with everything that would bring it along in a push, for telling which peers are
+// subscribed to it; anything may use a type, so for those that is the whole database
+fn surroundings(db: &Database, object_id: &str) -> HashMap<ObjectId, Object> {
+    let objects = db.object_get_net(object_id);
+
+    match objects.get(&db.resolve_id(object_id)) {
+        Some(Object::Type { .. }) => db.get_net(),
+        _ => objects,
+    }
+}
+
+// Pushes a peer the objects it changed its own copy of, as they are in the database, after the
+// change was refused
+fn hand_back(server: &Server, db: &Database, peer: usize, object_ids: &[ObjectId]) {
+    let objects: HashMap<ObjectId, Object> = object_ids
+        .iter()
+        .flat_map(|object_id| db.object_get_net(object_id))
+        .collect();
+
+    if !objects.is_empty() {
+        server.send(
+            peer,
+            Message::Push {
+                id: None,
+                objects,
+                author: None,
+            },
+        );
+    }
+}
+
+// Names of the objects a push changed, leaving out most of them for large pushes
+fn describe_changed(db: &Database, changed: &[ObjectId]) -> String {
+    let names: Vec<&str> = changed
+        .iter()
+        .take(3)
+        .map(|object_id| {
+            db.name(object_id)
+                .map_or(object_id.as_str(), String::as_str)
         })
+        .collect();
+
+    match changed.len() {
+        count if count > names.len() => format!(
+            "changed {} and {} more",
+            names.join(", "),
+            count - names.len()
+        ),
+        _ => format!("changed {}", names.join(", ")),
     }
+}
 
-    // Handle incoming network messages
-    pub fn handle_network_updates(&mut self) {
-        loop {
-            let ProjectKind::Remote(client) = &mut self.kind else {
-                return;
-            };
+// What the peers of a project did to it, oldest first, for the activity feed
+#[derive(Default)]
+pub(crate) struct ActivityLog {
+    pub(crate) entries: VecDeque<Activity>,
+}
 
-            let Ok(message) = client.rx.try_recv() else {
-                return;
-            };
+// only shown by the GUI, the daemon gets the log lines instead
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub(crate) struct Activity {
+    pub(crate) at: Instant,
+    pub(crate) who: String,
+    pub(crate) what: String,
+}
 
-            match message {
-                Message::Push { objects } => self.db.push_net(objects),
-            }
+impl ActivityLog {
+    // Also logged, so the feed is not lost when running without the GUI
+    fn record(&mut self, who: String, what: String) {
+        log::info!("{} {}", who, what);
+
+        if self.entries.len() == ACTIVITY_LIMIT {
+            self.entries.pop_front();
         }
+
+        self.entries.push_back(Activity {
+            at: Instant::now(),
+            who,
+            what,
+        });
     }
 }
+
+fn describe_rejected(rejected: &HashMap<ObjectId, String>) -> String {
+    rejected
+        .iter()
+        .map(|(object_id, reason)| format!("{} ({})", object_id, reason))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,376 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    sync::{mpsc, Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    net::{Message, Query},
+    transport::{Stream, Transport},
+};
+
+// How long a replayed peer waits for binal to connect to it
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(10);
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Pulled once everything was sent, as binal answers it only after handling what came before
+const DONE_ID: u64 = u64::MAX;
+// How long to wait for that answer before leaving anyway
+const DONE_TIMEOUT: Duration = Duration::from_secs(30);
+// How long a recorded reply waits for binal to send the pull it answers
+const PULL_TIMEOUT: Duration = Duration::from_secs(1);
+
+// One message of a recording, each written as a line of JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    // milliseconds since the recording started
+    pub at: u64,
+    // the peer of a hub it was exchanged with, left out for the peer binal connected to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer: Option<usize>,
+    pub direction: Direction,
+    // kept as plain JSON, so messages that could not be decoded are recorded as well
+    pub message: Value,
+    // why the message could not be decoded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    // received by binal
+    In,
+    // sent by binal
+    Out,
+}
+
+// Writes every message binal exchanges over the connections it is given to, see `Entry`
+pub struct Recorder {
+    started: Instant,
+    // written a line at a time, so a crash loses nothing that was already recorded
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> io::Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            started: Instant::now(),
+            file: Mutex::new(File::create(path)?),
+        }))
+    }
+
+    fn write(&self, entry: Entry) {
+        let mut line = match serde_json::to_vec(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("Could not record message: {}", e);
+                return;
+            }
+        };
+
+        line.push(b'\n');
+
+        if let Err(e) = self.file.lock().unwrap().write_all(&line) {
+            log::error!("Could not record message: {}", e);
+        }
+    }
+}
+
+// Where a connection records its messages, if anywhere
+#[derive(Clone, Default)]
+pub(crate) struct Tap {
+    recorder: Option<Arc<Recorder>>,
+    peer: Option<usize>,
+}
+
+impl Tap {
+    pub(crate) fn new(recorder: Option<Arc<Recorder>>, peer: Option<usize>) -> Self {
+        Self { recorder, peer }
+    }
+
+    pub(crate) fn message(&self, direction: Direction, message: &Message) {
+        match serde_json::to_value(message) {
+            Ok(value) => self.entry(direction, value, None),
+            Err(e) => self.entry(direction, Value::Null, Some(e.to_string())),
+        }
+    }
+
+    // A message that was received, but could not be decoded
+    pub(crate) fn undecodable(&self, message: Value, error: String) {
+        self.entry(Direction::In, message, Some(error))
+    }
+
+    fn entry(&self, direction: Direction, message: Value, error: Option<String>) {
+        let Some(recorder) = &self.recorder else {
+            return;
+        };
+
+        recorder.write(Entry {
+            at: recorder.started.elapsed().as_millis() as u64,
+            peer: self.peer,
+            direction,
+            message,
+            error,
+        })
+    }
+}
+
+// A recording read back, which can stand in for the peers binal exchanged messages with, so
+// whatever happened can be reproduced without them
+pub struct Replay {
+    pub entries: Vec<Entry>,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut entries = Vec::new();
+
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let entry = serde_json::from_str(&line)
+                .map_err(|e| format!("line {} is not a recorded message: {}", number + 1, e))?;
+
+            entries.push(entry);
+        }
+
+        Ok(Self { entries })
+    }
+
+    // Whether this was recorded by a hub, which the peers connected to, rather than by binal
+    // connecting to a single tool
+    pub fn is_hub(&self) -> bool {
+        self.entries.iter().any(|entry| entry.peer.is_some())
+    }
+
+    // Stands in for the tool binal connected to: waits for binal to connect on `transport`, then
+    // sends it whatever the tool sent. With `paced`, messages are as far apart as they were
+    pub fn serve(&self, transport: &dyn Transport, paced: bool) -> io::Result<JoinHandle<()>> {
+        let listener = transport.listen()?;
+        let entries = self.received_from(None);
+        let start = Instant::now();
+
+        Ok(std::thread::spawn(move || {
+            let deadline = Instant::now() + ACCEPT_TIMEOUT;
+
+            let stream = loop {
+                match listener.accept() {
+                    Ok(Some((stream, _))) => break stream,
+                    Ok(None) if Instant::now() < deadline => {
+                        std::thread::sleep(ACCEPT_POLL_INTERVAL)
+                    }
+                    Ok(None) => return log::error!("binal did not connect to the replay"),
+                    Err(e) => return log::error!("Could not accept binal for the replay: {}", e),
+                }
+            };
+
+            play(stream, entries, paced.then_some(start))
+        }))
+    }
+
+    // Stands in for every peer of the hub it was recorded by, connecting each one to
+    // `transport` and sending what it sent. With `paced`, peers also join when they did
+    pub fn connect(&self, transport: Box<dyn Transport>, paced: bool) -> JoinHandle<()> {
+        // when each peer joined, along with what it sent
+        let mut peers: Vec<(u64, Vec<Entry>)> = Vec::new();
+        let mut seen = HashSet::new();
+
+        for entry in &self.entries {
+            let Some(peer) = entry.peer.filter(|peer| seen.insert(*peer)) else {
+                continue;
+            };
+
+            let entries = self.received_from(Some(peer));
+
+            if !entries.is_empty() {
+                peers.push((entry.at, entries));
+            }
+        }
+
+        let start = Instant::now();
+
+        std::thread::spawn(move || {
+            let mut players = Vec::new();
+
+            for (joined, entries) in peers {
+                if paced {
+                    let due = start + Duration::from_millis(joined);
+                    std::thread::sleep(due.saturating_duration_since(Instant::now()));
+                }
+
+                match transport.connect() {
+                    Ok(stream) => players.push(std::thread::spawn(move || {
+                        play(stream, entries, paced.then_some(start))
+                    })),
+                    Err(e) => log::error!("Could not connect a replayed peer to binal: {}", e),
+                }
+            }
+
+            for player in players {
+                let _ = player.join();
+            }
+        })
+    }
+
+    // What binal received from a peer, along with when relative to the start of the recording
+    fn received_from(&self, peer: Option<usize>) -> Vec<Entry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.peer == peer && entry.direction == Direction::In)
+            .cloned()
+            .collect()
+    }
+}
+
+// Sends what a peer sent over `stream`, hello first, and returns once binal handled all of it.
+// The recorded hello is changed to ask for plain JSON without a token, so the rest can be sent as
+// recorded
+fn play(stream: Box<dyn Stream>, entries: Vec<Entry>, start: Option<Instant>) {
+    let hello = entries
+        .iter()
+        .find(|entry| kind(&entry.message) == Some("hello"));
+
+    let Some(hello) = hello else {
+        return log::error!("The recording has no hello to replay");
+    };
+
+    let mut hello = hello.message.clone();
+
+    if let Some(hello) = hello.as_object_mut() {
+        hello.insert(String::from("encodings"), Value::from(["json"].as_slice()));
+        hello.remove("compression");
+        hello.remove("challenge");
+
+        // so binal can answer the final pull either way
+        if let Some(Value::Array(messages)) = hello.get_mut("messages") {
+            for kind in ["response", "error"] {
+                if !messages.iter().any(|m| m == kind) {
+                    messages.push(Value::from(kind));
+                }
+            }
+        }
+    }
+
+    let (done, finished) = mpsc::channel();
+    let (pull, pulled) = mpsc::channel();
+
+    // binal's messages are not needed, but have to be read so it never blocks writing them
+    let reader = match stream.try_clone() {
+        Ok(reader) => std::thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let Ok(line) = line else {
+                    return;
+                };
+
+                log::debug!("Replayed peer got {}", line);
+
+                let message: Value = serde_json::from_str(&line).unwrap_or_default();
+
+                let id = message.get("id").and_then(Value::as_u64);
+
+                if id == Some(DONE_ID) {
+                    let _ = done.send(());
+                }
+
+                if let (Some("pull"), Some(id)) = (kind(&message), id) {
+                    let _ = pull.send(id);
+                }
+            }
+        }),
+        Err(e) => return log::error!("Could not replay: {}", e),
+    };
+
+    // the hello is sent above, and without a token there is nothing to answer, so only what
+    // came after the handshake is sent as it was recorded
+    let rest = entries
+        .iter()
+        .filter(|entry| !matches!(kind(&entry.message), Some("hello" | "auth")));
+    let messages = std::iter::once((entries[0].at, &hello))
+        .chain(rest.map(|entry| (entry.at, &entry.message)));
+
+    let done = Message::Pull {
+        id: DONE_ID,
+        query: Query::Objects { ids: Vec::new() },
+    };
+    let done = serde_json::to_value(done).unwrap_or_default();
+
+    let mut writer = stream;
+    let mut waiting = Some(HashSet::new());
+
+    for (at, message) in messages.chain(std::iter::once((0, &done))) {
+        if let Some(start) = start {
+            let due = start + Duration::from_millis(at);
+            std::thread::sleep(due.saturating_duration_since(Instant::now()));
+        }
+
+        // binal drops replies to pulls it has not sent yet, and may be further behind than it
+        // was when recorded. Once it does not send one at all, it is not pulling the same things
+        // as it did then, so the rest is sent without waiting
+        if let (Some(seen), Some(id)) = (&mut waiting, reply_to(message)) {
+            if !wait_for_pull(&pulled, seen, id) {
+                log::warn!(
+                    "binal did not pull what reply {} answers, sending it anyway",
+                    id
+                );
+                waiting = None;
+            }
+        }
+
+        let mut line = message.to_string().into_bytes();
+        line.push(b'\n');
+
+        if let Err(e) = writer.write_all(&line).and_then(|()| writer.flush()) {
+            return log::error!("binal closed the connection during the replay: {}", e);
+        }
+    }
+
+    if finished.recv_timeout(DONE_TIMEOUT).is_err() {
+        log::warn!("binal did not answer the end of the replay");
+    }
+
+    log::info!("Replayed the {} messages binal received", entries.len());
+
+    // everything was handled, so leaving is what is left to do, as the peer did when recorded
+    let _ = writer.shutdown();
+    let _ = reader.join();
+}
+
+// Whether binal sent the pull with this id, going by the ones it sent so far and the ones in
+// `pulled` as they come in
+fn wait_for_pull(pulled: &mpsc::Receiver<u64>, seen: &mut HashSet<u64>, id: u64) -> bool {
+    let deadline = Instant::now() + PULL_TIMEOUT;
+
+    while !seen.contains(&id) {
+        match pulled.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(pulled) => seen.insert(pulled),
+            Err(_) => return false,
+        };
+    }
+
+    true
+}
+
+// The id of the pull a message answers, if it is a reply to one
+fn reply_to(message: &Value) -> Option<u64> {
+    match kind(message)? {
+        "response" | "error" | "summary" | "hashes" => message.get("id")?.as_u64(),
+        _ => None,
+    }
+}
+
+fn kind(message: &Value) -> Option<&str> {
+    message.get("kind").and_then(Value::as_str)
+}
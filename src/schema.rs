@@ -0,0 +1,220 @@
+// JSON Schema of the messages peers exchange, generated from the types in `net` so it cannot
+// drift from what binal actually accepts, along with a checker that points out where a message
+// that could not be decoded goes wrong
+use std::sync::OnceLock;
+
+use serde_json::{Map, Value};
+
+use crate::net::Message;
+
+pub fn schema() -> &'static Value {
+    static SCHEMA: OnceLock<Value> = OnceLock::new();
+
+    SCHEMA.get_or_init(|| serde_json::to_value(schemars::schema_for!(Message)).unwrap_or_default())
+}
+
+// Every way the message does not match the schema, each as the JSON pointer to the offending
+// part followed by what is wrong with it, such as `/objects/type:1/size: expected integer, got
+// string`. Empty if it matches
+pub fn validate(message: &Value) -> Vec<String> {
+    let mut problems = Vec::new();
+    check(schema(), message, "", &mut problems);
+
+    problems
+}
+
+// Only covers what schemars generates for the types in `net`
+fn check(schema: &Value, value: &Value, path: &str, problems: &mut Vec<String>) {
+    if let Some(target) = schema.get("$ref").and_then(Value::as_str) {
+        let found = problems.len();
+
+        match resolve(target) {
+            Some(target) => check(target, value, path, problems),
+            None => problems.push(format!("{}: the schema has no {}", at(path), target)),
+        }
+
+        // the rest of the schema applies along with the reference, unless that already found
+        // something wrong
+        if problems.len() > found {
+            return;
+        }
+    }
+
+    let mut problem = |text: String| problems.push(format!("{}: {}", at(path), text));
+
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            types => types.as_str().into_iter().collect(),
+        };
+
+        let actual = type_of(value);
+        let matches = types
+            .iter()
+            .any(|t| *t == actual || (*t == "number" && actual == "integer"));
+
+        if !matches {
+            return problem(format!("expected {}, got {}", types.join(" or "), actual));
+        }
+    }
+
+    if let Some(expected) = schema.get("const") {
+        if value != expected {
+            return problem(format!("expected {}, got {}", expected, value));
+        }
+    }
+
+    if let Some(number) = value.as_f64() {
+        let minimum = schema.get("minimum").and_then(Value::as_f64);
+        let maximum = schema.get("maximum").and_then(Value::as_f64);
+
+        if let Some(minimum) = minimum.filter(|minimum| number < *minimum) {
+            return problem(format!("{} is less than {}", value, minimum));
+        }
+
+        if let Some(maximum) = maximum.filter(|maximum| number > *maximum) {
+            return problem(format!("{} is more than {}", value, maximum));
+        }
+    }
+
+    if let Some(alternatives) = schema.get("oneOf").and_then(Value::as_array) {
+        return check_alternatives(alternatives, value, path, problems);
+    }
+
+    if let Value::Object(fields) = value {
+        check_fields(schema, fields, path, problems);
+    }
+
+    if let (Some(items), Value::Array(values)) = (schema.get("items"), value) {
+        for (index, item) in values.iter().enumerate() {
+            check(items, item, &format!("{}/{}", path, index), problems);
+        }
+    }
+}
+
+fn check_fields(
+    schema: &Value,
+    fields: &Map<String, Value>,
+    path: &str,
+    problems: &mut Vec<String>,
+) {
+    let properties = schema.get("properties").and_then(Value::as_object);
+
+    for required in schema
+        .get("required")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let Some(required) = required.as_str() else {
+            continue;
+        };
+
+        if !fields.contains_key(required) {
+            problems.push(format!("{}: missing field `{}`", at(path), required));
+        }
+    }
+
+    for (name, field) in fields {
+        let field_path = format!("{}/{}", path, escape(name));
+
+        match properties.and_then(|properties| properties.get(name)) {
+            Some(property) => check(property, field, &field_path, problems),
+            None => match schema.get("additionalProperties") {
+                Some(Value::Bool(false)) => {
+                    problems.push(format!("{}: unexpected field", field_path))
+                }
+                Some(additional @ Value::Object(_)) => {
+                    check(additional, field, &field_path, problems)
+                }
+                _ => {}
+            },
+        }
+    }
+}
+
+// The variants of a tagged enum, told apart by their `kind`, so a message with a known kind is
+// only checked against that variant instead of reporting every variant's problems
+fn check_alternatives(
+    alternatives: &[Value],
+    value: &Value,
+    path: &str,
+    problems: &mut Vec<String>,
+) {
+    let kinds: Vec<&Value> = alternatives
+        .iter()
+        .filter_map(|alternative| alternative.pointer("/properties/kind/const"))
+        .collect();
+
+    if kinds.len() == alternatives.len() {
+        if let Some(fields) = value.as_object() {
+            let Some(kind) = fields.get("kind") else {
+                return problems.push(format!("{}: missing field `kind`", at(path)));
+            };
+
+            let Some(index) = kinds.iter().position(|k| *k == kind) else {
+                let kinds: Vec<String> = kinds.iter().map(|k| k.to_string()).collect();
+
+                return problems.push(format!(
+                    "{}/kind: expected one of {}, got {}",
+                    path,
+                    kinds.join(", "),
+                    kind
+                ));
+            };
+
+            return check(&alternatives[index], value, path, problems);
+        }
+    }
+
+    // otherwise whichever alternative comes closest is what the sender most likely meant
+    let mut closest: Option<Vec<String>> = None;
+
+    for alternative in alternatives {
+        let mut attempt = Vec::new();
+        check(alternative, value, path, &mut attempt);
+
+        if attempt.is_empty() {
+            return;
+        }
+
+        if closest
+            .as_ref()
+            .is_none_or(|closest| attempt.len() < closest.len())
+        {
+            closest = Some(attempt);
+        }
+    }
+
+    problems.extend(closest.unwrap_or_default());
+}
+
+// Where a problem is, the whole message being `/`
+fn at(path: &str) -> &str {
+    if path.is_empty() {
+        "/"
+    } else {
+        path
+    }
+}
+
+fn resolve(target: &str) -> Option<&'static Value> {
+    schema().pointer(target.strip_prefix('#')?)
+}
+
+fn type_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+// As in a JSON pointer
+fn escape(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
@@ -0,0 +1,450 @@
+use std::{
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use ring::digest::{digest, SHA256};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::WebPkiSupportedAlgorithms,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    ClientConfig, ClientConnection, DigitallySignedStruct, ServerConfig, ServerConnection,
+    SignatureScheme,
+};
+
+use crate::transport::{Listener, Stream, Transport};
+
+// Certificates are self-signed, so this is the name they are issued for and the one asked for,
+// while what actually identifies the peer is the fingerprint of its certificate
+const SERVER_NAME: &str = "binal";
+
+// SHA-256 of a DER certificate, as colon separated hex like openssl prints it
+pub fn fingerprint(certificate: &[u8]) -> String {
+    digest(&SHA256, certificate)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+// Accepts fingerprints with or without colons, in either case
+fn parse_fingerprint(text: &str) -> Result<Vec<u8>, String> {
+    let digits: String = text.chars().filter(|c| *c != ':').collect();
+
+    if digits.len() != 64 || !digits.is_ascii() {
+        return Err(format!("Expected a SHA-256 fingerprint, got {}", text));
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("Expected a SHA-256 fingerprint, got {}", text))
+        })
+        .collect()
+}
+
+// The certificate the listening side presents, and its private key
+pub struct Identity {
+    certificates: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+}
+
+impl Identity {
+    // Loads a PEM file holding a certificate and its key, first generating a self-signed one
+    // into it if the file does not exist yet
+    pub fn load_or_generate(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            Self::generate(path)?;
+        }
+
+        let certificates = CertificateDer::pem_file_iter(path)
+            .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("Could not read certificate from {}: {}", path.display(), e))?;
+
+        if certificates.is_empty() {
+            return Err(format!("No certificate in {}", path.display()));
+        }
+
+        let key = PrivateKeyDer::from_pem_file(path)
+            .map_err(|e| format!("Could not read private key from {}: {}", path.display(), e))?;
+
+        Ok(Self { certificates, key })
+    }
+
+    fn generate(path: &Path) -> Result<(), String> {
+        let generated = rcgen::generate_simple_self_signed(vec![String::from(SERVER_NAME)])
+            .map_err(|e| format!("Could not generate a certificate: {}", e))?;
+
+        let pem = generated.cert.pem() + &generated.signing_key.serialize_pem();
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+
+        // the file holds the private key
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        options
+            .open(path)
+            .and_then(|mut file| file.write_all(pem.as_bytes()))
+            .map_err(|e| format!("Could not write certificate to {}: {}", path.display(), e))?;
+
+        log::info!("Generated a self-signed certificate in {}", path.display());
+
+        Ok(())
+    }
+
+    // What peers pin to connect to whoever presents this identity
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.certificates[0])
+    }
+}
+
+// Another transport wrapped in TLS. The listening side presents an `Identity`, and the
+// connecting side only accepts the certificate whose fingerprint it was given
+pub struct Tls {
+    inner: Box<dyn Transport>,
+    side: Side,
+}
+
+enum Side {
+    Connecting(Arc<ClientConfig>),
+    Listening {
+        config: Arc<ServerConfig>,
+        fingerprint: String,
+    },
+}
+
+impl Tls {
+    pub fn connecting(inner: Box<dyn Transport>, fingerprint: &str) -> Result<Self, String> {
+        let verifier = Pinned {
+            fingerprint: parse_fingerprint(fingerprint)?,
+            algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        };
+
+        let config = ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+
+        Ok(Self {
+            inner,
+            side: Side::Connecting(Arc::new(config)),
+        })
+    }
+
+    pub fn listening(inner: Box<dyn Transport>, identity: &Identity) -> Result<Self, String> {
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(identity.certificates.clone(), identity.key.clone_key())
+            .map_err(|e| format!("Could not use certificate: {}", e))?;
+
+        Ok(Self {
+            inner,
+            side: Side::Listening {
+                config: Arc::new(config),
+                fingerprint: identity.fingerprint(),
+            },
+        })
+    }
+}
+
+impl fmt::Display for Tls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} over TLS", self.inner)
+    }
+}
+
+impl Transport for Tls {
+    fn connect(&self) -> io::Result<Box<dyn Stream>> {
+        let Side::Connecting(config) = &self.side else {
+            return Err(io::Error::other(
+                "no certificate fingerprint to connect with",
+            ));
+        };
+
+        let name = ServerName::try_from(SERVER_NAME).map_err(io::Error::other)?;
+        let session = ClientConnection::new(config.clone(), name).map_err(io::Error::other)?;
+
+        Ok(Box::new(TlsStream::new(
+            self.inner.connect()?,
+            session.into(),
+        )?))
+    }
+
+    fn listen(&self) -> io::Result<Box<dyn Listener>> {
+        let Side::Listening {
+            config,
+            fingerprint,
+        } = &self.side
+        else {
+            return Err(io::Error::other("no certificate to listen with"));
+        };
+
+        Ok(Box::new(TlsListener {
+            inner: self.inner.listen()?,
+            config: config.clone(),
+            fingerprint: fingerprint.clone(),
+        }))
+    }
+}
+
+struct TlsListener {
+    inner: Box<dyn Listener>,
+    config: Arc<ServerConfig>,
+    fingerprint: String,
+}
+
+impl Listener for TlsListener {
+    fn accept(&self) -> io::Result<Option<(Box<dyn Stream>, String)>> {
+        let Some((stream, addr)) = self.inner.accept()? else {
+            return Ok(None);
+        };
+
+        let session = ServerConnection::new(self.config.clone()).map_err(io::Error::other)?;
+
+        Ok(Some((
+            Box::new(TlsStream::new(stream, session.into())?),
+            addr,
+        )))
+    }
+
+    fn local_addr(&self) -> String {
+        format!(
+            "{} (TLS, certificate {})",
+            self.inner.local_addr(),
+            self.fingerprint
+        )
+    }
+}
+
+// Only trusts the one certificate whose fingerprint it was given, still checking that the peer
+// holds its private key
+#[derive(Debug)]
+struct Pinned {
+    fingerprint: Vec<u8>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if digest(&SHA256, end_entity).as_ref() != self.fingerprint {
+            return Err(rustls::Error::General(format!(
+                "certificate {} is not the pinned one",
+                fingerprint(end_entity)
+            )));
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+// Shared between the clones of a TLS stream. The socket is read without holding `session`, so
+// one thread can wait on the peer while another keeps writing; locks are always taken in the
+// order `incoming`, `outgoing`, `session`
+struct TlsShared {
+    session: Mutex<rustls::Connection>,
+    // the socket to read from, and what was read from it that the session has not taken yet
+    incoming: Mutex<(Box<dyn Stream>, Vec<u8>)>,
+    // the socket to write to, held while sending so records go out in the order they were made
+    outgoing: Mutex<Box<dyn Stream>>,
+    // for shutting down or setting timeouts while the others are in use
+    socket: Mutex<Box<dyn Stream>>,
+}
+
+// The TLS handshake happens on first use, on whichever thread that is, rather than while
+// accepting
+#[derive(Clone)]
+struct TlsStream(Arc<TlsShared>);
+
+impl TlsStream {
+    fn new(socket: Box<dyn Stream>, session: rustls::Connection) -> io::Result<Self> {
+        Ok(Self(Arc::new(TlsShared {
+            session: Mutex::new(session),
+            incoming: Mutex::new((socket.try_clone()?, Vec::new())),
+            outgoing: Mutex::new(socket.try_clone()?),
+            socket: Mutex::new(socket),
+        })))
+    }
+
+    fn handshake(&self) -> io::Result<()> {
+        if !self.0.session.lock().unwrap().is_handshaking() {
+            return Ok(());
+        }
+
+        let mut incoming = self.0.incoming.lock().unwrap();
+        let mut outgoing = self.0.outgoing.lock().unwrap();
+        let mut session = self.0.session.lock().unwrap();
+
+        let mut socket = Duplex {
+            reader: &mut incoming.0,
+            writer: &mut outgoing,
+        };
+
+        while session.is_handshaking() {
+            session.complete_io(&mut socket)?;
+        }
+
+        while session.wants_write() {
+            session.write_tls(&mut socket)?;
+        }
+
+        socket.flush()
+    }
+
+    // Sends whatever records the session has ready
+    fn send_records(&self) -> io::Result<()> {
+        let mut outgoing = self.0.outgoing.lock().unwrap();
+        let mut records = Vec::new();
+
+        {
+            let mut session = self.0.session.lock().unwrap();
+
+            while session.wants_write() {
+                session.write_tls(&mut records)?;
+            }
+        }
+
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        outgoing.write_all(&records)?;
+        outgoing.flush()
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.handshake()?;
+
+        let mut incoming = self.0.incoming.lock().unwrap();
+        let (socket, pending) = &mut *incoming;
+
+        loop {
+            let mut session = self.0.session.lock().unwrap();
+
+            match session.reader().read(buf) {
+                Ok(size) => return Ok(size),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                // plugins tend to close without a close_notify, and a message cut off by that
+                // is caught by the framing anyway
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(0),
+                Err(e) => return Err(e),
+            }
+
+            if !pending.is_empty() {
+                let mut input = pending.as_slice();
+                session.read_tls(&mut input)?;
+                pending.drain(..pending.len() - input.len());
+
+                let processed = session.process_new_packets();
+                drop(session);
+
+                // alerts about anything that went wrong, or replies to the peer
+                self.send_records()?;
+                processed.map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+                continue;
+            }
+
+            drop(session);
+
+            let mut chunk = [0; 16 * 1024];
+            let size = socket.read(&mut chunk)?;
+
+            if size == 0 {
+                // lets the session tell a clean close from a cut off connection
+                self.0.session.lock().unwrap().read_tls(&mut &[][..])?;
+                continue;
+            }
+
+            pending.extend_from_slice(&chunk[..size]);
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.handshake()?;
+
+        let size = self.0.session.lock().unwrap().writer().write(buf)?;
+        self.send_records()?;
+
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_records()
+    }
+}
+
+impl Stream for TlsStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.socket.lock().unwrap().set_read_timeout(timeout)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.0.socket.lock().unwrap().shutdown()
+    }
+}
+
+// The two halves of a socket put back together for the handshake
+struct Duplex<'a> {
+    reader: &'a mut Box<dyn Stream>,
+    writer: &'a mut Box<dyn Stream>,
+}
+
+impl Read for Duplex<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for Duplex<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,444 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+    net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    str::FromStr,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+};
+
+// Port used when an address leaves it out, and the one the plugins listen on by default
+pub const DEFAULT_PORT: u16 = 12007;
+
+// A connection to a peer; clones share the connection so one thread can read while another writes
+pub trait Stream: Read + Write + Send + 'static {
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    // Closes the connection for every clone, waking up anyone blocked reading from it
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+// Somewhere connections can be made to or accepted on
+pub trait Transport: fmt::Display + Send + 'static {
+    fn connect(&self) -> io::Result<Box<dyn Stream>>;
+    fn listen(&self) -> io::Result<Box<dyn Listener>>;
+}
+
+pub trait Listener: Send + 'static {
+    // Returns the next waiting connection and where it came from, without blocking
+    fn accept(&self) -> io::Result<Option<(Box<dyn Stream>, String)>>;
+    fn local_addr(&self) -> String;
+}
+
+// Parses `unix:/path/to/socket`, `memory:name`, or a TCP address such as `127.0.0.1:12007`,
+// `[::1]:12007` or `host.example:12007`, where the port defaults to `DEFAULT_PORT`
+pub fn parse(text: &str) -> Result<Box<dyn Transport>, String> {
+    let text = text.trim();
+
+    if let Some(path) = text.strip_prefix("unix:") {
+        #[cfg(unix)]
+        return Ok(Box::new(Unix(PathBuf::from(path))));
+
+        #[cfg(not(unix))]
+        return Err(format!("Unix sockets are not available here: {}", path));
+    }
+
+    if let Some(name) = text.strip_prefix("memory:") {
+        return Ok(Box::new(Memory(name.to_string())));
+    }
+
+    if text.is_empty() {
+        return Err(String::from("Expected an address"));
+    }
+
+    if let Ok(addr) = SocketAddr::from_str(text) {
+        return Ok(Box::new(Tcp(addr.to_string())));
+    }
+
+    // a bare IPv6 address would otherwise have its last group taken for the port, and the
+    // brackets it needs when a port follows are fine without one as well
+    let bare = text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'))
+        .unwrap_or(text);
+
+    if let Ok(ip) = IpAddr::from_str(bare) {
+        return Ok(Box::new(Tcp(SocketAddr::new(ip, DEFAULT_PORT).to_string())));
+    }
+
+    match text.rsplit_once(':') {
+        Some((host, port)) => match u16::from_str(port) {
+            Ok(port) => Ok(Box::new(Tcp(format!("{}:{}", host, port)))),
+            Err(_) => Err(format!("Invalid port in {}", text)),
+        },
+        None => Ok(Box::new(Tcp(format!("{}:{}", text, DEFAULT_PORT)))),
+    }
+}
+
+// TCP over IPv4 or IPv6, with host names resolved on every connect
+pub struct Tcp(pub String);
+
+impl fmt::Display for Tcp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Transport for Tcp {
+    fn connect(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(TcpStream::connect(self.0.as_str())?))
+    }
+
+    fn listen(&self) -> io::Result<Box<dyn Listener>> {
+        let listener = TcpListener::bind(self.0.as_str())?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Box::new(listener))
+    }
+}
+
+impl Stream for TcpStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> io::Result<Option<(Box<dyn Stream>, String)>> {
+        match TcpListener::accept(self) {
+            Ok((stream, addr)) => {
+                stream.set_nonblocking(false)?;
+                Ok(Some((Box::new(stream), addr.to_string())))
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn local_addr(&self) -> String {
+        match TcpListener::local_addr(self) {
+            Ok(addr) => addr.to_string(),
+            Err(_) => String::from("unknown address"),
+        }
+    }
+}
+
+// Unix domain sockets, for plugins running on the same machine
+#[cfg(unix)]
+pub struct Unix(pub PathBuf);
+
+#[cfg(unix)]
+impl fmt::Display for Unix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unix:{}", self.0.display())
+    }
+}
+
+#[cfg(unix)]
+impl Transport for Unix {
+    fn connect(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(UnixStream::connect(&self.0)?))
+    }
+
+    fn listen(&self) -> io::Result<Box<dyn Listener>> {
+        // a socket file left behind by a listener that went away without cleaning up. Anything
+        // else at the path is most likely a typo, and not ours to remove
+        if let Ok(metadata) = std::fs::symlink_metadata(&self.0) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", self.0.display()),
+                ));
+            }
+
+            if UnixStream::connect(&self.0).is_err() {
+                std::fs::remove_file(&self.0)?;
+            }
+        }
+
+        let listener = UnixListener::bind(&self.0)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Box::new(UnixSocketListener {
+            listener,
+            path: self.0.clone(),
+        }))
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(UnixStream::try_clone(self)?))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+// Removes the socket file once nobody is listening on it anymore
+#[cfg(unix)]
+struct UnixSocketListener {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl Listener for UnixSocketListener {
+    fn accept(&self) -> io::Result<Option<(Box<dyn Stream>, String)>> {
+        match self.listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                Ok(Some((
+                    Box::new(stream),
+                    format!("unix:{}", self.path.display()),
+                )))
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn local_addr(&self) -> String {
+        format!("unix:{}", self.path.display())
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocketListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// Connections within the same process, addressed by name, so projects can be wired together
+// without opening any ports
+pub struct Memory(pub String);
+
+type MemoryListeners = Mutex<HashMap<String, mpsc::Sender<MemoryStream>>>;
+
+fn memory_listeners() -> &'static MemoryListeners {
+    static LISTENERS: std::sync::OnceLock<MemoryListeners> = std::sync::OnceLock::new();
+    LISTENERS.get_or_init(MemoryListeners::default)
+}
+
+impl fmt::Display for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "memory:{}", self.0)
+    }
+}
+
+impl Transport for Memory {
+    fn connect(&self) -> io::Result<Box<dyn Stream>> {
+        let listeners = memory_listeners().lock().unwrap();
+
+        let Some(listener) = listeners.get(&self.0) else {
+            return Err(io::Error::new(
+                ErrorKind::ConnectionRefused,
+                format!("nothing is listening on {}", self),
+            ));
+        };
+
+        let (local, remote) = MemoryStream::pair();
+
+        listener
+            .send(remote)
+            .map_err(|_| io::Error::new(ErrorKind::ConnectionRefused, "listener went away"))?;
+
+        Ok(Box::new(local))
+    }
+
+    fn listen(&self) -> io::Result<Box<dyn Listener>> {
+        let mut listeners = memory_listeners().lock().unwrap();
+
+        if listeners.contains_key(&self.0) {
+            return Err(io::Error::new(
+                ErrorKind::AddrInUse,
+                format!("{} is already being listened on", self),
+            ));
+        }
+
+        let (tx, rx) = mpsc::channel();
+        listeners.insert(self.0.clone(), tx);
+
+        Ok(Box::new(MemoryListener {
+            name: self.0.clone(),
+            rx: Mutex::new(rx),
+        }))
+    }
+}
+
+struct MemoryListener {
+    name: String,
+    rx: Mutex<mpsc::Receiver<MemoryStream>>,
+}
+
+impl Listener for MemoryListener {
+    fn accept(&self) -> io::Result<Option<(Box<dyn Stream>, String)>> {
+        match self.rx.lock().unwrap().try_recv() {
+            Ok(stream) => Ok(Some((Box::new(stream), format!("memory:{}", self.name)))),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn local_addr(&self) -> String {
+        format!("memory:{}", self.name)
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        memory_listeners().lock().unwrap().remove(&self.name);
+    }
+}
+
+// Bytes written to one end, waiting to be read from the other
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<(VecDeque<u8>, bool)>,
+    ready: Condvar,
+}
+
+impl Pipe {
+    fn close(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.ready.notify_all();
+    }
+}
+
+// One side of an in-memory connection, closed once every clone of it is dropped or shut down
+struct MemoryEnd {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl Drop for MemoryEnd {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+#[derive(Clone)]
+struct MemoryStream(Arc<MemoryEnd>);
+
+impl MemoryStream {
+    fn pair() -> (Self, Self) {
+        let (a, b) = (Arc::new(Pipe::default()), Arc::new(Pipe::default()));
+
+        let end = |incoming, outgoing| {
+            Self(Arc::new(MemoryEnd {
+                incoming,
+                outgoing,
+                read_timeout: Mutex::new(None),
+            }))
+        };
+
+        (end(a.clone(), b.clone()), end(b, a))
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pipe = &self.0.incoming;
+        let deadline = self
+            .0
+            .read_timeout
+            .lock()
+            .unwrap()
+            .map(|t| Instant::now() + t);
+        let mut state = pipe.state.lock().unwrap();
+
+        loop {
+            let (buffer, closed) = &mut *state;
+
+            if !buffer.is_empty() {
+                let count = buf.len().min(buffer.len());
+
+                for (slot, byte) in buf.iter_mut().zip(buffer.drain(..count)) {
+                    *slot = byte;
+                }
+
+                return Ok(count);
+            }
+
+            if *closed {
+                return Ok(0);
+            }
+
+            state = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+
+                    if timeout.is_zero() {
+                        return Err(ErrorKind::WouldBlock.into());
+                    }
+
+                    pipe.ready.wait_timeout(state, timeout).unwrap().0
+                }
+                None => pipe.ready.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let pipe = &self.0.outgoing;
+        let mut state = pipe.state.lock().unwrap();
+
+        if state.1 {
+            return Err(ErrorKind::BrokenPipe.into());
+        }
+
+        state.0.extend(buf);
+        pipe.ready.notify_all();
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for MemoryStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self.0.read_timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.0.incoming.close();
+        self.0.outgoing.close();
+        Ok(())
+    }
+}
@@ -0,0 +1,628 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use eframe::egui::{self, Ui};
+use rfd::FileDialog;
+
+use crate::{
+    ir::Database,
+    net::{Client, ConnectionState, Object, ObjectId, Query, Role, Server},
+    project::{Project, ProjectKind, Tab},
+    record::Recorder,
+    tls::{Identity, Tls},
+    transport::{self, Transport},
+};
+
+#[derive(Default)]
+pub struct OpenProjectMenu {
+    address_text: String,
+    fingerprint_text: String,
+    certificate_text: String,
+    token_text: String,
+    read_only_token_text: String,
+    record_text: String,
+}
+
+impl OpenProjectMenu {
+    pub fn render(
+        &mut self,
+        ui: &mut Ui,
+        projects: &mut Vec<Project>,
+        errors: &mut VecDeque<String>,
+        remain_open: &mut bool,
+    ) {
+        if ui.button("Close").clicked() {
+            *remain_open = false
+        }
+
+        ui.add_space(20.0);
+
+        //Open project from file
+        let open = ui.button("Open").clicked();
+
+        ui.add_space(5.0);
+
+        //Create new project
+        let new = ui.button("New").clicked();
+
+        if open || new {
+            let file = if new {
+                FileDialog::new().save_file()
+            } else {
+                FileDialog::new().pick_file()
+            };
+
+            let Some(file) = file else { return };
+
+            let filename = file.file_name().unwrap().to_string_lossy().to_string();
+
+            let project = match Project::create(ProjectKind::Local(file), filename) {
+                Ok(project) => project,
+                Err(e) => {
+                    errors.push_back(format!("Could not open project: {}", e));
+                    return;
+                }
+            };
+
+            projects.push(project);
+            *remain_open = false;
+        }
+
+        ui.add_space(15.0);
+
+        let address_label = ui.label("Address:");
+        ui.add(
+            egui::TextEdit::singleline(&mut self.address_text)
+                .hint_text("127.0.0.1:12007, [::1], host:port or unix:/path"),
+        )
+        .labelled_by(address_label.id);
+
+        let fingerprint_label = ui.label("Certificate fingerprint:");
+        ui.add(
+            egui::TextEdit::singleline(&mut self.fingerprint_text)
+                .hint_text("SHA-256 to connect over TLS"),
+        )
+        .labelled_by(fingerprint_label.id);
+
+        let certificate_label = ui.label("Certificate file:");
+        ui.add(
+            egui::TextEdit::singleline(&mut self.certificate_text)
+                .hint_text("PEM to listen over TLS, generated if missing"),
+        )
+        .labelled_by(certificate_label.id);
+
+        let token_label = ui.label("Token:");
+        ui.add(
+            egui::TextEdit::singleline(&mut self.token_text)
+                .password(true)
+                .hint_text("shared with the peers"),
+        )
+        .labelled_by(token_label.id);
+
+        let read_only_token_label = ui.label("Read-only token:");
+        ui.add(
+            egui::TextEdit::singleline(&mut self.read_only_token_text)
+                .password(true)
+                .hint_text("to listen with, for peers that may not change anything"),
+        )
+        .labelled_by(read_only_token_label.id);
+
+        let record_label = ui.label("Record to file:");
+        ui.add(
+            egui::TextEdit::singleline(&mut self.record_text)
+                .hint_text("every message exchanged, to replay later"),
+        )
+        .labelled_by(record_label.id);
+
+        if ui.button("Connect").clicked() {
+            let Some(transport) = self.transport(false, errors) else {
+                return;
+            };
+
+            let Ok(recorder) = self.recorder(errors) else {
+                return;
+            };
+
+            let project_name = transport.to_string();
+
+            let client = match Client::connect(transport, self.token(), recorder) {
+                Ok(client) => client,
+                Err(e) => {
+                    errors.push_back(format!("Could not connect: {}", e));
+                    return;
+                }
+            };
+
+            let project = match Project::create(ProjectKind::Remote(client), project_name) {
+                Ok(project) => project,
+                Err(e) => {
+                    errors.push_back(format!("Could not create project: {}", e));
+                    return;
+                }
+            };
+
+            projects.push(project);
+            *remain_open = false
+        }
+
+        ui.add_space(5.0);
+
+        //Accept connections from plugins and relay between them
+        if ui.button("Listen").clicked() {
+            let Some(transport) = self.transport(true, errors) else {
+                return;
+            };
+
+            let Ok(recorder) = self.recorder(errors) else {
+                return;
+            };
+
+            let server = match Server::listen(transport.as_ref(), self.tokens(), recorder) {
+                Ok(server) => server,
+                Err(e) => {
+                    errors.push_back(format!("Could not listen: {}", e));
+                    return;
+                }
+            };
+
+            let project_name = format!("Hub {}", server.local_addr);
+
+            let project = match Project::create(ProjectKind::Hub(server), project_name) {
+                Ok(project) => project,
+                Err(e) => {
+                    errors.push_back(format!("Could not create project: {}", e));
+                    return;
+                }
+            };
+
+            projects.push(project);
+            *remain_open = false
+        }
+    }
+
+    // The address, wrapped in TLS if a fingerprint to connect with or a certificate to listen
+    // with was given
+    fn transport(
+        &self,
+        listening: bool,
+        errors: &mut VecDeque<String>,
+    ) -> Option<Box<dyn Transport>> {
+        let address = match self.address_text.trim() {
+            "" => "127.0.0.1",
+            address => address,
+        };
+
+        let fingerprint = self.fingerprint_text.trim();
+        let certificate = self.certificate_text.trim();
+
+        let transport = transport::parse(address).and_then(|transport| {
+            if listening && !certificate.is_empty() {
+                let identity = Identity::load_or_generate(Path::new(certificate))?;
+                Ok(Box::new(Tls::listening(transport, &identity)?) as Box<dyn Transport>)
+            } else if !listening && !fingerprint.is_empty() {
+                Ok(Box::new(Tls::connecting(transport, fingerprint)?))
+            } else {
+                Ok(transport)
+            }
+        });
+
+        match transport {
+            Ok(transport) => Some(transport),
+            Err(e) => {
+                errors.push_back(e);
+                None
+            }
+        }
+    }
+
+    // Nothing is recorded without a file to record to
+    fn recorder(&self, errors: &mut VecDeque<String>) -> Result<Option<Arc<Recorder>>, ()> {
+        let path = self.record_text.trim();
+
+        if path.is_empty() {
+            return Ok(None);
+        }
+
+        match Recorder::create(Path::new(path)) {
+            Ok(recorder) => Ok(Some(recorder)),
+            Err(e) => {
+                errors.push_back(format!("Could not record to {}: {}", path, e));
+                Err(())
+            }
+        }
+    }
+
+    fn token(&self) -> Option<String> {
+        Some(self.token_text.clone()).filter(|token| !token.is_empty())
+    }
+
+    // Tokens to listen with, where peers with the read-only one cannot change anything
+    fn tokens(&self) -> HashMap<String, Role> {
+        let read_only = Some(self.read_only_token_text.clone()).filter(|token| !token.is_empty());
+
+        let contributor = self.token().map(|token| (token, Role::Contributor));
+        let read_only = read_only.map(|token| (token, Role::ReadOnly));
+
+        contributor.into_iter().chain(read_only).collect()
+    }
+}
+
+impl Project {
+    // Render the project UI and handle input
+    pub fn render(
+        &mut self,
+        ui: &mut Ui,
+        errors: &mut VecDeque<String>,
+        clipboard: &mut HashMap<String, Object>,
+    ) {
+        if ui.input(|i| i.modifiers.ctrl && i.key_released(egui::Key::S)) {
+            self.save(errors);
+        }
+
+        // text fields get these keys while they are focused
+        let typing = ui.ctx().wants_keyboard_input();
+
+        if !typing && ui.input(|i| i.events.iter().any(|e| matches!(e, egui::Event::Paste(_)))) {
+            self.add_objects(clipboard.clone(), errors)
+        }
+
+        if !typing && ui.input(|i| i.events.iter().any(|e| matches!(e, egui::Event::Copy))) {
+            *clipboard = self.get_selected()
+        }
+
+        if !typing && ui.input(|i| i.key_released(egui::Key::Delete)) {
+            let contents = std::mem::take(&mut self.selected);
+
+            for object_id in contents {
+                self.delete_object(&object_id, errors)
+            }
+        }
+
+        if ui.input(|i| i.key_released(egui::Key::Escape)) {
+            self.selected.clear()
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Types").clicked() {
+                self.current_tab = Tab::Types
+            }
+
+            if ui.button("Functions").clicked() {
+                self.current_tab = Tab::Functions
+            }
+
+            if ui.button("Globals").clicked() {
+                self.current_tab = Tab::Globals
+            }
+
+            if ui.button("Comments").clicked() {
+                self.current_tab = Tab::Comments
+            }
+
+            if let ProjectKind::Remote(client) = &self.kind {
+                ui.separator();
+
+                let peer = client.peer();
+
+                match client.state() {
+                    ConnectionState::Connected if self.resync.is_some() => {
+                        ui.label(format!("Resyncing with {}...", peer.tool))
+                    }
+                    ConnectionState::Connected => ui.label(format!(
+                        "{} {} ({}, {} compression)",
+                        peer.tool,
+                        peer.tool_version,
+                        client.encoding(),
+                        client.compression()
+                    )),
+                    ConnectionState::Reconnecting { attempt, retry_at } => ui.label(format!(
+                        "Disconnected, reconnecting in {}s (attempt {})",
+                        retry_at.saturating_duration_since(Instant::now()).as_secs(),
+                        attempt
+                    )),
+                };
+
+                ui.separator();
+                ui.label(client.traffic().to_string());
+
+                if let Some(address) = self.cursor {
+                    ui.separator();
+                    ui.label(format!("At {:#x}", address));
+                }
+            }
+
+            if let ProjectKind::Hub(server) = &self.kind {
+                ui.separator();
+
+                let tools: Vec<String> = server
+                    .peers()
+                    .into_iter()
+                    .map(|(peer, hello)| {
+                        let mut tool = format!("{} {}", hello.tool, hello.tool_version);
+
+                        if let Some(address) = self.peer_cursors.get(&peer) {
+                            tool += &format!(" at {:#x}", address);
+                        }
+
+                        match server.role(peer) {
+                            Some(role) if role != Role::Contributor => {
+                                format!("{} ({})", tool, role)
+                            }
+                            _ => tool,
+                        }
+                    })
+                    .collect();
+
+                ui.label(format!(
+                    "Listening on {}, {} connected: {}",
+                    server.local_addr,
+                    tools.len(),
+                    tools.join(", ")
+                ));
+
+                ui.separator();
+                ui.label(server.traffic().to_string());
+
+                ui.separator();
+
+                if ui
+                    .button("Lock")
+                    .on_hover_text("Keep the peers from changing the selected objects")
+                    .clicked()
+                {
+                    self.lock_selected(errors);
+                }
+
+                if ui.button("Unlock").clicked() {
+                    self.unlock_selected(errors);
+                }
+            }
+
+            if !matches!(self.kind, ProjectKind::Local(_)) {
+                ui.separator();
+                ui.checkbox(&mut self.follow, "Follow")
+                    .on_hover_text("Navigate along when a tool of another project moves");
+                ui.toggle_value(&mut self.show_activity, "Activity");
+            }
+        });
+
+        if self.show_activity {
+            egui::SidePanel::right("activity").show_inside(ui, |ui| self.render_activity(ui));
+        }
+
+        if let ProjectKind::Remote(_) = &self.kind {
+            self.render_pull_bar(ui, errors);
+            self.render_rejected(ui);
+        }
+
+        match self.current_tab {
+            Tab::Types => Self::render_main_view(
+                &mut self.selected,
+                ui,
+                self.db.types.len(),
+                self.db
+                    .types
+                    .iter()
+                    .map(|t| (&t.object_id, lock_label(&self.db, &t.object_id, &t.name))),
+            ),
+            Tab::Functions => Self::render_main_view(
+                &mut self.selected,
+                ui,
+                self.db.functions.len(),
+                self.db
+                    .functions
+                    .iter()
+                    .map(|f| (&f.object_id, lock_label(&self.db, &f.object_id, &f.name))),
+            ),
+            Tab::Globals => Self::render_main_view(
+                &mut self.selected,
+                ui,
+                self.db.data.len(),
+                self.db
+                    .data
+                    .iter()
+                    .map(|d| (&d.object_id, lock_label(&self.db, &d.object_id, &d.name))),
+            ),
+            Tab::Comments => Self::render_main_view(
+                &mut self.selected,
+                ui,
+                self.db.comments.len(),
+                self.db.comments.iter().map(|c| {
+                    // rows are a single line high
+                    let text = c.text.lines().next().unwrap_or_default();
+                    let target = self.db.comment_target(c);
+
+                    let text = format!("{}: {}", target, text);
+                    (&c.object_id, lock_label(&self.db, &c.object_id, &text))
+                }),
+            ),
+        };
+    }
+
+    fn render_pull_bar(&mut self, ui: &mut Ui, errors: &mut VecDeque<String>) {
+        let mut query = None;
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.pull_text)
+                    .hint_text("type name or 0x1000-0x2000"),
+            );
+
+            if ui.button("Pull Type").clicked() {
+                query = Some(Query::Type {
+                    name: self.pull_text.clone(),
+                });
+            }
+
+            if ui.button("Pull Functions").clicked() {
+                match parse_range(&self.pull_text) {
+                    Some((start, end)) => query = Some(Query::Functions { start, end }),
+                    None => errors.push_back(format!(
+                        "Expected an address range like 0x1000-0x2000, got {}",
+                        self.pull_text
+                    )),
+                }
+            }
+
+            if ui.button("Pull Changes").clicked() {
+                query = Some(Query::Changed {
+                    since: self.revision,
+                });
+            }
+        });
+
+        if let Some(query) = query {
+            self.pull(query, errors)
+        }
+    }
+
+    // Who is connected, and what the peers did most recently first
+    fn render_activity(&self, ui: &mut Ui) {
+        ui.heading("Connected");
+
+        match &self.kind {
+            ProjectKind::Remote(client) => match client.state() {
+                ConnectionState::Connected => ui.label(client.peer().identity()),
+                ConnectionState::Reconnecting { .. } => ui.weak("Nobody"),
+            },
+            ProjectKind::Hub(_) if self.peer_identities.is_empty() => ui.weak("Nobody"),
+            _ => {
+                ui.vertical(|ui| {
+                    let mut identities: Vec<_> = self.peer_identities.iter().collect();
+                    identities.sort();
+
+                    for (_, identity) in identities {
+                        ui.label(identity);
+                    }
+                })
+                .response
+            }
+        };
+
+        ui.separator();
+        ui.heading("Activity");
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            let now = Instant::now();
+
+            for activity in self.activity.entries.iter().rev() {
+                ui.horizontal_wrapped(|ui| {
+                    ui.strong(&activity.who);
+                    ui.label(&activity.what);
+                    ui.weak(format_ago(now.duration_since(activity.at)));
+                });
+            }
+        });
+    }
+
+    // List the objects the remote tool rejected so they can be retried once the cause is fixed
+    fn render_rejected(&mut self, ui: &mut Ui) {
+        if self.rejected.is_empty() {
+            return;
+        }
+
+        let mut retry = Vec::new();
+        let mut dismiss = Vec::new();
+
+        egui::CollapsingHeader::new(format!("Rejected ({})", self.rejected.len())).show(ui, |ui| {
+            for (object_id, (object, reason)) in &self.rejected {
+                ui.horizontal(|ui| {
+                    if ui.button("Retry").clicked() {
+                        retry.push(object_id.clone())
+                    }
+
+                    if ui.button("Dismiss").clicked() {
+                        dismiss.push(object_id.clone())
+                    }
+
+                    ui.label(format!("{}: {}", object.name(), reason));
+                });
+            }
+
+            if ui.button("Retry All").clicked() {
+                retry.extend(self.rejected.keys().cloned())
+            }
+        });
+
+        for object_id in dismiss {
+            self.rejected.remove(&object_id);
+        }
+
+        let data: HashMap<ObjectId, Object> = retry
+            .into_iter()
+            .filter_map(|object_id| {
+                let (object, _) = self.rejected.remove(&object_id)?;
+                Some((object_id, object))
+            })
+            .collect();
+
+        if !data.is_empty() {
+            self.push_remote(data)
+        }
+    }
+
+    // Lists objects by name, selecting them by id
+    fn render_main_view<'a, I: Iterator<Item = (&'a ObjectId, N)>, N: Into<egui::WidgetText>>(
+        selected: &mut HashSet<ObjectId>,
+        ui: &mut Ui,
+        len: usize,
+        iter: I,
+    ) {
+        let text_style = ui.text_style_height(&egui::TextStyle::Body);
+
+        ui.columns(2, |ui| {
+            egui::ScrollArea::vertical().show_rows(&mut ui[0], text_style, len, |ui, row_range| {
+                let objects = iter.skip(row_range.start).take(row_range.count());
+
+                for (object_id, name) in objects {
+                    let is_selected = selected.contains(object_id);
+                    let label = ui.selectable_label(is_selected, name);
+
+                    if label.clicked() && is_selected {
+                        selected.remove(object_id);
+                    } else if label.clicked() && !is_selected {
+                        selected.insert(object_id.clone());
+                    }
+                }
+            });
+        })
+    }
+}
+
+// The name of an object, along with who holds it if it is locked
+fn lock_label(db: &Database, object_id: &str, name: &str) -> String {
+    match db.lock_of(object_id) {
+        Some(lock) => format!("{} (locked by {})", name, lock.who),
+        None => name.to_string(),
+    }
+}
+
+// How long ago something happened, to the largest whole unit
+fn format_ago(elapsed: Duration) -> String {
+    match elapsed.as_secs() {
+        seconds if seconds < 60 => format!("{}s ago", seconds),
+        seconds if seconds < 60 * 60 => format!("{}m ago", seconds / 60),
+        seconds => format!("{}h ago", seconds / (60 * 60)),
+    }
+}
+
+// Parses ranges in the form `start-end`, where both addresses are either decimal or 0x-prefixed hex
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (start, end) = text.split_once('-')?;
+
+    let parse = |text: &str| {
+        let text = text.trim();
+
+        match text.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => usize::from_str(text).ok(),
+        }
+    };
+
+    Some((parse(start)?, parse(end)?))
+}
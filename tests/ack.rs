@@ -0,0 +1,113 @@
+// Pushes that carry an id are answered with what was applied, along with what was rejected and
+// why
+mod common;
+
+use std::collections::HashMap;
+
+use binal::{
+    mock::MockPeer,
+    net::{function_id, Client, Message},
+    transport::Memory,
+};
+
+use common::{
+    connect_raw, function, function_taking, hub, object, program, send_raw, settle, TIMEOUT,
+};
+
+#[test]
+fn hub_tells_peers_what_it_applied() {
+    let mut hub = hub("ack-hub");
+    let (mut stream, messages) = connect_raw("ack-hub", &["push", "ack", "reject"]);
+
+    let objects = HashMap::from([
+        (function_id(0x1000), function("main", 0x1000)),
+        (
+            function_id(0x2000),
+            function_taking("bad", 0x2000, "Missing"),
+        ),
+    ]);
+    send_raw(
+        &mut stream,
+        &Message::Push {
+            id: Some(3),
+            objects,
+            author: None,
+        },
+    );
+
+    // only pushes that asked for it are answered
+    for (id, location) in [(None, 0x3000), (Some(4), 0x4000)] {
+        let objects = HashMap::from([(function_id(location), function("other", location))]);
+        send_raw(
+            &mut stream,
+            &Message::Push {
+                id,
+                objects,
+                author: None,
+            },
+        );
+    }
+
+    let mut replies = Vec::new();
+    assert!(settle(&mut hub, |_| {
+        replies.extend(
+            messages
+                .try_iter()
+                .filter(|m| !matches!(m, Message::Hello(_))),
+        );
+        replies.len() >= 3
+    }));
+
+    let Message::Reject { id: 3, rejected } = &replies[0] else {
+        panic!("expected a reject, got {:?}", replies[0]);
+    };
+    assert_eq!(
+        rejected,
+        &HashMap::from([(function_id(0x2000), String::from("unknown type Missing"))])
+    );
+
+    let acks: Vec<(u64, &[String])> = replies[1..]
+        .iter()
+        .map(|reply| match reply {
+            Message::Ack { id, applied } => (*id, applied.as_slice()),
+            _ => panic!("expected an ack, got {:?}", reply),
+        })
+        .collect();
+
+    let (main, other) = (function_id(0x1000), function_id(0x4000));
+    assert_eq!(acks, [(3, [main].as_slice()), (4, [other].as_slice())]);
+    assert_eq!(hub.database().get_net().len(), 3);
+}
+
+#[test]
+fn client_hears_back_about_its_pushes() {
+    let transport = Memory(String::from("ack-remote"));
+    let mock = MockPeer::listen(&transport, program(), None).unwrap();
+    let mut client = Client::connect(Box::new(transport), None, None).unwrap();
+
+    // the mock, like Binary Ninja, has nowhere to put comments on types
+    let comment = object(r#"{"kind":"comment","target":{"kind":"type","name":"S"},"text":"?"}"#);
+    let objects = HashMap::from([
+        (function_id(0x2000), function("helper", 0x2000)),
+        (String::from("comment:1"), comment),
+    ]);
+
+    let id = client.push(objects).unwrap().expect("the mock sends acks");
+
+    let replies: Vec<Message> = std::iter::from_fn(|| client.rx.recv_timeout(TIMEOUT).ok())
+        .filter(|m| matches!(m, Message::Reject { .. } | Message::Ack { .. }))
+        .take(2)
+        .collect();
+
+    assert!(matches!(
+        &replies[0],
+        Message::Reject { id: rejected_id, rejected }
+            if *rejected_id == id && rejected.keys().eq(["comment:1"])
+    ));
+    assert!(matches!(
+        &replies[1],
+        Message::Ack { id: acked_id, applied }
+            if *acked_id == id && applied == &[function_id(0x2000)]
+    ));
+    assert!(mock.program().objects.contains_key(&function_id(0x2000)));
+}
@@ -0,0 +1,109 @@
+// A hub keeps track of who changed what, and tells its peers, naming each peer by who it said it
+// was in its hello rather than by whatever it claims in its messages
+mod common;
+
+use std::collections::HashMap;
+
+use binal::{
+    mock::{MockPeer, Program},
+    net::{function_id, Hello, Message},
+    project::Project,
+};
+
+use common::{connect_raw, function, hub, join, send_raw, settle};
+
+// Who the raw peer from `connect_raw` is
+fn raw() -> String {
+    Hello {
+        tool: String::from("raw"),
+        ..common::hello(&["json"], &[])
+    }
+    .identity()
+}
+
+// The authors of what the mock was sent, by message kind
+fn authors(mock: &MockPeer) -> Vec<(&'static str, Option<String>)> {
+    mock.received()
+        .into_iter()
+        .filter_map(|message| match message {
+            Message::Push { author, .. } => Some(("push", author)),
+            Message::Delete { author, .. } => Some(("delete", author)),
+            Message::Rename { author, .. } => Some(("rename", author)),
+            Message::Lock { holder, .. } => Some(("lock", holder)),
+            Message::Unlock { author, .. } => Some(("unlock", author)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn activity(project: &Project) -> Vec<(String, String)> {
+    project
+        .activity()
+        .map(|(who, what)| (who.to_string(), what.to_string()))
+        .collect()
+}
+
+#[test]
+fn hub_names_peers_by_who_they_are() {
+    let mut hub = hub("activity-hub");
+    let mock = join("activity-hub", Program::default(), None);
+    let (mut stream, _messages) = connect_raw(
+        "activity-hub",
+        &["push", "delete", "rename", "lock", "unlock"],
+    );
+
+    // every change claims to be made by someone else
+    let someone = Some(String::from("mallory (ida)"));
+    let main = function_id(0x1000);
+    let changes = [
+        Message::Push {
+            id: None,
+            objects: HashMap::from([(main.clone(), function("main", 0x1000))]),
+            author: someone.clone(),
+        },
+        Message::Rename {
+            kind: String::from("function"),
+            old: String::from("main"),
+            new: String::from("entry"),
+            author: someone.clone(),
+        },
+        Message::Lock {
+            object: main.clone(),
+            holder: someone.clone(),
+        },
+        Message::Unlock {
+            object: main.clone(),
+            author: someone.clone(),
+        },
+        Message::Delete {
+            object: main,
+            author: someone,
+        },
+    ];
+
+    for change in &changes {
+        send_raw(&mut stream, change);
+    }
+    assert!(settle(&mut hub, |_| authors(&mock).len() == changes.len()));
+
+    let raw = raw();
+    let kinds = ["push", "rename", "lock", "unlock", "delete"];
+    let expected: Vec<_> = kinds.map(|kind| (kind, Some(raw.clone()))).to_vec();
+    assert_eq!(authors(&mock), expected);
+
+    stream.shutdown().unwrap();
+    assert!(settle(&mut hub, |p| p.activity().count() == 8));
+
+    let expected = [
+        ("mock (mock)", "joined"),
+        (&raw, "joined"),
+        (&raw, "changed main"),
+        (&raw, "renamed function main to entry"),
+        (&raw, "locked entry"),
+        (&raw, "unlocked entry"),
+        (&raw, "deleted entry"),
+        (&raw, "left"),
+    ]
+    .map(|(who, what)| (who.to_string(), what.to_string()));
+    assert_eq!(activity(&hub), expected);
+}
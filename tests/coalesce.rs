@@ -0,0 +1,171 @@
+// Pushes arriving in quick succession are merged, while nothing sent is lost to a network thread
+// that has fallen behind
+mod common;
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    net::TcpListener,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use binal::{
+    mock::{MockPeer, Program},
+    net::{function_id, Client, Hello, Message, Object},
+    transport::{Memory, Stream, Tcp},
+};
+
+use common::{connected, object, read_raw, send_raw, TIMEOUT};
+
+// A function returning an int of `size` bytes, so updates to it can be told apart
+fn function(name: &str, location: usize, size: u16) -> Object {
+    object(&format!(
+        r#"{{"kind":"function","name":"{}","location":{},"arguments":[],
+             "return_type":{{"kind":"int","size":{}}}}}"#,
+        name, location, size
+    ))
+}
+
+#[test]
+fn merges_repeated_updates() {
+    let transport = Memory(String::from("coalesce-merge"));
+    let mock = MockPeer::listen(&transport, Program::default(), None).unwrap();
+    let client = Client::connect(Box::new(transport), None, None).unwrap();
+    assert!(connected(&mock));
+
+    for size in 1..=100 {
+        mock.define(function_id(0x1000), function("main", 0x1000, size));
+    }
+
+    let last = function("main", 0x1000, 100).content_hash();
+    let mut pushes = 0;
+
+    loop {
+        let Ok(Message::Push { objects, .. }) = client.rx.recv_timeout(TIMEOUT) else {
+            panic!("the last update never arrived");
+        };
+
+        pushes += 1;
+
+        if objects[&function_id(0x1000)].content_hash() == last {
+            break;
+        }
+    }
+
+    assert!(pushes < 100, "{} pushes were not merged", pushes);
+    assert!(client.traffic().merged > 0);
+}
+
+#[test]
+fn sends_everything_pushed_while_behind() {
+    let transport = Memory(String::from("coalesce-queue"));
+    let mock = MockPeer::listen(&transport, Program::default(), None).unwrap();
+    let mut client = Client::connect(Box::new(transport), None, None).unwrap();
+    assert!(connected(&mock));
+
+    // far more than fit in the channels between threads
+    let count = 5000;
+
+    for location in 0..count {
+        let objects = HashMap::from([(function_id(location), function("f", location, 4))]);
+        client.push(objects).unwrap();
+    }
+
+    let deadline = Instant::now() + TIMEOUT;
+
+    while !mock.wait_until(Duration::from_millis(10), |p| p.objects.len() == count) {
+        assert!(Instant::now() < deadline, "not every push arrived");
+        while client.rx.try_recv().is_ok() {}
+    }
+
+    assert_eq!(client.traffic().dropped, 0);
+}
+
+#[test]
+fn holds_back_what_a_stalled_peer_cannot_take() {
+    // over TCP, so the peer not reading eventually stops the network thread writing
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    let accepted = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut stream: Box<dyn Stream> = Box::new(stream);
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+
+        let hello = Hello {
+            tool: String::from("raw"),
+            messages: ["push", "cursor"].map(String::from).to_vec(),
+            ..common::hello(&["json"], &[])
+        };
+        send_raw(&mut stream, &Message::Hello(Box::new(hello)));
+
+        (stream, reader)
+    });
+
+    let client = Client::connect(Box::new(Tcp(address)), None, None).unwrap();
+    let (_stream, reader) = accepted.join().unwrap();
+
+    // far more than the channels and socket buffers hold, which would block if it was queued
+    let rounds = 4000;
+    let locations = 0..10;
+    let name = "f".repeat(1000);
+    let (done, sent) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        for round in 0..rounds {
+            let objects = locations
+                .clone()
+                .map(|location| (function_id(location), function(&name, location, round)))
+                .collect();
+
+            let push = Message::Push {
+                id: None,
+                objects,
+                author: None,
+            };
+
+            client.send(push).unwrap();
+            client.send(Message::Cursor { address: 0 }).unwrap();
+        }
+
+        let _ = done.send(client);
+    });
+
+    let client = sent
+        .recv_timeout(TIMEOUT)
+        .expect("sending waited for the peer");
+    let traffic = client.traffic();
+    assert!(traffic.merged > 0);
+    assert!(traffic.dropped > 0);
+
+    // once the peer reads again, the latest version of everything arrives
+    let rx = read_raw(reader);
+    let last: HashMap<_, _> = (0..10)
+        .map(|location| {
+            let object = function(&"f".repeat(1000), location, rounds - 1);
+            (function_id(location), object.content_hash())
+        })
+        .collect();
+    let mut latest = HashMap::new();
+    let deadline = Instant::now() + TIMEOUT;
+
+    while latest != last {
+        assert!(
+            Instant::now() < deadline,
+            "the latest versions never arrived"
+        );
+        client.flush().unwrap();
+
+        while let Ok(message) = rx.recv_timeout(Duration::from_millis(10)) {
+            if let Message::Push { objects, .. } = message {
+                for (object_id, object) in objects {
+                    latest.insert(object_id, object.content_hash());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+// Comments are objects like any other, synced between tools, and keep what they are on from
+// being deleted
+mod common;
+
+use std::collections::HashMap;
+
+use binal::{
+    ir::{Database, DatabaseError},
+    mock::Program,
+    net::Object,
+    project::Project,
+};
+
+use common::{hub, join, object, program, settle, structure};
+
+fn comment(address: usize, text: &str) -> Object {
+    object(&format!(
+        r#"{{"kind":"comment","target":{{"kind":"address","address":{}}},"text":"{}"}}"#,
+        address, text
+    ))
+}
+
+fn text(program: &Program, object_id: &str) -> Option<String> {
+    program
+        .objects
+        .get(object_id)
+        .map(|object| object.name().clone())
+}
+
+fn text_in(project: &Project, object_id: &str) -> Option<String> {
+    project.database().name(object_id).cloned()
+}
+
+#[test]
+fn hub_relays_comments_and_their_edits() {
+    let mut hub = hub("comments-hub");
+    let author = join("comments-hub", program(), None);
+    let reader = join("comments-hub", Program::default(), None);
+    assert!(settle(&mut hub, |_| reader.program().objects.len() == 2));
+
+    let id = "comment:0x1004";
+
+    author.define(String::from(id), comment(0x1004, "checks the argument"));
+    assert!(settle(&mut hub, |_| {
+        text(&reader.program(), id).as_deref() == Some("checks the argument")
+    }));
+
+    // there is one comment per place, so an edit replaces the text
+    author.define(String::from(id), comment(0x1004, "returns early if null"));
+    assert!(settle(&mut hub, |_| {
+        text(&reader.program(), id).as_deref() == Some("returns early if null")
+    }));
+    assert_eq!(text_in(&hub, id).as_deref(), Some("returns early if null"));
+    assert_eq!(hub.database().get_net().len(), 3);
+
+    author.remove(id);
+    assert!(settle(&mut hub, |p| {
+        text_in(p, id).is_none() && text(&reader.program(), id).is_none()
+    }));
+}
+
+#[test]
+fn comments_keep_their_type_around() {
+    let mut db = Database::default();
+
+    let objects = HashMap::from([
+        (String::from("type:s"), structure("S")),
+        (
+            String::from("comment:type:s"),
+            object(r#"{"kind":"comment","target":{"kind":"type","name":"S"},"text":"opaque"}"#),
+        ),
+    ]);
+    assert!(db.push_net(objects).rejected.is_empty());
+
+    let Err(DatabaseError::Referenced(name, by)) = db.delete("type:s") else {
+        panic!("deleted a type with a comment on it");
+    };
+    assert_eq!(
+        (name.as_str(), by.as_slice()),
+        ("S", [String::from("the comment on S")].as_slice())
+    );
+
+    assert!(db.delete("comment:type:s").unwrap());
+    assert!(db.delete("type:s").unwrap());
+}
@@ -0,0 +1,201 @@
+// What the integration tests share: made up objects, hubs on in-memory connections, and a way to
+// drive a project until something happened. Each test file uses only some of it
+#![allow(dead_code)]
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, BufReader, Write},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use binal::{
+    mock::{MockPeer, Program},
+    net::{function_id, Hello, Message, Object, ObjectId, Role, Server},
+    project::{Project, ProjectKind},
+    transport::{Memory, Stream, Transport},
+};
+use serde::Serialize;
+
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn object(json: &str) -> Object {
+    serde_json::from_str(json).unwrap()
+}
+
+// A function without arguments returning an int
+pub fn function(name: &str, location: usize) -> Object {
+    object(&format!(
+        r#"{{"kind":"function","name":"{}","location":{},"arguments":[],
+             "return_type":{{"kind":"int","size":4}}}}"#,
+        name, location
+    ))
+}
+
+// A function taking a pointer to the type named `argument`, which it depends on
+pub fn function_taking(name: &str, location: usize, argument: &str) -> Object {
+    object(&format!(
+        r#"{{"kind":"function","name":"{}","location":{},
+             "arguments":[{{"name":"s","type":{{"kind":"pointer","depth":1,"name":"{}"}}}}],
+             "return_type":{{"kind":"int","size":4}}}}"#,
+        name, location, argument
+    ))
+}
+
+// An empty struct of `size` bytes
+pub fn structure_of(name: &str, size: usize) -> Object {
+    object(&format!(
+        r#"{{"kind":"type","name":"{}","size":{},"alignment":4,
+             "info":{{"kind":"struct","fields":[]}}}}"#,
+        name, size
+    ))
+}
+
+pub fn structure(name: &str) -> Object {
+    structure_of(name, 4)
+}
+
+// A struct S and `main` taking a pointer to it
+pub fn program() -> Program {
+    let objects: HashMap<ObjectId, Object> = [
+        (String::from("type:s"), structure("S")),
+        (function_id(0x1000), function_taking("main", 0x1000, "S")),
+    ]
+    .into();
+
+    Program { objects }
+}
+
+// Functions without arguments, by name and location
+pub fn functions(functions: &[(&str, usize)]) -> Program {
+    let objects = functions
+        .iter()
+        .map(|(name, location)| (function_id(*location), function(name, *location)))
+        .collect();
+
+    Program { objects }
+}
+
+// What a peer speaking the given encodings and compression methods says first
+pub fn hello(encodings: &[&str], compression: &[&str]) -> Hello {
+    Hello {
+        encodings: encodings.iter().map(|e| e.to_string()).collect(),
+        compression: compression.iter().map(|c| c.to_string()).collect(),
+        ..Hello::default()
+    }
+}
+
+pub fn hub(address: &str) -> Project {
+    hub_with(address, HashMap::new())
+}
+
+pub fn hub_with(address: &str, tokens: HashMap<String, Role>) -> Project {
+    let server = Server::listen(&Memory(String::from(address)), tokens, None).unwrap();
+    Project::create(ProjectKind::Hub(server), String::from("hub")).unwrap()
+}
+
+// Connects a mock to the hub listening on `address`
+pub fn join(address: &str, program: Program, token: Option<&str>) -> MockPeer {
+    let transport = Memory(String::from(address));
+    MockPeer::connect(&transport, program, token.map(String::from)).unwrap()
+}
+
+// Handles network updates until `condition` holds, or gives up after a while
+pub fn settle(project: &mut Project, mut condition: impl FnMut(&Project) -> bool) -> bool {
+    let deadline = Instant::now() + TIMEOUT;
+    let mut errors = VecDeque::new();
+
+    while Instant::now() < deadline {
+        project.handle_network_updates(&mut errors);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        if condition(project) {
+            return true;
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    false
+}
+
+// Keeps handling network updates for a while, to give what should not happen the chance to
+pub fn linger(project: &mut Project, duration: Duration) {
+    let until = Instant::now() + duration;
+    assert!(settle(project, |_| Instant::now() >= until));
+}
+
+// Waits for binal to connect to a mock listening for it
+pub fn connected(mock: &MockPeer) -> bool {
+    let deadline = Instant::now() + TIMEOUT;
+
+    while mock.connections() == 0 {
+        if Instant::now() >= deadline {
+            return false;
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    true
+}
+
+// Speaks the protocol by hand as JSON lines, to send what the mock never would, announcing the
+// message kinds in `messages`. What binal sends back arrives on the returned channel
+pub fn connect_raw(address: &str, messages: &[&str]) -> (Box<dyn Stream>, mpsc::Receiver<Message>) {
+    let mut stream = Memory(String::from(address)).connect().unwrap();
+    let reader = BufReader::new(stream.try_clone().unwrap());
+
+    let hello = Hello {
+        tool: String::from("raw"),
+        messages: messages.iter().map(|m| m.to_string()).collect(),
+        ..hello(&["json"], &[])
+    };
+
+    send_raw(&mut stream, &Message::Hello(Box::new(hello)));
+
+    (stream, read_raw(reader))
+}
+
+// Listens on `address` for a single connection, which is handed to `serve` on another thread, to
+// stand in for a tool binal connects to
+pub fn accept_one(address: &str, serve: impl FnOnce(Box<dyn Stream>) + Send + 'static) {
+    let listener = Memory(String::from(address)).listen().unwrap();
+
+    std::thread::spawn(move || loop {
+        match listener.accept() {
+            Ok(Some((stream, _))) => {
+                drop(listener);
+                return serve(stream);
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(10)),
+            Err(e) => panic!("{}", e),
+        }
+    });
+}
+
+// Messages as they are read from `reader`, on another thread so nothing waits for them
+pub fn read_raw(reader: impl BufRead + Send + 'static) -> mpsc::Receiver<Message> {
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        for line in reader.lines() {
+            let Ok(line) = line else {
+                return;
+            };
+
+            if tx.send(serde_json::from_str(&line).unwrap()).is_err() {
+                return;
+            }
+        }
+    });
+
+    rx
+}
+
+// Sends anything that serializes, so it can be something binal does not accept as well
+pub fn send_raw(stream: &mut Box<dyn Stream>, message: &impl Serialize) {
+    let mut line = serde_json::to_vec(message).unwrap();
+    line.push(b'\n');
+    stream.write_all(&line).unwrap();
+}
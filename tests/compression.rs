@@ -0,0 +1,144 @@
+// The stream after the hellos is compressed with the best method both peers announced
+mod common;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{Read, Write},
+    sync::mpsc,
+};
+
+use binal::{
+    net::{function_id, Client, Compression, Hello, Message, Object, ObjectId},
+    transport::{Memory, Transport},
+};
+
+use common::{function, hub, settle};
+
+fn hello(compression: &[&str]) -> Hello {
+    common::hello(&["json"], compression)
+}
+
+#[test]
+fn prefers_zstd() {
+    let negotiate = |compression: &[&str]| Compression::negotiate(&hello(compression));
+
+    assert_eq!(negotiate(&["deflate", "zstd"]), Compression::Zstd);
+    assert_eq!(negotiate(&["deflate"]), Compression::Deflate);
+    assert_eq!(negotiate(&["brotli"]), Compression::None);
+    assert_eq!(negotiate(&[]), Compression::None);
+}
+
+#[test]
+fn syncs_over_zstd() {
+    let mut hub = hub("compression-zstd");
+    let mut client = Client::connect(
+        Box::new(Memory(String::from("compression-zstd"))),
+        None,
+        None,
+    )
+    .unwrap();
+    assert_eq!(client.compression(), Compression::Zstd);
+
+    let pushed: HashMap<ObjectId, Object> = (0..1000)
+        .map(|i| (function_id(i), function(&format!("f{}", i), i)))
+        .collect();
+
+    client.push(pushed).unwrap();
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 1000));
+
+    let traffic = client.traffic();
+    assert!(
+        traffic.sent_wire < traffic.sent / 2,
+        "{} bytes went out as {}",
+        traffic.sent,
+        traffic.sent_wire
+    );
+}
+
+// Lines of JSON as they are inflated, for reading what binal sends over deflate
+fn inflate_lines(mut reader: impl Read + Send + 'static) -> mpsc::Receiver<Message> {
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let mut state = flate2::Decompress::new(false);
+        let mut input = [0; 4096];
+        let mut output = Vec::new();
+
+        // binal's hello comes before compression starts
+        let mut byte = [0];
+        while reader.read_exact(&mut byte).is_ok() && byte[0] != b'\n' {}
+
+        while let Ok(read) = reader.read(&mut input) {
+            if read == 0 {
+                return;
+            }
+
+            let mut consumed = 0;
+
+            while consumed < read {
+                let before = state.total_in();
+
+                output.reserve(64 * 1024);
+                state
+                    .decompress_vec(
+                        &input[consumed..read],
+                        &mut output,
+                        flate2::FlushDecompress::Sync,
+                    )
+                    .unwrap();
+
+                match (state.total_in() - before) as usize {
+                    0 => break,
+                    taken => consumed += taken,
+                }
+            }
+
+            while let Some(end) = output.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = output.drain(..=end).collect();
+
+                if tx.send(serde_json::from_slice(&line).unwrap()).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    rx
+}
+
+#[test]
+fn speaks_deflate_with_peers_that_only_have_it() {
+    let mut hub = hub("compression-deflate");
+    let mut stream = Memory(String::from("compression-deflate"))
+        .connect()
+        .unwrap();
+    let rx = inflate_lines(stream.try_clone().unwrap());
+
+    common::send_raw(&mut stream, &Message::Hello(Box::new(hello(&["deflate"]))));
+
+    let mut writer = flate2::write::DeflateEncoder::new(stream, flate2::Compression::default());
+
+    let objects = HashMap::from([(function_id(0x1000), function("main", 0x1000))]);
+    let push = Message::Push {
+        id: None,
+        objects,
+        author: None,
+    };
+
+    let mut line = serde_json::to_vec(&push).unwrap();
+    line.push(b'\n');
+    writer.write_all(&line).unwrap();
+    writer.flush().unwrap();
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 1));
+
+    let mut errors = VecDeque::new();
+    let added = HashMap::from([(function_id(0x2000), function("helper", 0x2000))]);
+    hub.add_objects(added, &mut errors);
+
+    assert!(settle(&mut hub, |_| {
+        matches!(
+            rx.try_recv(),
+            Ok(Message::Push { objects, .. }) if objects.contains_key(&function_id(0x2000))
+        )
+    }));
+}
@@ -0,0 +1,79 @@
+// Tools report where their cursor is, and the ones following along are sent there
+mod common;
+
+use std::time::Duration;
+
+use binal::{
+    mock::{MockPeer, Program},
+    net::{Client, Message},
+    project::{Project, ProjectKind},
+    transport::Memory,
+};
+
+use common::{connected, hub, join, linger, program, settle};
+
+fn sent_to(mock: &MockPeer) -> Vec<usize> {
+    mock.received()
+        .into_iter()
+        .filter_map(|message| match message {
+            Message::Goto { address } => Some(address),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn hub_sends_followers_where_a_peer_went() {
+    let mut hub = hub("cursor-follow");
+    hub.set_follow(true);
+
+    let leader = join("cursor-follow", program(), None);
+    let follower = join("cursor-follow", Program::default(), None);
+    assert!(settle(&mut hub, |_| follower.program().objects.len() == 2));
+
+    leader.cursor(0x1000);
+    assert!(settle(&mut hub, |_| sent_to(&follower) == [0x1000]));
+
+    assert_eq!(hub.take_moved(), Some(0x1000));
+    assert_eq!(hub.take_moved(), None);
+    assert!(sent_to(&leader).is_empty());
+}
+
+#[test]
+fn hub_only_sends_peers_along_when_following() {
+    let mut hub = hub("cursor-still");
+
+    let leader = join("cursor-still", program(), None);
+    let other = join("cursor-still", Program::default(), None);
+    assert!(settle(&mut hub, |_| other.program().objects.len() == 2));
+
+    leader.cursor(0x1000);
+    linger(&mut hub, Duration::from_millis(200));
+
+    // the move is still there to pass on to other projects
+    assert_eq!(hub.take_moved(), Some(0x1000));
+    assert!(sent_to(&other).is_empty());
+}
+
+#[test]
+fn tools_echoing_a_goto_did_not_move() {
+    let transport = Memory(String::from("cursor-remote"));
+    let mock = MockPeer::listen(&transport, program(), None).unwrap();
+
+    let client = Client::connect(Box::new(transport), None, None).unwrap();
+    let mut project = Project::create(ProjectKind::Remote(client), String::from("mock")).unwrap();
+    assert!(connected(&mock));
+    project.set_follow(true);
+
+    project.goto(0x1000);
+    assert!(settle(&mut project, |_| sent_to(&mock) == [0x1000]));
+
+    // the tool reports arriving where it was sent, and later moving on its own
+    mock.cursor(0x1000);
+    linger(&mut project, Duration::from_millis(200));
+    assert_eq!(project.take_moved(), None);
+
+    mock.cursor(0x2000);
+    linger(&mut project, Duration::from_millis(200));
+    assert_eq!(project.take_moved(), Some(0x2000));
+}
@@ -0,0 +1,52 @@
+// Deletes go through on both sides, except for types something else still refers to
+mod common;
+
+use std::collections::VecDeque;
+
+use binal::{
+    ir::{Database, DatabaseError},
+    mock::MockPeer,
+    net::{function_id, Client},
+    project::{Project, ProjectKind},
+    transport::Memory,
+};
+
+use common::{program, settle, TIMEOUT};
+
+#[test]
+fn refuses_to_delete_referenced_types() {
+    let mut db = Database::default();
+    assert!(db.push_net(program().objects).rejected.is_empty());
+
+    assert!(matches!(
+        db.delete("type:s"),
+        Err(DatabaseError::Referenced(..))
+    ));
+    assert!(db.name("type:s").is_some());
+
+    assert!(db.delete(&function_id(0x1000)).unwrap());
+    assert!(db.delete("type:s").unwrap());
+    assert!(!db.delete("type:s").unwrap());
+}
+
+#[test]
+fn deletes_reach_the_other_side() {
+    let transport = Memory(String::from("delete-remote"));
+    let mock = MockPeer::listen(&transport, program(), None).unwrap();
+
+    let client = Client::connect(Box::new(transport), None, None).unwrap();
+    let mut project = Project::create(ProjectKind::Remote(client), String::from("mock")).unwrap();
+    assert!(settle(&mut project, |p| p.database().get_net().len() == 2));
+
+    // refused here, since the type is still in use, so the tool never hears of it
+    let mut errors = VecDeque::new();
+    project.delete_object("type:s", &mut errors);
+    assert_eq!(errors.len(), 1);
+    assert!(project.database().name("type:s").is_some());
+
+    project.delete_object(&function_id(0x1000), &mut errors);
+    assert!(mock.wait_until(TIMEOUT, |p| p.objects.len() == 1));
+
+    mock.remove("type:s");
+    assert!(settle(&mut project, |p| p.database().get_net().is_empty()));
+}
@@ -0,0 +1,114 @@
+// Peers that both speak MessagePack switch to it after the hellos, each message prefixed with
+// its length
+mod common;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{Read, Write},
+    sync::mpsc,
+};
+
+use binal::{
+    net::{function_id, Client, Encoding, Hello, Message},
+    transport::{Memory, Stream, Transport},
+};
+
+use common::{function, hub, settle};
+
+fn hello(encodings: &[&str]) -> Hello {
+    common::hello(encodings, &[])
+}
+
+#[test]
+fn prefers_message_pack() {
+    assert_eq!(
+        Encoding::negotiate(&hello(&["json", "msgpack"])),
+        Encoding::MessagePack
+    );
+    assert_eq!(Encoding::negotiate(&hello(&["json"])), Encoding::Json);
+    assert_eq!(Encoding::negotiate(&hello(&["cbor"])), Encoding::Json);
+}
+
+#[test]
+fn syncs_over_message_pack() {
+    let mut hub = hub("framing-binal");
+    let mut client =
+        Client::connect(Box::new(Memory(String::from("framing-binal"))), None, None).unwrap();
+    assert_eq!(client.encoding(), Encoding::MessagePack);
+
+    let pushed = HashMap::from([(function_id(0x1000), function("main", 0x1000))]);
+    client.push(pushed).unwrap();
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 1));
+
+    let mut errors = VecDeque::new();
+    let added = HashMap::from([(function_id(0x2000), function("helper", 0x2000))]);
+    hub.add_objects(added, &mut errors);
+    assert!(errors.is_empty(), "{:?}", errors);
+
+    assert!(settle(&mut hub, |_| {
+        matches!(
+            client.rx.try_recv(),
+            Ok(Message::Push { objects, .. }) if objects.contains_key(&function_id(0x2000))
+        )
+    }));
+}
+
+fn write_frame(stream: &mut Box<dyn Stream>, message: &Message) {
+    let buffer = rmp_serde::to_vec_named(message).unwrap();
+
+    stream
+        .write_all(&(buffer.len() as u32).to_be_bytes())
+        .unwrap();
+    stream.write_all(&buffer).unwrap();
+}
+
+#[test]
+fn frames_messages_with_their_length() {
+    let mut hub = hub("framing-raw");
+    let mut stream = Memory(String::from("framing-raw")).connect().unwrap();
+    let mut reader = stream.try_clone().unwrap();
+
+    // the hellos are always JSON lines
+    common::send_raw(&mut stream, &Message::Hello(Box::new(hello(&["msgpack"]))));
+
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let mut byte = [0];
+
+        while reader.read_exact(&mut byte).is_ok() && byte[0] != b'\n' {}
+
+        let mut size = [0; 4];
+
+        while reader.read_exact(&mut size).is_ok() {
+            let mut buffer = vec![0; u32::from_be_bytes(size) as usize];
+            reader.read_exact(&mut buffer).unwrap();
+
+            let message: Message = rmp_serde::from_slice(&buffer).unwrap();
+            if tx.send(message).is_err() {
+                return;
+            }
+        }
+    });
+
+    let objects = HashMap::from([(function_id(0x1000), function("main", 0x1000))]);
+    let push = Message::Push {
+        id: None,
+        objects,
+        author: None,
+    };
+
+    write_frame(&mut stream, &push);
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 1));
+
+    let mut errors = VecDeque::new();
+    let added = HashMap::from([(function_id(0x2000), function("helper", 0x2000))]);
+    hub.add_objects(added, &mut errors);
+
+    assert!(settle(&mut hub, |_| {
+        matches!(
+            rx.try_recv(),
+            Ok(Message::Push { objects, .. }) if objects.contains_key(&function_id(0x2000))
+        )
+    }));
+}
@@ -0,0 +1,109 @@
+// Both sides say hello first, and only carry on if they speak the same protocol version, after
+// which neither sends the other what it did not announce support for
+mod common;
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    time::Duration,
+};
+
+use binal::{
+    net::{function_id, Client, Hello, Message, PROTOCOL_VERSION},
+    transport::{Memory, Transport},
+};
+
+use common::{
+    accept_one, connect_raw, function, hub, join, linger, program, read_raw, send_raw, settle,
+    TIMEOUT,
+};
+
+fn newer() -> Message {
+    Message::Hello(Box::new(Hello {
+        version: PROTOCOL_VERSION + 1,
+        tool: String::from("raw"),
+        ..common::hello(&["json"], &[])
+    }))
+}
+
+#[test]
+fn turns_away_peers_speaking_another_version() {
+    let mut hub = hub("handshake-peer");
+
+    let mut stream = Memory(String::from("handshake-peer")).connect().unwrap();
+    let messages = read_raw(BufReader::new(stream.try_clone().unwrap()));
+
+    let push = Message::Push {
+        id: None,
+        objects: program().objects,
+        author: None,
+    };
+
+    // in one go, since the hub hangs up as soon as it read the hello
+    let mut lines = Vec::new();
+    for message in [newer(), push] {
+        serde_json::to_writer(&mut lines, &message).unwrap();
+        lines.push(b'\n');
+    }
+    stream.write_all(&lines).unwrap();
+
+    linger(&mut hub, Duration::from_millis(200));
+    assert!(hub.database().get_net().is_empty());
+
+    // the hub said hello before it knew, and nothing after
+    assert!(matches!(
+        messages.recv_timeout(TIMEOUT),
+        Ok(Message::Hello(_))
+    ));
+    assert!(messages.recv_timeout(TIMEOUT).is_err());
+}
+
+#[test]
+fn refuses_tools_speaking_another_version() {
+    accept_one("handshake-tool", |mut stream| {
+        send_raw(&mut stream, &newer());
+
+        // until binal hangs up
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        for _ in reader.lines().map_while(Result::ok) {}
+    });
+
+    let transport = Box::new(Memory(String::from("handshake-tool")));
+    let Err(e) = Client::connect(transport, None, None) else {
+        panic!("connected to a tool speaking another version");
+    };
+
+    let expected = format!(
+        "speaks protocol version {}, but binal speaks version {}",
+        PROTOCOL_VERSION + 1,
+        PROTOCOL_VERSION
+    );
+    assert!(e.to_string().contains(&expected), "{}", e);
+}
+
+#[test]
+fn only_sends_peers_what_they_announced() {
+    let mut hub = hub("handshake-messages");
+    let mock = join("handshake-messages", program(), None);
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 2));
+
+    // a peer that only takes pushes
+    let (_stream, messages) = connect_raw("handshake-messages", &["push"]);
+
+    mock.lock(&function_id(0x1000));
+    mock.define(function_id(0x2000), function("later", 0x2000));
+
+    let mut received = Vec::new();
+    assert!(settle(&mut hub, |_| {
+        received.extend(messages.try_iter());
+        received.iter().any(|message| match message {
+            Message::Push { objects, .. } => objects.contains_key(&function_id(0x2000)),
+            _ => false,
+        })
+    }));
+
+    // the lock was passed on to everyone else before the push
+    assert!(mock.locks().contains_key(&function_id(0x1000)));
+    assert!(received
+        .iter()
+        .all(|message| matches!(message, Message::Hello(_) | Message::Push { .. })));
+}
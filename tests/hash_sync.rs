@@ -0,0 +1,94 @@
+// Brings binal up to date with a tool that compares content hashes, pulling only what differs
+mod common;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use binal::{
+    mock::MockPeer,
+    net::{function_id, Client, Message, ObjectId, Query},
+    project::{Project, ProjectKind},
+    transport::Memory,
+};
+
+use common::{function, functions, settle, TIMEOUT};
+
+fn names(project: &Project) -> Vec<String> {
+    let mut names: Vec<String> = project
+        .database()
+        .get_net()
+        .values()
+        .map(|object| object.name().clone())
+        .collect();
+
+    names.sort();
+    names
+}
+
+// Ids of the objects binal pulled one by one, after comparing hashes
+fn pulled(mock: &MockPeer) -> Vec<ObjectId> {
+    let mut ids: Vec<ObjectId> = mock
+        .received()
+        .into_iter()
+        .flat_map(|message| match message {
+            Message::Pull {
+                query: Query::Objects { ids },
+                ..
+            } => ids,
+            _ => Vec::new(),
+        })
+        .collect();
+
+    ids.sort();
+    ids
+}
+
+#[test]
+fn pulls_only_what_differs_after_reconnecting() {
+    let address = "hash-sync-reconnect";
+    let transport = Memory(String::from(address));
+    let before = functions(&[("same", 0x1000), ("changed", 0x2000), ("removed", 0x3000)]);
+    let mock = MockPeer::listen(&transport, before, None).unwrap();
+
+    let client = Client::connect(Box::new(Memory(String::from(address))), None, None).unwrap();
+    let mut project = Project::create(ProjectKind::Remote(client), String::from("mock")).unwrap();
+    assert!(settle(&mut project, |p| p.database().get_net().len() == 3));
+
+    // the tool comes back with a different program, on the same address
+    drop(mock);
+
+    let after = functions(&[("same", 0x1000), ("renamed", 0x2000), ("added", 0x4000)]);
+    let deadline = Instant::now() + TIMEOUT;
+    let mock = loop {
+        match MockPeer::listen(&transport, after.clone(), None) {
+            Ok(mock) => break mock,
+            Err(_) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+            Err(e) => panic!("{}", e),
+        }
+    };
+
+    assert!(settle(&mut project, |p| names(p) == ["added", "renamed", "same"]));
+    assert_eq!(pulled(&mock), [function_id(0x2000), function_id(0x4000)]);
+}
+
+#[test]
+fn keeps_objects_added_while_syncing() {
+    let transport = Memory(String::from("hash-sync-race"));
+    let mock = MockPeer::listen(&transport, functions(&[("main", 0x1000)]), None).unwrap();
+
+    let client = Client::connect(Box::new(transport), None, None).unwrap();
+    let mut project = Project::create(ProjectKind::Remote(client), String::from("mock")).unwrap();
+
+    // asks for the summary, which the tool answers before it gets to the push below
+    let mut errors = VecDeque::new();
+    project.handle_network_updates(&mut errors);
+
+    let added = HashMap::from([(function_id(0x2000), function("helper", 0x2000))]);
+    project.add_objects(added, &mut errors);
+    assert!(errors.is_empty(), "{:?}", errors);
+
+    assert!(settle(&mut project, |p| names(p) == ["helper", "main"]));
+    assert!(mock.wait_until(TIMEOUT, |p| p.objects.len() == 2));
+}
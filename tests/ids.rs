@@ -0,0 +1,52 @@
+// Objects are kept by their ids, so a push can rename them, while names are only what they are
+// currently called
+mod common;
+
+use std::collections::HashMap;
+
+use binal::{ir::Database, net::function_id};
+
+use common::{function_taking, object, program, structure};
+
+#[test]
+fn refuses_ids_pushed_twice() {
+    let mut db = Database::default();
+
+    // the second id is only an alias of the first, once it is known
+    let objects = HashMap::from([
+        (String::from("type:s"), structure("S")),
+        (String::from("type:other"), structure("S")),
+    ]);
+
+    let result = db.push_net(objects);
+    assert_eq!(result.rejected.len(), 1);
+    assert_eq!(db.types.len(), 1);
+}
+
+#[test]
+fn rejects_references_to_names_renamed_in_the_same_push() {
+    let mut db = Database::default();
+    assert!(db.push_net(program().objects).rejected.is_empty());
+
+    // S becomes T, while the others still refer to it as S, as a push merged from several
+    // updates could
+    let comment =
+        object(r#"{"kind":"comment","target":{"kind":"type","name":"S"},"text":"still S"}"#);
+    let objects = HashMap::from([
+        (String::from("type:s"), structure("T")),
+        (function_id(0x2000), function_taking("helper", 0x2000, "S")),
+        (String::from("comment:1"), comment),
+    ]);
+
+    let result = db.push_net(objects);
+
+    assert_eq!(result.changed, ["type:s"]);
+    assert_eq!(result.rejected[&function_id(0x2000)], "unknown type S");
+    assert_eq!(result.rejected["comment:1"], "unknown type S");
+    assert_eq!(db.type_id("T").map(String::as_str), Some("type:s"));
+    assert!(db.type_id("S").is_none());
+
+    // referring to it by the new name goes through
+    let objects = HashMap::from([(function_id(0x2000), function_taking("helper", 0x2000, "T"))]);
+    assert!(db.push_net(objects).rejected.is_empty());
+}
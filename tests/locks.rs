@@ -0,0 +1,173 @@
+// Objects locked by one peer of a hub cannot be changed by the others until the lock is released
+mod common;
+
+use std::{collections::HashMap, time::Duration};
+
+use binal::{
+    mock::{MockPeer, Program},
+    net::{function_id, Message, Object, ObjectId, Role},
+    project::Project,
+};
+
+use common::{connect_raw, function, join, linger, send_raw, settle, structure};
+
+fn program() -> Program {
+    let objects: HashMap<ObjectId, Object> = [
+        (function_id(0x1000), function("main", 0x1000)),
+        (String::from("type:s"), structure("S")),
+        (String::from("type:t"), structure("T")),
+    ]
+    .into();
+
+    Program { objects }
+}
+
+fn hub(address: &str) -> Project {
+    let tokens = HashMap::from([
+        (String::from("member"), Role::Contributor),
+        (String::from("admin"), Role::Admin),
+    ]);
+
+    common::hub_with(address, tokens)
+}
+
+fn name(program: &Program, object_id: &str) -> Option<String> {
+    program
+        .objects
+        .get(object_id)
+        .map(|object| object.name().clone())
+}
+
+fn errors(mock: &MockPeer) -> Vec<String> {
+    mock.received()
+        .into_iter()
+        .filter_map(|message| match message {
+            Message::Error { id: None, message } => Some(message),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn hands_back_changes_to_objects_locked_by_someone_else() {
+    let mut hub = hub("locks-conflict");
+    let owner = join("locks-conflict", program(), Some("member"));
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 3));
+
+    let other = join("locks-conflict", Program::default(), Some("member"));
+    assert!(settle(&mut hub, |_| other.program().objects.len() == 3));
+
+    let main = function_id(0x1000);
+    owner.lock(&main);
+    assert!(settle(&mut hub, |_| other.locks().contains_key(&main)));
+
+    other.define(main.clone(), function("renamed", 0x1000));
+    assert!(settle(&mut hub, |_| {
+        name(&other.program(), &main).as_deref() == Some("main") && !errors(&other).is_empty()
+    }));
+    assert_eq!(hub.database().name(&main).map(String::as_str), Some("main"));
+
+    // the owner's own changes go through
+    owner.define(main.clone(), function("renamed", 0x1000));
+    assert!(settle(&mut hub, |_| {
+        name(&other.program(), &main).as_deref() == Some("renamed")
+    }));
+}
+
+#[test]
+fn admins_release_anyone_s_lock() {
+    let mut hub = hub("locks-admin");
+    let owner = join("locks-admin", program(), Some("member"));
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 3));
+
+    let admin = join("locks-admin", Program::default(), Some("admin"));
+    let other = join("locks-admin", Program::default(), Some("member"));
+    assert!(settle(&mut hub, |_| {
+        admin.program().objects.len() == 3 && other.program().objects.len() == 3
+    }));
+
+    let main = function_id(0x1000);
+    owner.lock(&main);
+    assert!(settle(&mut hub, |_| other.locks().contains_key(&main)));
+
+    // only the holder or an admin can release it
+    other.unlock(&main);
+    linger(&mut hub, Duration::from_millis(200));
+    assert!(hub.database().lock_of(&main).is_some());
+
+    admin.unlock(&main);
+    assert!(settle(&mut hub, |p| p.database().lock_of(&main).is_none()));
+    assert!(settle(&mut hub, |_| !other.locks().contains_key(&main)));
+}
+
+#[test]
+fn hands_back_renames_that_cannot_be_applied() {
+    let mut hub = common::hub("locks-rename");
+    let _owner = join("locks-rename", program(), None);
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 3));
+
+    // S cannot take the name of T, which is already taken
+    let (mut stream, rx) = connect_raw("locks-rename", &["push", "rename", "error"]);
+    let rename = Message::Rename {
+        kind: String::from("type"),
+        old: String::from("S"),
+        new: String::from("T"),
+        author: None,
+    };
+
+    send_raw(&mut stream, &rename);
+
+    let mut refused = false;
+    let mut handed_back = false;
+
+    assert!(settle(&mut hub, |_| {
+        while let Ok(message) = rx.try_recv() {
+            match message {
+                Message::Error { id: None, .. } => refused = true,
+                Message::Push { objects, .. } if refused => {
+                    handed_back |= objects.get("type:s").map(Object::name) == Some(&"S".into())
+                }
+                _ => {}
+            }
+        }
+
+        refused && handed_back
+    }));
+
+    assert!(hub.database().type_id("S").is_some());
+}
+
+#[test]
+fn releases_the_locks_of_peers_that_leave() {
+    let mut hub = hub("locks-leave");
+    let owner = join("locks-leave", program(), Some("member"));
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 3));
+
+    let main = function_id(0x1000);
+    owner.lock(&main);
+    assert!(settle(&mut hub, |p| p.database().lock_of(&main).is_some()));
+
+    // a peer joining later is told who holds what
+    let late = join("locks-leave", Program::default(), Some("member"));
+    assert!(settle(&mut hub, |_| !late.locks().is_empty()));
+    assert_eq!(
+        late.locks(),
+        HashMap::from([(main.clone(), Some(String::from("mock (mock)")))])
+    );
+
+    drop(owner);
+    assert!(settle(&mut hub, |_| late.locks().is_empty()));
+    assert!(hub.database().lock_of(&main).is_none());
+
+    let released = late.received().into_iter().any(|message| {
+        matches!(message, Message::Unlock { object, author }
+            if object == main && author.as_deref() == Some("mock (mock)"))
+    });
+    assert!(released);
+
+    // so anyone can change it again
+    late.define(main.clone(), function("renamed", 0x1000));
+    assert!(settle(&mut hub, |p| {
+        p.database().name(&main).map(String::as_str) == Some("renamed")
+    }));
+}
@@ -0,0 +1,130 @@
+// Syncs binal with mock plugins over in-memory connections
+mod common;
+
+use std::collections::{HashMap, VecDeque};
+
+use binal::{
+    mock::{MockPeer, Program},
+    net::{function_id, Client, Message},
+    project::{Project, ProjectKind},
+    transport::Memory,
+};
+
+use common::{
+    connect_raw, connected, function, function_taking, hub, join, program, send_raw, settle,
+    TIMEOUT,
+};
+
+fn name(project: &Project, object_id: &str) -> Option<String> {
+    project.database().name(object_id).cloned()
+}
+
+#[test]
+fn connects_and_syncs_both_ways() {
+    let transport = Memory(String::from("mock-remote"));
+    let mock = MockPeer::listen(&transport, program(), None).unwrap();
+
+    let client = Client::connect(Box::new(transport), None, None).unwrap();
+    let mut project = Project::create(ProjectKind::Remote(client), String::from("mock")).unwrap();
+
+    assert!(settle(&mut project, |p| p.database().get_net().len() == 2));
+
+    mock.define(function_id(0x1000), function("renamed", 0x1000));
+    assert!(settle(&mut project, |p| {
+        name(p, &function_id(0x1000)).as_deref() == Some("renamed")
+    }));
+
+    let mut errors = VecDeque::new();
+    let added = HashMap::from([(function_id(0x2000), function("helper", 0x2000))]);
+    project.add_objects(added, &mut errors);
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(mock.wait_until(TIMEOUT, |p| p.objects.contains_key(&function_id(0x2000))));
+
+    project.delete_object(&function_id(0x2000), &mut errors);
+    assert!(mock.wait_until(TIMEOUT, |p| !p.objects.contains_key(&function_id(0x2000))));
+
+    mock.remove(&function_id(0x1000));
+    assert!(settle(&mut project, |p| name(p, &function_id(0x1000)).is_none()));
+}
+
+#[test]
+fn hub_relays_between_plugins() {
+    let mut hub = hub("mock-hub");
+
+    let first = join("mock-hub", program(), None);
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 2));
+
+    let second = join("mock-hub", Program::default(), None);
+    assert!(settle(&mut hub, |_| second.program().objects.len() == 2));
+
+    second.define(function_id(0x3000), function("shared", 0x3000));
+    assert!(settle(&mut hub, |_| {
+        first.program().objects.contains_key(&function_id(0x3000))
+    }));
+
+    first.lock(&function_id(0x3000));
+    assert!(settle(&mut hub, |_| {
+        second.locks().contains_key(&function_id(0x3000))
+    }));
+}
+
+#[test]
+fn refuses_the_wrong_token() {
+    let transport = Memory(String::from("mock-token"));
+    let _mock = MockPeer::listen(&transport, program(), Some(String::from("secret"))).unwrap();
+
+    assert!(Client::connect(
+        Box::new(Memory(String::from("mock-token"))),
+        Some(String::from("wrong")),
+        None
+    )
+    .is_err());
+    assert!(Client::connect(Box::new(transport), Some(String::from("secret")), None).is_ok());
+}
+
+#[test]
+fn pushes_definitions_with_the_types_they_use() {
+    let transport = Memory(String::from("mock-define"));
+    let mock = MockPeer::listen(&transport, program(), None).unwrap();
+    let (_stream, messages) = connect_raw("mock-define", &["push", "rename"]);
+    assert!(connected(&mock));
+
+    // a new name for `main`, which takes a pointer to S
+    mock.define(function_id(0x1000), function_taking("entry", 0x1000, "S"));
+
+    let mut sent = std::iter::from_fn(|| messages.recv_timeout(TIMEOUT).ok())
+        .skip_while(|message| !matches!(message, Message::Rename { .. }));
+
+    let Some(Message::Rename { kind, old, new, .. }) = sent.next() else {
+        panic!("the mock did not report the rename");
+    };
+    assert_eq!([kind, old, new], ["function", "main", "entry"]);
+
+    let Some(Message::Push { objects, .. }) = sent.next() else {
+        panic!("the mock did not push the function after renaming it");
+    };
+    let mut pushed: Vec<&String> = objects.keys().collect();
+    pushed.sort();
+    assert_eq!(pushed, [&function_id(0x1000), "type:s"]);
+}
+
+#[test]
+fn renames_references_along_with_types() {
+    let transport = Memory(String::from("mock-rename"));
+    let mock = MockPeer::listen(&transport, program(), None).unwrap();
+    let (mut stream, _messages) = connect_raw("mock-rename", &["push", "rename"]);
+    assert!(connected(&mock));
+
+    let rename = Message::Rename {
+        kind: String::from("type"),
+        old: String::from("S"),
+        new: String::from("T"),
+        author: None,
+    };
+    send_raw(&mut stream, &rename);
+
+    assert!(mock.wait_until(TIMEOUT, |program| {
+        let main = &program.objects[&function_id(0x1000)];
+        program.find("type", "T").is_some() && main.dependencies() == ["T"]
+    }));
+}
@@ -0,0 +1,196 @@
+// Pulls are answered by id, so several can be waiting at once, and ones that find nothing are
+// answered with an error
+mod common;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::BufReader,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use binal::{
+    mock::MockPeer,
+    net::{function_id, Client, Hello, Message, Query},
+    project::{Project, ProjectKind},
+    transport::Memory,
+};
+
+use common::{
+    accept_one, connect_raw, functions, hub, join, linger, program, read_raw, send_raw, settle,
+    TIMEOUT,
+};
+
+fn ids(message: &Message) -> Vec<String> {
+    let Message::Response { objects, .. } = message else {
+        panic!("expected a response, got {:?}", message);
+    };
+
+    let mut ids: Vec<String> = objects.keys().cloned().collect();
+    ids.sort();
+    ids
+}
+
+#[test]
+fn matches_answers_to_their_pulls() {
+    let transport = Memory(String::from("pull-remote"));
+    let _mock = MockPeer::listen(&transport, program(), None).unwrap();
+    let mut client = Client::connect(Box::new(transport), None, None).unwrap();
+
+    let r#type = client
+        .pull(Query::Type {
+            name: String::from("S"),
+        })
+        .unwrap();
+    let function = client
+        .pull(Query::Functions {
+            start: 0x1000,
+            end: 0x1001,
+        })
+        .unwrap();
+    let missing = client
+        .pull(Query::Type {
+            name: String::from("Missing"),
+        })
+        .unwrap();
+
+    // each answer arrives where its pull waits for it, whatever order they are looked at in
+    let Message::Error { id, message } = missing.rx.recv_timeout(TIMEOUT).unwrap() else {
+        panic!("found a type that does not exist");
+    };
+    assert_eq!(
+        (id, message.as_str()),
+        (Some(missing.id), "no matching objects")
+    );
+
+    let answer = function.rx.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(ids(&answer), [function_id(0x1000), String::from("type:s")]);
+
+    let answer = r#type.rx.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(ids(&answer), ["type:s"]);
+}
+
+#[test]
+fn reports_pulls_that_failed() {
+    let transport = Memory(String::from("pull-failed"));
+    let _mock = MockPeer::listen(&transport, program(), None).unwrap();
+
+    let client = Client::connect(Box::new(transport), None, None).unwrap();
+    let mut project = Project::create(ProjectKind::Remote(client), String::from("mock")).unwrap();
+    assert!(settle(&mut project, |p| p.database().get_net().len() == 2));
+
+    let mut errors = VecDeque::new();
+    let query = Query::Type {
+        name: String::from("Missing"),
+    };
+    project.pull(query, &mut errors);
+
+    // not through `settle`, which expects no errors
+    let deadline = Instant::now() + TIMEOUT;
+
+    while errors.is_empty() && Instant::now() < deadline {
+        project.handle_network_updates(&mut errors);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(errors, ["Pull request failed: no matching objects"]);
+}
+
+#[test]
+fn hub_answers_peers_by_id() {
+    let mut hub = hub("pull-hub");
+    let _mock = join("pull-hub", program(), None);
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 2));
+
+    let (mut stream, messages) = connect_raw("pull-hub", &["pull", "response", "error"]);
+
+    for (id, name) in [(7, "Missing"), (8, "S")] {
+        let query = Query::Type {
+            name: String::from(name),
+        };
+        send_raw(&mut stream, &Message::Pull { id, query });
+    }
+
+    let mut answers = Vec::new();
+    assert!(settle(&mut hub, |_| {
+        answers.extend(
+            messages
+                .try_iter()
+                .filter(|m| !matches!(m, Message::Hello(_))),
+        );
+        answers.len() == 2
+    }));
+
+    assert!(matches!(
+        &answers[0],
+        Message::Error { id: Some(7), message } if message == "no matching objects"
+    ));
+    assert!(matches!(answers[1], Message::Response { id: 8, .. }));
+    assert_eq!(ids(&answers[1]), ["type:s"]);
+}
+
+#[test]
+fn keeps_changes_that_arrive_along_with_a_reply() {
+    let (answered, replied) = mpsc::channel();
+
+    // a tool that renames `main` right after answering the pull for it
+    accept_one("pull-stale", move |mut stream| {
+        let hello = Hello {
+            tool: String::from("raw"),
+            messages: ["push", "pull", "response"].map(String::from).to_vec(),
+            ..common::hello(&["json"], &[])
+        };
+        send_raw(&mut stream, &Message::Hello(Box::new(hello)));
+
+        for message in read_raw(BufReader::new(stream.try_clone().unwrap())) {
+            let Message::Pull { id, query } = message else {
+                continue;
+            };
+
+            let objects = match query {
+                Query::Functions { .. } => functions(&[("main", 0x1000)]).objects,
+                _ => HashMap::new(),
+            };
+            let renamed = !objects.is_empty();
+
+            let response = Message::Response {
+                id,
+                revision: 0,
+                objects,
+            };
+            send_raw(&mut stream, &response);
+
+            if renamed {
+                let objects = functions(&[("renamed", 0x1000)]).objects;
+                let push = Message::Push {
+                    id: None,
+                    objects,
+                    author: None,
+                };
+                send_raw(&mut stream, &push);
+
+                answered.send(()).unwrap();
+            }
+        }
+    });
+
+    let client = Client::connect(Box::new(Memory(String::from("pull-stale"))), None, None).unwrap();
+    let mut project = Project::create(ProjectKind::Remote(client), String::from("raw")).unwrap();
+
+    let mut errors = VecDeque::new();
+    let query = Query::Functions {
+        start: 0x1000,
+        end: 0x1001,
+    };
+    project.pull(query, &mut errors);
+
+    // so the reply and the push are handled together
+    replied.recv_timeout(TIMEOUT).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+
+    assert!(settle(&mut project, |p| !p.database().get_net().is_empty()));
+    linger(&mut project, Duration::from_millis(100));
+
+    let name = project.database().name(&function_id(0x1000));
+    assert_eq!(name.map(String::as_str), Some("renamed"));
+}
@@ -0,0 +1,124 @@
+// Remote projects reconnect on their own, waiting longer after every failed attempt, and drop
+// what the tool removed in the meantime
+mod common;
+
+use std::{
+    io::BufReader,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use binal::{
+    mock::Program,
+    net::{Client, ConnectionState, Hello, Message},
+    project::{Project, ProjectKind},
+    transport::{Memory, Stream},
+};
+
+use common::{accept_one, functions, read_raw, send_raw, settle, TIMEOUT};
+
+// A tool without content hashes, which pushes everything it has on connecting and answers pulls
+// for changes with nothing, like older plugins. Hands over its end of the connection
+fn tool(address: &str, program: Program) -> mpsc::Receiver<Box<dyn Stream>> {
+    let (tx, rx) = mpsc::channel();
+
+    accept_one(address, move |mut stream| {
+        tx.send(stream.try_clone().unwrap()).unwrap();
+
+        let hello = Hello {
+            tool: String::from("raw"),
+            messages: ["push", "pull", "response"].map(String::from).to_vec(),
+            ..common::hello(&["json"], &[])
+        };
+        send_raw(&mut stream, &Message::Hello(Box::new(hello)));
+
+        let push = Message::Push {
+            id: None,
+            objects: program.objects,
+            author: None,
+        };
+        send_raw(&mut stream, &push);
+
+        for message in read_raw(BufReader::new(stream.try_clone().unwrap())) {
+            if let Message::Pull { id, .. } = message {
+                let response = Message::Response {
+                    id,
+                    revision: 0,
+                    objects: Default::default(),
+                };
+                send_raw(&mut stream, &response);
+            }
+        }
+    });
+
+    rx
+}
+
+fn names(project: &Project) -> Vec<String> {
+    let mut names: Vec<String> = project
+        .database()
+        .get_net()
+        .values()
+        .map(|object| object.name().clone())
+        .collect();
+
+    names.sort();
+    names
+}
+
+// When the client is going to try connecting next, once it got to the given attempt
+fn retry_at(client: &Client, attempt: u32) -> Instant {
+    let deadline = Instant::now() + TIMEOUT;
+
+    loop {
+        match client.state() {
+            ConnectionState::Reconnecting {
+                attempt: current,
+                retry_at,
+            } if current == attempt => return retry_at,
+            _ if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+            state => panic!("still {:?} instead of attempt {}", state, attempt),
+        }
+    }
+}
+
+#[test]
+fn waits_longer_after_every_failed_attempt() {
+    let address = "reconnect-backoff";
+    let connection = tool(address, functions(&[("main", 0x1000)]));
+    let client = Client::connect(Box::new(Memory(String::from(address))), None, None).unwrap();
+
+    // nobody listens anymore, so the first attempt fails and the next one waits twice as long
+    connection.recv().unwrap().shutdown().unwrap();
+
+    let first = retry_at(&client, 1);
+    let second = retry_at(&client, 2);
+    assert!(second.duration_since(first) >= Duration::from_secs(2));
+    assert_eq!(client.generation(), 0);
+
+    let _connection = tool(address, functions(&[("main", 0x1000)]));
+
+    let deadline = Instant::now() + TIMEOUT;
+    while client.state() != ConnectionState::Connected && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(client.state(), ConnectionState::Connected);
+    assert_eq!(client.generation(), 1);
+}
+
+#[test]
+fn drops_what_the_tool_removed_while_away() {
+    let address = "reconnect-resync";
+    let connection = tool(address, functions(&[("kept", 0x1000), ("removed", 0x2000)]));
+
+    let client = Client::connect(Box::new(Memory(String::from(address))), None, None).unwrap();
+    let mut project = Project::create(ProjectKind::Remote(client), String::from("raw")).unwrap();
+    assert!(settle(&mut project, |p| names(p) == ["kept", "removed"]));
+
+    // the tool comes back without one of the functions and with another one
+    connection.recv().unwrap().shutdown().unwrap();
+    let _connection = tool(address, functions(&[("kept", 0x1000), ("added", 0x3000)]));
+
+    assert!(settle(&mut project, |p| names(p) == ["added", "kept"]));
+}
@@ -0,0 +1,107 @@
+// Replaying a recorded session leaves binal with the same objects the session did
+mod common;
+
+use std::{collections::HashMap, path::PathBuf};
+
+use binal::{
+    mock::{MockPeer, Program},
+    net::{function_id, Client, Object, ObjectId, Server},
+    project::{Project, ProjectKind},
+    record::{Recorder, Replay},
+    transport::Memory,
+};
+
+use common::{join, program, settle};
+
+// A function taking a pointer to S
+fn function(name: &str, location: usize) -> Object {
+    common::function_taking(name, location, "S")
+}
+
+fn recording(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("binal-{}-{}.jsonl", name, std::process::id()))
+}
+
+// What the project holds, by the hash of each object
+fn contents(project: &Project) -> HashMap<ObjectId, String> {
+    project
+        .database()
+        .get_net()
+        .into_iter()
+        .map(|(object_id, object)| (object_id, object.content_hash()))
+        .collect()
+}
+
+#[test]
+fn replays_what_a_tool_sent() {
+    let path = recording("remote");
+    let recorder = Recorder::create(&path).unwrap();
+
+    let transport = Memory(String::from("record-remote"));
+    let mock = MockPeer::listen(&transport, program(), None).unwrap();
+
+    let client = Client::connect(Box::new(transport), None, Some(recorder)).unwrap();
+    let mut project = Project::create(ProjectKind::Remote(client), String::from("mock")).unwrap();
+    assert!(settle(&mut project, |p| p.database().get_net().len() == 2));
+
+    mock.define(function_id(0x1000), function("start", 0x1000));
+    mock.define(function_id(0x2000), function("helper", 0x2000));
+    assert!(settle(&mut project, |p| p.database().get_net().len() == 3));
+    assert!(settle(&mut project, |p| {
+        p.database().name(&function_id(0x1000)).map(String::as_str) == Some("start")
+    }));
+
+    let recorded = contents(&project);
+    drop(project);
+
+    let replay = Replay::open(&path).unwrap();
+    assert!(!replay.is_hub());
+
+    let transport = Memory(String::from("record-remote-replay"));
+    let player = replay.serve(&transport, false).unwrap();
+
+    let client = Client::connect(Box::new(transport), None, None).unwrap();
+    let mut project = Project::create(ProjectKind::Remote(client), String::from("mock")).unwrap();
+    assert!(settle(&mut project, |p| contents(p) == recorded));
+
+    assert!(settle(&mut project, |_| player.is_finished()));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn replays_what_the_peers_of_a_hub_sent() {
+    let path = recording("hub");
+    let recorder = Recorder::create(&path).unwrap();
+
+    let transport = Memory(String::from("record-hub"));
+    let server = Server::listen(&transport, HashMap::new(), Some(recorder)).unwrap();
+    let mut hub = Project::create(ProjectKind::Hub(server), String::from("hub")).unwrap();
+
+    let first = join("record-hub", program(), None);
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 2));
+
+    let second = join("record-hub", Program::default(), None);
+    assert!(settle(&mut hub, |_| second.program().objects.len() == 2));
+
+    first.define(function_id(0x1000), function("start", 0x1000));
+    second.define(function_id(0x2000), function("helper", 0x2000));
+    assert!(settle(&mut hub, |p| {
+        p.database().get_net().len() == 3
+            && p.database().name(&function_id(0x1000)).map(String::as_str) == Some("start")
+    }));
+
+    let recorded = contents(&hub);
+    drop(hub);
+
+    let replay = Replay::open(&path).unwrap();
+    assert!(replay.is_hub());
+
+    let mut hub = common::hub("record-hub-replay");
+    let transport = Memory(String::from("record-hub-replay"));
+    let player = replay.connect(Box::new(transport), false);
+    assert!(settle(&mut hub, |p| contents(p) == recorded));
+
+    // the peers only leave once the hub answered everything they sent
+    assert!(settle(&mut hub, |_| player.is_finished()));
+    std::fs::remove_file(path).unwrap();
+}
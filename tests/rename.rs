@@ -0,0 +1,81 @@
+// Renamed objects keep their id, so whatever refers to them follows along
+mod common;
+
+use binal::{
+    ir::{Database, DatabaseError},
+    mock::Program,
+    net::{function_id, Message, Object, TypeRef},
+};
+
+use common::{function_taking, hub, join, settle, structure, TIMEOUT};
+
+// S and T, and `main` taking a pointer to S
+fn program() -> Program {
+    let mut program = common::program();
+    program
+        .objects
+        .insert(String::from("type:t"), structure("T"));
+
+    program
+}
+
+#[test]
+fn renaming_a_type_keeps_its_id() {
+    let mut db = Database::default();
+    assert!(db.push_net(program().objects).rejected.is_empty());
+
+    let index = |db: &mut Database| {
+        db.types
+            .iter_ids_mut()
+            .find(|(_, t)| t.object_id == "type:s")
+            .map(|(index, _)| index)
+    };
+
+    let before = index(&mut db);
+    let renamed = db.rename("type", "S", "U").unwrap();
+
+    assert_eq!(renamed.as_deref(), Some("type:s"));
+    assert_eq!(index(&mut db), before);
+    assert_eq!(db.types[before.unwrap()].name, "U");
+
+    // the function still refers to the same type, by its new name
+    let Object::Function { arguments, .. } =
+        &db.object_get_net(&function_id(0x1000))[&function_id(0x1000)]
+    else {
+        panic!("main is not a function");
+    };
+
+    assert!(matches!(&arguments[0].r#type, TypeRef::Pointer { name, .. } if name == "U"));
+
+    assert!(matches!(
+        db.rename("type", "U", "T"),
+        Err(DatabaseError::Exists(..))
+    ));
+    assert_eq!(db.rename("type", "S", "V").unwrap(), None);
+}
+
+#[test]
+fn hub_passes_renames_on() {
+    let mut hub = hub("rename-hub");
+
+    let first = join("rename-hub", program(), None);
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 3));
+
+    let second = join("rename-hub", Program::default(), None);
+    assert!(settle(&mut hub, |_| second.program().objects.len() == 3));
+
+    first.define(function_id(0x1000), function_taking("start", 0x1000, "S"));
+    assert!(settle(&mut hub, |p| {
+        p.database().name(&function_id(0x1000)).map(String::as_str) == Some("start")
+    }));
+    assert_eq!(hub.database().functions.len(), 1);
+
+    assert!(settle(&mut hub, |_| {
+        second.received().iter().any(|message| {
+            matches!(message, Message::Rename { old, new, .. } if old == "main" && new == "start")
+        })
+    }));
+    assert!(second.wait_until(TIMEOUT, |p| {
+        p.objects[&function_id(0x1000)].name() == "start"
+    }));
+}
@@ -0,0 +1,72 @@
+// Peers of a hub may only change what the role of the token they connected with allows
+mod common;
+
+use std::collections::HashMap;
+
+use binal::{
+    mock::{MockPeer, Program},
+    net::{function_id, Message, Object, ObjectId, Role},
+};
+
+use common::{function, hub_with, join, settle, structure_of};
+
+fn program() -> Program {
+    let objects: HashMap<ObjectId, Object> = [
+        (function_id(0x1000), function("main", 0x1000)),
+        (String::from("type:s"), structure_of("S", 4)),
+    ]
+    .into();
+
+    Program { objects }
+}
+
+fn hash(program: &Program, object_id: &str) -> Option<String> {
+    program.objects.get(object_id).map(Object::content_hash)
+}
+
+#[test]
+fn read_only_peers_cannot_change_anything() {
+    let tokens = HashMap::from([
+        (String::from("reader"), Role::ReadOnly),
+        (String::from("member"), Role::Contributor),
+    ]);
+    let mut hub = hub_with("roles-hub", tokens);
+
+    let member = join("roles-hub", program(), Some("member"));
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 2));
+
+    let reader = join("roles-hub", Program::default(), Some("reader"));
+    assert!(settle(&mut hub, |_| reader.program().objects.len() == 2));
+
+    let original = hash(&program(), "type:s");
+    let refused = |mock: &MockPeer| {
+        mock.received()
+            .iter()
+            .filter(|message| matches!(message, Message::Error { id: None, .. }))
+            .count()
+    };
+
+    // the change is refused and the hub's version handed back
+    reader.define(String::from("type:s"), structure_of("S", 8));
+    assert!(settle(&mut hub, |_| {
+        refused(&reader) == 1 && hash(&reader.program(), "type:s") == original
+    }));
+
+    reader.remove(&function_id(0x1000));
+    assert!(settle(&mut hub, |_| {
+        refused(&reader) == 2 && reader.program().objects.contains_key(&function_id(0x1000))
+    }));
+
+    let db = hub.database();
+    assert_eq!(db.get_net().len(), 2);
+    assert_eq!(
+        db.get_net().get("type:s").map(Object::content_hash),
+        original
+    );
+
+    // while contributors' changes go through, and reach the read-only peer as well
+    member.define(String::from("type:s"), structure_of("S", 8));
+    let changed = hash(&member.program(), "type:s");
+    assert!(settle(&mut hub, |_| hash(&reader.program(), "type:s") == changed));
+    assert_eq!(refused(&member), 0);
+}
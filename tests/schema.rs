@@ -0,0 +1,73 @@
+// Messages that cannot be decoded are checked against the schema, so peers are told what is wrong
+// with them
+mod common;
+
+use binal::{net::Message, schema::validate};
+use serde_json::{json, Value};
+
+use common::{connect_raw, hub, send_raw, settle};
+
+// A struct whose size is a string
+fn bad_push() -> Value {
+    json!({
+        "kind": "push",
+        "objects": {
+            "type:1": {
+                "kind": "type",
+                "name": "S",
+                "size": "4",
+                "alignment": 4,
+                "info": {"kind": "struct", "fields": []}
+            }
+        }
+    })
+}
+
+#[test]
+fn accepts_valid_messages() {
+    assert!(validate(&json!({"kind": "push", "objects": {}})).is_empty());
+    assert!(validate(&json!({"kind": "delete", "object": "type:1"})).is_empty());
+    assert!(validate(&json!({"kind": "pull", "id": 1, "query": {"kind": "summary"}})).is_empty());
+}
+
+#[test]
+fn points_at_what_is_wrong() {
+    assert_eq!(
+        validate(&bad_push()),
+        ["/objects/type:1/size: expected integer, got string"]
+    );
+    assert_eq!(
+        validate(&json!({"kind": "pull", "id": -1, "query": {"kind": "summary"}})),
+        ["/id: -1 is less than 0"]
+    );
+    assert_eq!(
+        validate(&json!({"kind": "push"})),
+        ["/: missing field `objects`"]
+    );
+    assert_eq!(
+        validate(&json!({"objects": {}})),
+        ["/: missing field `kind`"]
+    );
+    assert_eq!(validate(&json!("push")), ["/: expected object, got string"]);
+
+    let unknown = validate(&json!({"kind": "teleport"}));
+    assert_eq!(unknown.len(), 1);
+    assert!(unknown[0].starts_with("/kind: expected one of \"hello\""));
+    assert!(unknown[0].ends_with("got \"teleport\""));
+}
+
+#[test]
+fn tells_peers_why_their_message_was_refused() {
+    let mut hub = hub("schema-hub");
+    let (mut stream, rx) = connect_raw("schema-hub", &["push", "error"]);
+    send_raw(&mut stream, &bad_push());
+
+    let expected = "invalid message: /objects/type:1/size: expected integer, got string";
+
+    assert!(settle(&mut hub, |_| {
+        rx.try_iter().any(|message| {
+            matches!(message, Message::Error { id: None, message } if message == expected)
+        })
+    }));
+    assert!(hub.database().get_net().is_empty());
+}
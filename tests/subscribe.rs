@@ -0,0 +1,140 @@
+// Peers that subscribe are only pushed the objects their filters let through
+mod common;
+
+use binal::{
+    mock::Program,
+    net::{function_id, Filter, Message, Object},
+};
+
+use common::{hub, join, settle, structure};
+
+// A function taking a pointer to S
+fn function(name: &str, location: usize) -> Object {
+    common::function_taking(name, location, "S")
+}
+
+fn names(pattern: &str) -> Filter {
+    Filter {
+        name: Some(String::from(pattern)),
+        ..Filter::default()
+    }
+}
+
+#[test]
+fn names_match_as_globs() {
+    let matches = |pattern: &str, name: &str| names(pattern).matches(&function(name, 0));
+
+    assert!(matches("main", "main"));
+    assert!(!matches("main", "main2"));
+    assert!(!matches("main", "mai"));
+    assert!(matches("sub_*", "sub_"));
+    assert!(matches("sub_*", "sub_1000"));
+    assert!(!matches("sub_*", "main"));
+    assert!(matches("*_init", "foo_bar_init"));
+    assert!(!matches("*_init", "foo_init_x"));
+    assert!(matches("a*b*c", "aXbYbZc"));
+    assert!(!matches("a*b*c", "aXbYbZ"));
+    assert!(matches("f??", "foo"));
+    assert!(!matches("f??", "fo"));
+    assert!(matches("*", ""));
+    assert!(!matches("?", ""));
+}
+
+#[test]
+fn filters_combine_kind_name_and_address() {
+    let filter = Filter {
+        objects: vec![String::from("function")],
+        name: Some(String::from("sub_*")),
+        start: Some(0x1000),
+        end: Some(0x2000),
+    };
+
+    assert!(filter.matches(&function("sub_1000", 0x1000)));
+    assert!(!filter.matches(&function("sub_2000", 0x2000)));
+    assert!(!filter.matches(&function("main", 0x1800)));
+    assert!(!filter.matches(&structure("sub_t")));
+
+    // types have no address, so they never fall into a range
+    let range = Filter {
+        start: Some(0),
+        ..Filter::default()
+    };
+
+    assert!(!range.matches(&structure("S")));
+    assert!(Filter::default().matches(&structure("S")));
+}
+
+fn pushed(messages: &[Message], object_id: &str) -> bool {
+    messages.iter().any(|message| match message {
+        Message::Push { objects, .. } => objects.contains_key(object_id),
+        _ => false,
+    })
+}
+
+// The locks, renames and deletes among the messages, by the object they are about
+fn changes(messages: &[Message]) -> Vec<(&'static str, String)> {
+    messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::Lock { object, .. } => Some(("lock", object.clone())),
+            Message::Rename { old, .. } => Some(("rename", old.clone())),
+            Message::Delete { object, .. } => Some(("delete", object.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn peers_only_get_what_they_subscribed_to() {
+    let mut hub = hub("subscribe-hub");
+
+    let objects = [
+        (String::from("type:s"), structure("S")),
+        (function_id(0x1000), function("sub_1000", 0x1000)),
+    ]
+    .into();
+    let first = join("subscribe-hub", Program { objects }, None);
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 2));
+
+    let second = join("subscribe-hub", Program::default(), None);
+    assert!(settle(&mut hub, |_| second.program().objects.len() == 2));
+    let before = second.received().len();
+
+    // the hub sends over what the filters let through again, along with the types it refers
+    // to so that it can be resolved
+    second.subscribe(vec![names("sub_*")]);
+    assert!(settle(&mut hub, |_| second.received().len() > before));
+
+    match &second.received()[before] {
+        Message::Push { objects, .. } => {
+            assert!(objects.contains_key(&function_id(0x1000)));
+            assert!(objects.contains_key("type:s"));
+        }
+        message => panic!("expected a push, got {:?}", message),
+    }
+
+    // pushed in order, so the one left out would have arrived before the one let through
+    first.define(function_id(0x3000), function("main", 0x3000));
+    first.define(function_id(0x2000), function("sub_2000", 0x2000));
+    assert!(settle(&mut hub, |_| {
+        pushed(&second.received(), &function_id(0x2000))
+    }));
+    assert!(!pushed(&second.received(), &function_id(0x3000)));
+    // the same goes for the changes that only name the object
+    for object_id in [function_id(0x3000), function_id(0x2000)] {
+        first.lock(&object_id);
+    }
+    first.define(function_id(0x3000), function("start", 0x3000));
+    first.define(function_id(0x2000), function("sub_two", 0x2000));
+    first.remove(&function_id(0x3000));
+    first.remove(&function_id(0x2000));
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 2));
+    assert!(settle(&mut hub, |_| changes(&second.received()).len() == 3));
+
+    let expected = [
+        ("lock", function_id(0x2000)),
+        ("rename", String::from("sub_2000")),
+        ("delete", function_id(0x2000)),
+    ];
+    assert_eq!(changes(&second.received()), expected);
+}
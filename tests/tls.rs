@@ -0,0 +1,176 @@
+// Connections over TLS only trust the pinned certificate, and peers only get past the handshake
+// by proving they know the token for the session at hand
+mod common;
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    net::TcpListener,
+    path::PathBuf,
+    time::Duration,
+};
+
+use binal::{
+    net::{function_id, Client, Hello, Message, Role, Server},
+    project::{Project, ProjectKind},
+    tls::{Identity, Tls},
+    transport::{Stream, Tcp, Transport},
+};
+use ring::hmac;
+
+use common::{function, linger, send_raw, settle};
+
+const TOKEN: &str = "secret";
+
+// A port nothing listens on right now
+fn free_address() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+fn identity(name: &str) -> Identity {
+    let path = certificate(name);
+    let _ = std::fs::remove_file(&path);
+
+    let identity = Identity::load_or_generate(&path).unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    identity
+}
+
+fn certificate(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("binal-{}-{}.pem", name, std::process::id()))
+}
+
+// A hub over TLS on loopback that asks for `TOKEN`, along with the address it listens on
+fn hub(identity: &Identity) -> (Project, String) {
+    let address = free_address();
+    let transport = Tls::listening(Box::new(Tcp(address.clone())), identity).unwrap();
+    let tokens = HashMap::from([(String::from(TOKEN), Role::Contributor)]);
+
+    let server = Server::listen(&transport, tokens, None).unwrap();
+    let hub = Project::create(ProjectKind::Hub(server), String::from("hub")).unwrap();
+
+    (hub, address)
+}
+
+fn connect(address: &str, fingerprint: &str, token: &str) -> std::io::Result<Client> {
+    let transport = Tls::connecting(Box::new(Tcp(String::from(address))), fingerprint).unwrap();
+    Client::connect(Box::new(transport), Some(String::from(token)), None)
+}
+
+// Speaks the handshake by hand over TLS. Returns the stream, what reads from it, and the
+// challenge in the hub's hello
+fn connect_raw(address: &str, fingerprint: &str) -> (Box<dyn Stream>, impl BufRead, String) {
+    let transport = Tls::connecting(Box::new(Tcp(String::from(address))), fingerprint).unwrap();
+    let stream = transport.connect().unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    let Some(Message::Hello(hello)) = read(&mut reader) else {
+        panic!("the hub did not say hello");
+    };
+
+    (stream, reader, hello.challenge.unwrap())
+}
+
+fn read(reader: &mut impl BufRead) -> Option<Message> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+
+    serde_json::from_str(&line).ok()
+}
+
+fn hello(challenge: &str) -> Message {
+    Message::Hello(Box::new(Hello {
+        tool: String::from("raw"),
+        challenge: Some(String::from(challenge)),
+        messages: vec![String::from("push")],
+        ..common::hello(&["json"], &[])
+    }))
+}
+
+// What a peer that knows `token` answers, covering the hub's challenge and then its own
+fn proof(token: &str, hub: &str, ours: &str) -> Message {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, token.as_bytes());
+    let proof = hmac::sign(&key, format!("{}{}", hub, ours).as_bytes());
+
+    Message::Auth {
+        proof: proof
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    }
+}
+
+fn push() -> Message {
+    Message::Push {
+        id: None,
+        objects: HashMap::from([(function_id(0x1000), function("main", 0x1000))]),
+        author: None,
+    }
+}
+
+#[test]
+fn only_trusts_the_pinned_certificate() {
+    let identity = identity("tls-pinned");
+    let (mut hub, address) = hub(&identity);
+
+    let other = self::identity("tls-other");
+    assert!(connect(&address, &other.fingerprint(), TOKEN).is_err());
+
+    let mut client = connect(&address, &identity.fingerprint(), TOKEN).unwrap();
+    let objects = HashMap::from([(function_id(0x1000), function("main", 0x1000))]);
+    client.push(objects).unwrap();
+
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 1));
+}
+
+#[test]
+fn refuses_the_wrong_token_before_anything_else() {
+    let identity = identity("tls-token");
+    let (mut hub, address) = hub(&identity);
+
+    assert!(connect(&address, &identity.fingerprint(), "wrong").is_err());
+
+    // what comes after a wrong proof is never looked at
+    let (mut stream, mut reader, challenge) = connect_raw(&address, &identity.fingerprint());
+    send_raw(&mut stream, &hello("0123"));
+    send_raw(&mut stream, &proof("wrong", &challenge, "0123"));
+    send_raw(&mut stream, &push());
+
+    assert!(read(&mut reader).is_none());
+
+    linger(&mut hub, Duration::from_millis(200));
+    assert!(hub.database().get_net().is_empty());
+}
+
+#[test]
+fn answers_only_count_for_their_own_session() {
+    let identity = identity("tls-replay");
+    let (mut hub, address) = hub(&identity);
+    let fingerprint = identity.fingerprint();
+
+    // a peer that knows the token gets in
+    let (mut stream, mut reader, challenge) = connect_raw(&address, &fingerprint);
+    let answer = proof(TOKEN, &challenge, "0123");
+
+    send_raw(&mut stream, &hello("0123"));
+    send_raw(&mut stream, &answer);
+    assert!(matches!(read(&mut reader), Some(Message::Auth { .. })));
+
+    // while its answer is worth nothing to someone listening in, as the hub's challenge is new
+    let (mut stream, mut reader, _) = connect_raw(&address, &fingerprint);
+    send_raw(&mut stream, &hello("0123"));
+    send_raw(&mut stream, &answer);
+    send_raw(&mut stream, &push());
+    assert!(read(&mut reader).is_none());
+
+    // and neither is sending the hub its own challenge, to have it answer for us
+    let (mut stream, mut reader, challenge) = connect_raw(&address, &fingerprint);
+    send_raw(&mut stream, &hello(&challenge));
+    assert!(read(&mut reader).is_none());
+
+    linger(&mut hub, Duration::from_millis(200));
+    assert!(hub.database().get_net().is_empty());
+}
@@ -0,0 +1,171 @@
+// Pushes between a `Begin` and its `Commit` are applied all at once, and not at all if the peer
+// aborts or goes away first
+mod common;
+
+use std::{collections::HashMap, io::BufReader, sync::mpsc, time::Duration};
+
+use binal::{
+    net::{function_id, Client, Hello, Message, Object, ObjectId},
+    project::{Project, ProjectKind},
+    transport::{Memory, Stream},
+};
+
+use common::{
+    accept_one, connect_raw, function, function_taking, hub, linger, read_raw, send_raw, settle,
+    structure, TIMEOUT,
+};
+
+fn push(id: Option<u64>, objects: impl IntoIterator<Item = (ObjectId, Object)>) -> Message {
+    Message::Push {
+        id,
+        objects: objects.into_iter().collect(),
+        author: None,
+    }
+}
+
+// A tool that sends `messages` on connecting and answers pulls with nothing. Hands over its end of
+// the connection
+fn tool(
+    address: &str,
+    messages: impl IntoIterator<Item = Message> + Send + 'static,
+) -> mpsc::Receiver<Box<dyn Stream>> {
+    let (tx, rx) = mpsc::channel();
+
+    accept_one(address, move |mut stream| {
+        tx.send(stream.try_clone().unwrap()).unwrap();
+
+        let hello = Hello {
+            tool: String::from("raw"),
+            messages: ["push", "pull", "response", "begin", "commit"]
+                .map(String::from)
+                .to_vec(),
+            ..common::hello(&["json"], &[])
+        };
+        send_raw(&mut stream, &Message::Hello(Box::new(hello)));
+
+        for message in messages {
+            send_raw(&mut stream, &message);
+        }
+
+        for message in read_raw(BufReader::new(stream.try_clone().unwrap())) {
+            if let Message::Pull { id, .. } = message {
+                let response = Message::Response {
+                    id,
+                    revision: 0,
+                    objects: Default::default(),
+                };
+                send_raw(&mut stream, &response);
+            }
+        }
+    });
+
+    rx
+}
+
+fn name(project: &Project) -> Option<String> {
+    project.database().name(&function_id(0x1000)).cloned()
+}
+
+#[test]
+fn applies_nothing_before_the_commit() {
+    let mut hub = hub("transaction-commit");
+    let (mut stream, _messages) = connect_raw("transaction-commit", &["push", "begin", "commit"]);
+
+    // `main` refers to S, which arrives in a push of its own
+    send_raw(&mut stream, &Message::Begin { id: 1 });
+    send_raw(
+        &mut stream,
+        &push(None, [(String::from("type:s"), structure("S"))]),
+    );
+    send_raw(
+        &mut stream,
+        &push(
+            None,
+            [(function_id(0x1000), function_taking("main", 0x1000, "S"))],
+        ),
+    );
+
+    linger(&mut hub, Duration::from_millis(200));
+    assert!(hub.database().get_net().is_empty());
+
+    send_raw(&mut stream, &Message::Commit { id: 1 });
+    assert!(settle(&mut hub, |p| p.database().get_net().len() == 2));
+}
+
+#[test]
+fn aborted_transactions_leave_the_database_unchanged() {
+    let mut hub = hub("transaction-abort");
+    let (mut stream, messages) = connect_raw(
+        "transaction-abort",
+        &["push", "begin", "commit", "abort", "ack", "reject"],
+    );
+
+    send_raw(
+        &mut stream,
+        &push(None, [(function_id(0x1000), function("main", 0x1000))]),
+    );
+    assert!(settle(&mut hub, |p| name(p).as_deref() == Some("main")));
+
+    send_raw(&mut stream, &Message::Begin { id: 2 });
+    send_raw(
+        &mut stream,
+        &push(
+            Some(5),
+            [(function_id(0x1000), function("renamed", 0x1000))],
+        ),
+    );
+    send_raw(
+        &mut stream,
+        &push(None, [(function_id(0x2000), function("other", 0x2000))]),
+    );
+    send_raw(&mut stream, &Message::Abort { id: 2 });
+
+    let mut replies = Vec::new();
+    assert!(settle(&mut hub, |_| {
+        replies.extend(
+            messages
+                .try_iter()
+                .filter(|m| matches!(m, Message::Reject { .. } | Message::Ack { .. })),
+        );
+        replies.len() == 2
+    }));
+
+    let rejected = HashMap::from([(function_id(0x1000), String::from("transaction aborted"))]);
+    assert!(matches!(&replies[0], Message::Reject { id: 5, rejected: r } if r == &rejected));
+    assert!(matches!(&replies[1], Message::Ack { id: 5, applied } if applied.is_empty()));
+
+    assert_eq!(hub.database().get_net().len(), 1);
+    assert_eq!(name(&hub).as_deref(), Some("main"));
+}
+
+#[test]
+fn drops_transactions_cut_short() {
+    let address = "transaction-dropped";
+
+    let begun = [
+        Message::Begin { id: 3 },
+        push(None, [(String::from("type:s"), structure("S"))]),
+    ];
+    let connection = tool(address, begun);
+
+    let client = Client::connect(Box::new(Memory(String::from(address))), None, None).unwrap();
+    let mut project = Project::create(ProjectKind::Remote(client), String::from("raw")).unwrap();
+
+    // the connection goes away before the rest of the transaction and its commit, and what the
+    // tool pushes once it is back is not taken for part of it
+    connection
+        .recv_timeout(TIMEOUT)
+        .unwrap()
+        .shutdown()
+        .unwrap();
+    let _connection = tool(
+        address,
+        [push(
+            None,
+            [(function_id(0x1000), function("main", 0x1000))],
+        )],
+    );
+
+    assert!(settle(&mut project, |p| name(p).is_some()));
+    assert_eq!(project.database().get_net().len(), 1);
+}
@@ -0,0 +1,56 @@
+// Addresses typed by users turn into the transport they name
+use binal::transport::{parse, DEFAULT_PORT};
+
+fn parsed(text: &str) -> String {
+    parse(text).unwrap().to_string()
+}
+
+#[test]
+fn parses_tcp_addresses() {
+    let localhost = format!("[::1]:{}", DEFAULT_PORT);
+
+    assert_eq!(parsed("[::1]"), localhost);
+    assert_eq!(parsed("[::1]:12007"), localhost);
+    assert_eq!(parsed("::1"), localhost);
+    assert_eq!(parsed("[::1]:80"), "[::1]:80");
+    assert_eq!(parsed("127.0.0.1"), format!("127.0.0.1:{}", DEFAULT_PORT));
+    assert_eq!(parsed("host"), format!("host:{}", DEFAULT_PORT));
+    assert_eq!(parsed("host.example:80"), "host.example:80");
+
+    assert!(parse("").is_err());
+    assert!(parse("host:port").is_err());
+}
+
+#[test]
+fn parses_other_transports() {
+    assert_eq!(parsed("memory:hub"), "memory:hub");
+
+    #[cfg(unix)]
+    assert_eq!(parsed("unix:/tmp/binal.sock"), "unix:/tmp/binal.sock");
+}
+
+#[cfg(unix)]
+#[test]
+fn only_replaces_sockets() {
+    let directory = std::env::temp_dir().join(format!("binal-unix-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+
+    // a mistyped path to something else is left alone
+    let notes = directory.join("notes.txt");
+    std::fs::write(&notes, "keep me").unwrap();
+
+    let transport = parse(&format!("unix:{}", notes.display())).unwrap();
+    assert!(transport.listen().is_err());
+    assert_eq!(std::fs::read_to_string(&notes).unwrap(), "keep me");
+
+    // while a socket nobody listens on anymore is taken over
+    let socket = directory.join("binal.sock");
+    drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+
+    let transport = parse(&format!("unix:{}", socket.display())).unwrap();
+    let listener = transport.listen().unwrap();
+    assert!(transport.connect().is_ok());
+
+    drop(listener);
+    std::fs::remove_dir_all(directory).unwrap();
+}